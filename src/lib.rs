@@ -0,0 +1,3 @@
+mod rszzy;
+
+pub use crate::rszzy::*;
@@ -1,22 +1,115 @@
-mod rszzy;
-
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use fehler::throws;
-use rszzy::ZMachine;
+use rszzy::{
+    ensure, parse_interpreter_number, parse_script, InterpreterConfig, StoryReport, Strictness,
+    ZMachine, ZTerminal,
+};
 use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "rszzy")]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    /// The story to play.
     #[structopt(parse(from_os_str))]
-    story_file: std::path::PathBuf,
+    story_file: Option<PathBuf>,
+
+    /// Maximum depth of nested routine calls before the game is stopped with an error.
+    #[structopt(long, default_value = "1024")]
+    stack_limit: usize,
+
+    /// Stop with an error when the game breaks a rule that is normally only warned about,
+    /// such as writing to read-only parts of the header.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Interpreter number to report to the game (ZSpec 11.1.3.1), as a number or a
+    /// machine: decsystem-20, apple-iie, macintosh, amiga, atari-st, ibm-pc,
+    /// commodore-128, commodore-64, apple-iic, apple-iigs or tandy-color.
+    #[structopt(long, default_value = "ibm-pc", parse(try_from_str = parse_interpreter_number))]
+    interpreter_number: u8,
+
+    /// Interpreter version to report to the game, usually a capital letter.
+    #[structopt(long, default_value = "A")]
+    interpreter_version: char,
+
+    /// Screen height in lines. 255 means the game should never pause.
+    #[structopt(long, default_value = "25")]
+    screen_height: u8,
+
+    /// Screen width in characters.
+    #[structopt(long, default_value = "80")]
+    screen_width: u8,
+
+    /// Play headless, typing the keys in this file rather than reading the keyboard.
+    /// Each line is typed and ends with enter. Other keys go in braces: {up}, {down},
+    /// {left}, {right}, {f1}-{f12}, {kp0}-{kp9}, {esc}, {del} and {enter}. {wait} lets
+    /// a timer run out, and {{ types a brace.
+    #[structopt(long, parse(from_os_str))]
+    script: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Check a story file's length and checksum against its header, without playing it.
+    Verify {
+        #[structopt(parse(from_os_str))]
+        story_file: PathBuf,
+    },
+}
+
+#[throws]
+fn verify(story_file: &Path) {
+    let mut bytes = Vec::new();
+    File::open(story_file)?.read_to_end(&mut bytes)?;
+    let report = StoryReport::from_bytes(&bytes)?;
+    println!("{}", report);
+    ensure!(
+        report.problems().is_empty(),
+        anyhow!("{} failed verification", story_file.display())
+    );
 }
 
 #[throws]
 fn main() {
     let opt = Opt::from_args();
-    let file = File::open(&opt.story_file)?;
-    let zmachine = ZMachine::from_reader(file)?;
+    if let Some(Command::Verify { story_file }) = &opt.command {
+        return verify(story_file)?;
+    }
+    let story_file = opt
+        .story_file
+        .ok_or_else(|| anyhow!("No story file given"))?;
+
+    ensure!(
+        opt.interpreter_version.is_ascii(),
+        anyhow!(
+            "Interpreter version must be ASCII: {}",
+            opt.interpreter_version
+        )
+    );
+    let config = InterpreterConfig {
+        interpreter_number: opt.interpreter_number,
+        interpreter_version: opt.interpreter_version as u8,
+        screen_height_lines: opt.screen_height,
+        screen_width_chars: opt.screen_width,
+        ..InterpreterConfig::default()
+    };
+
+    let file = File::open(&story_file)?;
+    let strictness = if opt.strict {
+        Strictness::Strict
+    } else {
+        Strictness::Lenient
+    };
+    let terminal = match &opt.script {
+        Some(script) => ZTerminal::scripted(parse_script(&std::fs::read_to_string(script)?)?),
+        None => ZTerminal::default(),
+    };
+    let zmachine = ZMachine::from_reader(file, opt.stack_limit, strictness, config, terminal)?;
     zmachine.run()?
 }
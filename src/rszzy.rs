@@ -1,22 +1,43 @@
 mod abbrevs;
 mod addressing;
 mod constants;
+mod dictionary;
+#[doc(hidden)]
+pub mod fuzzing;
+mod globals;
 mod header;
+mod input;
+mod instruction;
+mod interpreter;
+mod keys;
+mod lexer;
 mod memory;
+mod objects;
+mod opcodes;
 mod pc;
 mod processor;
+mod stack;
+mod terminal;
 mod text;
 mod traits;
+mod unicode;
+mod verify;
 mod versions;
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use fehler::throws;
 use header::Header;
+pub use interpreter::{parse_interpreter_number, InterpreterConfig};
+pub use keys::parse_script;
+pub use memory::Strictness;
 use memory::ZMemory;
 use pc::PC;
 use processor::ZProcessor;
+use stack::ZStack;
 use std::io::Read;
-use traits::Memory;
+pub use terminal::ZTerminal;
+use traits::{Memory, Terminal};
+pub use verify::StoryReport;
 
 #[macro_export]
 macro_rules! ensure {
@@ -29,51 +50,69 @@ macro_rules! ensure {
 
 /// The public API for the ZMachine.
 /// All component types are defined.
-pub type ZMachine = Machine<ZMemory>;
+pub type ZMachine = Machine<ZMemory, ZTerminal>;
 
 impl ZMachine {
     #[throws]
-    pub fn from_reader<R>(rdr: R) -> ZMachine
+    pub fn from_reader<R>(
+        rdr: R,
+        stack_limit: usize,
+        strictness: Strictness,
+        config: InterpreterConfig,
+        terminal: ZTerminal,
+    ) -> ZMachine
     where
         R: Read,
     {
-        let memory = ZMemory::from_reader(rdr)?;
-        MachineBuilder::new().memory(memory).build()
+        let mut memory = ZMemory::from_reader(rdr)?;
+        memory.set_strictness(strictness);
+        MachineBuilder::new()
+            .memory(memory)
+            .terminal(terminal)
+            .stack_limit(stack_limit)
+            .config(config)
+            .build()?
     }
 }
 
 /// Abstract representation of the ZMachine as outlined in the Overview of ZSpec 1.1.
 /// All of the component types are represented as traits to facilitate testing.
-pub struct Machine<M> {
+pub struct Machine<M, T> {
     // The "CPU"
-    processor: ZProcessor<M>,
+    processor: ZProcessor<M, T>,
 }
 
-impl<M> Machine<M>
+impl<M, T> Machine<M, T>
 where
     M: Memory,
+    T: Terminal,
 {
     #[throws]
-    pub fn run(self) {
+    pub fn run(mut self) {
         self.processor.process()?;
     }
 }
 
-pub struct MachineBuilder<M> {
+pub struct MachineBuilder<M, T> {
     memory: Option<M>,
+    terminal: Option<T>,
     pc: PC,
-    stack: Option<()>,
+    stack: Option<ZStack>,
+    config: InterpreterConfig,
 }
 
-impl<M> MachineBuilder<M>
+impl<M, T> MachineBuilder<M, T>
 where
     M: Memory,
+    T: Terminal,
 {
-    fn new() -> MachineBuilder<M> {
+    fn new() -> MachineBuilder<M, T> {
         MachineBuilder {
             memory: None,
+            terminal: None,
             pc: PC::default(),
             stack: None,
+            config: InterpreterConfig::default(),
         }
     }
 
@@ -82,20 +121,52 @@ where
         self
     }
 
+    fn terminal(mut self, terminal: T) -> Self {
+        self.terminal = Some(terminal);
+        self
+    }
+
+    fn stack_limit(mut self, limit: usize) -> Self {
+        self.stack = Some(ZStack::with_limit(limit));
+        self
+    }
+
+    /// What the interpreter claims about itself in the header.
+    fn config(mut self, config: InterpreterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
     #[cfg(test)]
     fn pc(mut self, pc: PC) -> Self {
         self.pc = pc;
         self
     }
 
-    fn build(mut self) -> Machine<M> {
+    #[throws]
+    fn build(self) -> Machine<M, T> {
+        let memory = self
+            .memory
+            .ok_or_else(|| anyhow!("MachineBuilder requires a memory"))?;
+        let terminal = self
+            .terminal
+            .ok_or_else(|| anyhow!("MachineBuilder requires a terminal"))?;
+        let version = Header::new(&memory).version()?;
+
+        // The processor writes the config into the header.
+        let mut processor = ZProcessor::new(
+            memory,
+            version,
+            self.config,
+            self.pc,
+            self.stack.unwrap_or_default(),
+            terminal,
+        )?;
         if self.pc.is_zero() {
             // If the PC has been set explicitly, leave it alone.
-            // Otherwise, set it from the Header.
-            self.pc = PC::at(Header::start_pc(self.memory.as_ref().unwrap()));
+            // Otherwise, start where the header says.
+            processor.start()?;
         }
-
-        let processor = ZProcessor::new(self.memory.unwrap(), self.pc, ());
         Machine { processor }
     }
 }
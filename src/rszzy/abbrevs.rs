@@ -1,6 +1,6 @@
 use crate::ensure;
 use crate::rszzy::addressing::{WordAddress, ZOffset};
-use crate::rszzy::constants::header_offset::ABBREV_TABLE_START;
+use crate::rszzy::header::Header;
 use crate::rszzy::traits::{AbbrevTable, Memory};
 use anyhow::{anyhow, Error};
 use fehler::throws;
@@ -11,9 +11,7 @@ pub struct ZAbbrevTable(ZOffset);
 impl ZAbbrevTable {
     #[throws]
     pub fn new(memory: &impl Memory) -> ZAbbrevTable {
-        ZAbbrevTable(ZOffset::from(
-            memory.read_word(ZOffset::from(ABBREV_TABLE_START))?,
-        ))
+        ZAbbrevTable(ZOffset::from(Header::new(memory).abbreviations()?))
     }
 }
 
@@ -1,4 +1,8 @@
+use crate::ensure;
+use crate::rszzy::traits::Memory;
 use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
 use std::fmt::Display;
 
 /// Abstract representation for an offset into the "memory" of the ZMachine.
@@ -10,7 +14,7 @@ use std::fmt::Display;
 /// * the PC (this is not called out explicitly in the ZSpec, but it acts as a ZOffset.
 ///
 /// See each address type for details.
-#[derive(Default, Debug, Clone, Copy)]
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct ZOffset(usize);
 
 impl From<ZOffset> for usize {
@@ -31,21 +35,11 @@ impl From<u16> for ZOffset {
     }
 }
 
-impl From<i32> for ZOffset {
-    fn from(sz: i32) -> ZOffset {
-        // Range check or
-        ZOffset(sz as usize)
-    }
-}
-
-impl<T> std::ops::Add<T> for ZOffset
-where
-    T: Into<ZOffset>,
-{
+impl std::ops::Add<usize> for ZOffset {
     type Output = ZOffset;
 
-    fn add(self, rhs: T) -> Self::Output {
-        ZOffset(self.0 + rhs.into().0)
+    fn add(self, rhs: usize) -> Self::Output {
+        ZOffset(self.0 + rhs)
     }
 }
 
@@ -86,7 +80,7 @@ impl From<u16> for WordAddress {
 
 impl From<WordAddress> for ZOffset {
     fn from(wa: WordAddress) -> ZOffset {
-        ZOffset(usize::from(wa.0 * 2))
+        ZOffset(usize::from(wa.0) * 2)
     }
 }
 
@@ -96,25 +90,73 @@ impl From<WordAddress> for ZOffset {
 #[derive(Debug, Clone, Copy)]
 pub struct PackedAddress(u16);
 
-impl PackedAddress {
-    pub fn routine_offset(self, version: &Version) -> ZOffset {
-        ZOffset::from(usize::from(self.0 * version.packed_multiplier as u16))
+impl From<u16> for PackedAddress {
+    fn from(val: u16) -> PackedAddress {
+        PackedAddress(val)
     }
+}
 
-    pub fn string_offset(self, version: &Version) -> ZOffset {
-        ZOffset::from(usize::from(self.0 * version.packed_multiplier as u16))
+impl PackedAddress {
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    /// ZSpec 1.2.3 - the offset of the routine at this packed address.
+    #[throws]
+    pub fn routine_offset(self, memory: &impl Memory, version: &Version) -> ZOffset {
+        self.unpack(
+            memory,
+            version,
+            version.packed_offsets.map(|(routines, _)| routines),
+        )?
+    }
+
+    /// ZSpec 1.2.3 - the offset of the string at this packed address.
+    #[throws]
+    pub fn string_offset(self, memory: &impl Memory, version: &Version) -> ZOffset {
+        self.unpack(
+            memory,
+            version,
+            version.packed_offsets.map(|(_, strings)| strings),
+        )?
+    }
+
+    /// Scales the address by the version's multiplier and, in V6-7, adds 8 times the
+    /// header word at `offset_location`. The result must lie within the story.
+    #[throws]
+    fn unpack(
+        self,
+        memory: &impl Memory,
+        version: &Version,
+        offset_location: Option<usize>,
+    ) -> ZOffset {
+        let mut offset = usize::from(self.0) * usize::from(version.packed_multiplier);
+        if let Some(location) = offset_location {
+            offset += 8 * usize::from(memory.read_word(location)?);
+        }
+        ensure!(
+            offset < memory.memory_size(),
+            anyhow!(
+                "Packed address 0x{:x} unpacks to {}, past the end of memory",
+                self.0,
+                ZOffset(offset)
+            )
+        );
+        ZOffset(offset)
     }
 }
 
 #[cfg(test)]
 mod test {
+    use super::super::constants::header_offset::{ROUTINES_OFFSET, STRINGS_OFFSET};
+    use super::super::memory::test::{fake_memory, fake_memory_with};
     use super::super::versions::number_to_version;
     use super::*;
 
     #[test]
     fn test_zoffset() {
-        assert_eq!(32, usize::from(ZOffset::from(32)));
-        assert_eq!(55, usize::from(ZOffset::from(50) + 5));
+        assert_eq!(32, usize::from(ZOffset::from(32usize)));
+        assert_eq!(55, usize::from(ZOffset::from(50usize) + 5));
         assert_eq!("ZO:0x88", format!("{}", ZOffset(0x88)));
     }
 
@@ -128,14 +170,55 @@ mod test {
         assert_eq!(44, usize::from(ZOffset::from(WordAddress(22))));
     }
 
+    #[test]
+    fn test_word_address_overflow() {
+        assert_eq!(0x1fffe, usize::from(ZOffset::from(WordAddress(0xffff))));
+    }
+
     #[test]
     fn test_packed_address() {
+        let memory = fake_memory(0x1000);
         let v3 = number_to_version(3).unwrap();
-        assert_eq!(44, usize::from(PackedAddress(22).routine_offset(&v3)));
-        assert_eq!(44, usize::from(PackedAddress(22).string_offset(&v3)));
+        let routine = PackedAddress(22).routine_offset(&memory, v3).unwrap();
+        assert_eq!(44, usize::from(routine));
+        let string = PackedAddress(22).string_offset(&memory, v3).unwrap();
+        assert_eq!(44, usize::from(string));
 
         let v5 = number_to_version(5).unwrap();
-        assert_eq!(88, usize::from(PackedAddress(22).routine_offset(&v5)));
-        assert_eq!(88, usize::from(PackedAddress(22).string_offset(&v5)));
+        let routine = PackedAddress(22).routine_offset(&memory, v5).unwrap();
+        assert_eq!(88, usize::from(routine));
+        let string = PackedAddress(22).string_offset(&memory, v5).unwrap();
+        assert_eq!(88, usize::from(string));
+    }
+
+    #[test]
+    fn test_packed_address_large() {
+        // Addresses above 0x7fff (V5) and 0x3fff (V8) overflowed when unpacked as u16.
+        let v5 = fake_memory_with(5, 0x30000, 0x50, |_| {});
+        let version = number_to_version(5).unwrap();
+        let routine = PackedAddress(0x8000).routine_offset(&v5, version).unwrap();
+        assert_eq!(0x20000, usize::from(routine));
+        assert!(PackedAddress(0xc000).routine_offset(&v5, version).is_err());
+
+        let v8 = fake_memory_with(8, 0x30000, 0x50, |_| {});
+        let version = number_to_version(8).unwrap();
+        let string = PackedAddress(0x4000).string_offset(&v8, version).unwrap();
+        assert_eq!(0x20000, usize::from(string));
+        assert!(PackedAddress(0x6000).string_offset(&v8, version).is_err());
+    }
+
+    #[test]
+    fn test_packed_address_offsets() {
+        // ZSpec 1.2.3 - V6-7 add 8 times the routine or string offset from the header.
+        let memory = fake_memory_with(7, 0x1000, 0x50, |bytes| {
+            bytes[ROUTINES_OFFSET + 1] = 0x10;
+            bytes[STRINGS_OFFSET + 1] = 0x20;
+        });
+        let v7 = number_to_version(7).unwrap();
+        let routine = PackedAddress(0x10).routine_offset(&memory, v7).unwrap();
+        assert_eq!(0x40 + 0x80, usize::from(routine));
+        let string = PackedAddress(0x10).string_offset(&memory, v7).unwrap();
+        assert_eq!(0x40 + 0x100, usize::from(string));
+        assert!(PackedAddress(0x400).routine_offset(&memory, v7).is_err());
     }
 }
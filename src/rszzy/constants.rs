@@ -1,9 +1,82 @@
 /// Offsets into Header memory (the first 64bytes).
 /// See ZSpec 11 for details.
 pub mod header_offset {
+    pub const HEADER_SIZE: usize = 0x40;
+
     pub const VERSION_NUMBER: usize = 0x00;
+    pub const FLAGS1: usize = 0x01;
+    pub const RELEASE_NUMBER: usize = 0x02;
     pub const HIGH_MEMORY_MARK: usize = 0x04;
     pub const START_PC: usize = 0x06;
+    pub const DICTIONARY_START: usize = 0x08;
+    pub const OBJECT_TABLE_START: usize = 0x0a;
+    pub const GLOBAL_TABLE_START: usize = 0x0c;
     pub const STATIC_MEMORY_START: usize = 0x0e;
+    pub const FLAGS2: usize = 0x10;
+    pub const SERIAL_NUMBER: usize = 0x12;
     pub const ABBREV_TABLE_START: usize = 0x18;
+    pub const FILE_LENGTH: usize = 0x1a;
+    pub const CHECKSUM: usize = 0x1c;
+    pub const INTERPRETER_NUMBER: usize = 0x1e;
+    pub const INTERPRETER_VERSION: usize = 0x1f;
+    pub const SCREEN_HEIGHT_LINES: usize = 0x20;
+    pub const SCREEN_WIDTH_CHARS: usize = 0x21;
+    pub const SCREEN_WIDTH_UNITS: usize = 0x22;
+    pub const SCREEN_HEIGHT_UNITS: usize = 0x24;
+    // In V6, the height comes first.
+    pub const FONT_WIDTH_UNITS: usize = 0x26;
+    pub const FONT_HEIGHT_UNITS: usize = 0x27;
+    pub const ROUTINES_OFFSET: usize = 0x28;
+    pub const STRINGS_OFFSET: usize = 0x2a;
+    pub const DEFAULT_BACKGROUND: usize = 0x2c;
+    pub const DEFAULT_FOREGROUND: usize = 0x2d;
+    pub const TERMINATING_CHARS_TABLE: usize = 0x2e;
+    pub const STANDARD_REVISION: usize = 0x32;
+    pub const ALPHABET_TABLE_START: usize = 0x34;
+    pub const HEADER_EXTENSION_START: usize = 0x36;
+}
+
+/// Bits in Flags 1. The meanings differ before and after V4.
+/// See ZSpec 11.1.2 for details.
+pub mod flags1 {
+    // V1-3
+    // Set by the game. Only the tests read it until the interpreter draws the
+    // status line.
+    #[allow(dead_code)]
+    pub const STATUS_LINE_TIME: u8 = 0x02;
+    pub const STATUS_LINE_UNAVAILABLE: u8 = 0x10;
+    pub const SCREEN_SPLITTING: u8 = 0x20;
+    pub const VARIABLE_PITCH_DEFAULT: u8 = 0x40;
+
+    // V4+
+    pub const COLOURS: u8 = 0x01;
+    pub const PICTURES: u8 = 0x02;
+    pub const BOLDFACE: u8 = 0x04;
+    pub const ITALIC: u8 = 0x08;
+    pub const FIXED_SPACE: u8 = 0x10;
+    pub const SOUND_EFFECTS: u8 = 0x20;
+    pub const TIMED_INPUT: u8 = 0x80;
+}
+
+/// Bits in Flags 2.
+/// See ZSpec 11.1.3 for details.
+pub mod flags2 {
+    pub const TRANSCRIPTING: u16 = 0x0001;
+    pub const FORCE_FIXED_PITCH: u16 = 0x0002;
+    pub const REDRAW_STATUS: u16 = 0x0004;
+    pub const PICTURES: u16 = 0x0008;
+    pub const UNDO: u16 = 0x0010;
+    pub const MOUSE: u16 = 0x0020;
+    pub const COLOURS: u16 = 0x0040;
+    pub const SOUND_EFFECTS: u16 = 0x0080;
+    pub const MENUS: u16 = 0x0100;
+
+    /// ZSpec 1.1.1.1 - the only header bits the game may change while it runs.
+    pub const GAME_WRITABLE: u16 = TRANSCRIPTING | FORCE_FIXED_PITCH | REDRAW_STATUS;
+}
+
+/// Word numbers in the header extension table (V5+).
+/// See ZSpec 11.1.7 for details.
+pub mod header_extension {
+    pub const UNICODE_TABLE: usize = 3;
 }
@@ -0,0 +1,282 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::header::Header;
+use crate::rszzy::text::ZSCII;
+use crate::rszzy::traits::{Dictionary, Memory};
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+use std::cmp::Ordering;
+
+/// ZSpec 13 - a dictionary: the game's own, from the header, or one supplied to
+/// @tokenise.
+pub struct ZDictionary {
+    separators: Vec<ZSCII>,
+    entry_length: usize,
+    entry_count: usize,
+    // ZSpec 13.2 - a negative entry count means the entries are not sorted.
+    sorted: bool,
+    entries: ZOffset,
+
+    // ZSpec 13.3 - the length of the encoded text at the start of each entry.
+    encoded_length: usize,
+}
+
+impl ZDictionary {
+    /// The game's dictionary, whose address is in the header.
+    #[throws]
+    pub fn new(memory: &impl Memory, version: &Version) -> ZDictionary {
+        let base = ZOffset::from(Header::new(memory).dictionary()?);
+        ZDictionary::at(memory, version, base)?
+    }
+
+    /// ZSpec 13.2 - the dictionary at `base`: a count of word separators and the
+    /// separators themselves, the length of each entry, the number of entries as a
+    /// signed word, and then the entries.
+    #[throws]
+    pub fn at(memory: &impl Memory, version: &Version, base: ZOffset) -> ZDictionary {
+        let separator_count = usize::from(memory.read_byte(base)?);
+        let mut separators = Vec::with_capacity(separator_count);
+        for idx in 0..separator_count {
+            let byte = memory.read_byte(base + 1 + idx)?;
+            separators.push(ZSCII::from(u16::from(byte)));
+        }
+
+        let header = base + 1 + separator_count;
+        let entry_length = usize::from(memory.read_byte(header)?);
+        let raw_count = memory.read_word(header + 1)? as i16;
+        let entry_count = usize::from(raw_count.unsigned_abs());
+        let entries = header + 3;
+
+        let encoded_length = version.dictionary_zchars / 3 * 2;
+        ensure!(
+            entry_count == 0 || entry_length >= encoded_length,
+            anyhow!(
+                "Dictionary at {} has entries of {} bytes, too short for {} bytes of text",
+                base,
+                entry_length,
+                encoded_length
+            )
+        );
+        let end = usize::from(entries) + entry_count * entry_length;
+        ensure!(
+            end <= memory.memory_size(),
+            anyhow!(
+                "Dictionary at {} with {} entries runs past end of memory",
+                base,
+                entry_count
+            )
+        );
+
+        ZDictionary {
+            separators,
+            entry_length,
+            entry_count,
+            sorted: raw_count >= 0,
+            entries,
+            encoded_length,
+        }
+    }
+
+    fn entry(&self, idx: usize) -> ZOffset {
+        self.entries + idx * self.entry_length
+    }
+
+    /// Compares the encoded text of entry `idx` with `encoded`.
+    #[throws]
+    fn compare(&self, memory: &impl Memory, idx: usize, encoded: &[u8]) -> Ordering {
+        let entry = self.entry(idx);
+        for (i, byte) in encoded.iter().enumerate() {
+            match memory.read_byte(entry + i)?.cmp(byte) {
+                Ordering::Equal => {}
+                ordering => return ordering,
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl Dictionary for ZDictionary {
+    fn separators(&self) -> &[ZSCII] {
+        &self.separators
+    }
+
+    #[throws]
+    fn lookup(&self, memory: &impl Memory, encoded: &[u8]) -> Option<ZOffset> {
+        ensure!(
+            encoded.len() == self.encoded_length,
+            anyhow!(
+                "Looking up {} bytes of encoded text in a dictionary of {}-byte words",
+                encoded.len(),
+                self.encoded_length
+            )
+        );
+
+        if !self.sorted {
+            for idx in 0..self.entry_count {
+                if self.compare(memory, idx, encoded)? == Ordering::Equal {
+                    return Some(self.entry(idx));
+                }
+            }
+            return None;
+        }
+
+        // ZSpec 13.2 - sorted entries are in numerical order of their encoded text,
+        // which is the same as comparing the bytes in order.
+        let (mut low, mut high) = (0, self.entry_count);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.compare(memory, mid, encoded)? {
+                Ordering::Equal => return Some(self.entry(mid)),
+                Ordering::Less => low = mid + 1,
+                Ordering::Greater => high = mid,
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::rszzy::constants::header_offset::DICTIONARY_START;
+    use crate::rszzy::memory::test::{fake_memory_with, put_word};
+    use crate::rszzy::memory::ZMemory;
+    use crate::rszzy::text::encode_text;
+    use crate::rszzy::text::test::zscii;
+    use crate::rszzy::versions::number_to_version;
+
+    const DICT: usize = 0x100;
+    const WORDS: &[&str] = &["zebra", "apple", "cat", "bat", "a", "xylophone"];
+
+    fn encode(version: u8, word: &str) -> Vec<u8> {
+        let memory = fake_memory_with(version, 0x100, 0x80, |_| {});
+        encode_text(&memory, number_to_version(version).unwrap(), &zscii(word)).unwrap()
+    }
+
+    /// The bytes of a dictionary with the word separators `separators` and an entry
+    /// for each of `words`, sorted unless `unsorted`. Each entry has 2 bytes of data
+    /// holding the word's index in `words`.
+    pub fn dictionary_bytes(
+        version: u8,
+        separators: &str,
+        words: &[&str],
+        unsorted: bool,
+    ) -> Vec<u8> {
+        let mut entries = words
+            .iter()
+            .enumerate()
+            .map(|(idx, word)| {
+                let mut entry = encode(version, word);
+                entry.extend_from_slice(&[0, idx as u8]);
+                entry
+            })
+            .collect::<Vec<_>>();
+        if !unsorted {
+            entries.sort();
+        }
+
+        let entry_length = number_to_version(version).unwrap().dictionary_zchars / 3 * 2 + 2;
+        let mut dict = vec![separators.len() as u8];
+        dict.extend_from_slice(separators.as_bytes());
+        dict.push(entry_length as u8);
+        let count = if unsorted {
+            -(entries.len() as i16)
+        } else {
+            entries.len() as i16
+        };
+        dict.extend_from_slice(&count.to_be_bytes());
+        for entry in entries {
+            dict.extend_from_slice(&entry);
+        }
+        dict
+    }
+
+    // The game's dictionary, at DICT, with separators ".," and the entries in WORDS.
+    fn dictionary_memory(version: u8, unsorted: bool) -> ZMemory {
+        let dict = dictionary_bytes(version, ".,", WORDS, unsorted);
+        fake_memory_with(version, 0x400, 0x80, |bytes| {
+            put_word(bytes, DICTIONARY_START, DICT as u16);
+            bytes[DICT..DICT + dict.len()].copy_from_slice(&dict);
+        })
+    }
+
+    // The index in WORDS of the entry found for `word`, or None.
+    fn find(memory: &ZMemory, version: u8, word: &str) -> Option<u8> {
+        let version = number_to_version(version).unwrap();
+        let dict = ZDictionary::new(memory, version).unwrap();
+        dict.find_word(memory, version, &zscii(word))
+            .unwrap()
+            .map(|entry| memory.read_byte(entry + dict.encoded_length + 1).unwrap())
+    }
+
+    #[test]
+    fn test_header() {
+        let memory = dictionary_memory(3, false);
+        let dict = ZDictionary::new(&memory, number_to_version(3).unwrap()).unwrap();
+        assert_eq!(&zscii(".,")[..], dict.separators());
+        assert_eq!(6, dict.entry_length);
+        assert_eq!(WORDS.len(), dict.entry_count);
+        assert!(dict.sorted);
+        assert_eq!(DICT + 6, usize::from(dict.entries));
+    }
+
+    #[test]
+    fn test_sorted_lookup() {
+        for version in &[3, 5] {
+            let memory = dictionary_memory(*version, false);
+            for (idx, word) in WORDS.iter().enumerate() {
+                assert_eq!(Some(idx as u8), find(&memory, *version, word));
+            }
+            assert_eq!(None, find(&memory, *version, "dog"));
+            assert_eq!(None, find(&memory, *version, ""));
+        }
+    }
+
+    #[test]
+    fn test_truncated_lookup() {
+        // Only the first 6 z-chars count in V3, so "xylophones" matches "xylophone"...
+        let memory = dictionary_memory(3, false);
+        assert_eq!(Some(5), find(&memory, 3, "xylophones"));
+        assert_eq!(Some(5), find(&memory, 3, "xyloph"));
+
+        // ...while V5 words are 9 z-chars long.
+        let memory = dictionary_memory(5, false);
+        assert_eq!(Some(5), find(&memory, 5, "xylophones"));
+        assert_eq!(None, find(&memory, 5, "xyloph"));
+    }
+
+    #[test]
+    fn test_unsorted_lookup() {
+        let memory = dictionary_memory(3, true);
+        let dict = ZDictionary::new(&memory, number_to_version(3).unwrap()).unwrap();
+        assert!(!dict.sorted);
+        assert_eq!(WORDS.len(), dict.entry_count);
+        for (idx, word) in WORDS.iter().enumerate() {
+            assert_eq!(Some(idx as u8), find(&memory, 3, word));
+        }
+        assert_eq!(None, find(&memory, 3, "dog"));
+    }
+
+    #[test]
+    fn test_bad_dictionaries() {
+        let v3 = number_to_version(3).unwrap();
+
+        // Entries too short for the encoded text.
+        let memory = fake_memory_with(3, 0x400, 0x80, |bytes| {
+            bytes[DICT..DICT + 4].copy_from_slice(&[0, 3, 0, 1]);
+        });
+        assert!(ZDictionary::at(&memory, v3, DICT.into()).is_err());
+
+        // Entries past the end of memory.
+        let memory = fake_memory_with(3, 0x400, 0x80, |bytes| {
+            bytes[DICT..DICT + 4].copy_from_slice(&[0, 7, 0x10, 0]);
+        });
+        assert!(ZDictionary::at(&memory, v3, DICT.into()).is_err());
+
+        // Looking up text of the wrong length.
+        let memory = dictionary_memory(3, false);
+        let dict = ZDictionary::new(&memory, v3).unwrap();
+        assert!(dict.lookup(&memory, &[0; 6]).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+//! Entry points for the cargo-fuzz targets in `fuzz/`. Each one treats arbitrary bytes
+//! as a story file and runs part of the machine over it. Errors are expected; panics
+//! are bugs.
+
+use crate::rszzy::abbrevs::ZAbbrevTable;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::constants::header_offset::HEADER_SIZE;
+use crate::rszzy::header::Header;
+use crate::rszzy::instruction::decode;
+use crate::rszzy::memory::ZMemory;
+use crate::rszzy::text::ZString;
+use crate::rszzy::traits::Memory;
+use crate::rszzy::unicode::ZUnicodeTable;
+
+// Limits the work done per input, so that large inputs don't slow the fuzzer down.
+const MAX_OFFSETS: usize = 0x400;
+
+fn offsets(memory: &ZMemory) -> impl Iterator<Item = ZOffset> {
+    (HEADER_SIZE..memory.memory_size())
+        .take(MAX_OFFSETS)
+        .map(ZOffset::from)
+}
+
+/// Load `data` as a story, and read every header field.
+pub fn load_story(data: &[u8]) {
+    let memory = match ZMemory::from_reader(data) {
+        Ok(memory) => memory,
+        Err(_) => return,
+    };
+    let header = Header::new(&memory);
+    let _ = header.version();
+    let _ = header.serial();
+    let _ = header.file_length();
+    let _ = header.alphabet_table();
+    let _ = header.extension_word(3);
+}
+
+/// Decode a ZString at each offset of the story in `data`.
+pub fn decode_zstrings(data: &[u8]) {
+    let memory = match ZMemory::from_reader(data) {
+        Ok(memory) => memory,
+        Err(_) => return,
+    };
+    let (version, abbrevs, unicode) = match (
+        Header::new(&memory).version(),
+        ZAbbrevTable::new(&memory),
+        ZUnicodeTable::new(&memory),
+    ) {
+        (Ok(version), Ok(abbrevs), Ok(unicode)) => (version, abbrevs, unicode),
+        _ => return,
+    };
+    for offset in offsets(&memory) {
+        if let Ok(zs) = ZString::at(&memory, version, &abbrevs, offset) {
+            let _ = unicode.decode(zs);
+        }
+    }
+}
+
+/// Decode an instruction at each offset of the story in `data`.
+pub fn decode_instructions(data: &[u8]) {
+    let memory = match ZMemory::from_reader(data) {
+        Ok(memory) => memory,
+        Err(_) => return,
+    };
+    let version = match Header::new(&memory).version() {
+        Ok(version) => version,
+        Err(_) => return,
+    };
+    for offset in offsets(&memory) {
+        let _ = decode(&memory, version, offset);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::constants::header_offset::{
+        FILE_LENGTH, HIGH_MEMORY_MARK, STATIC_MEMORY_START, VERSION_NUMBER,
+    };
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn prop_short_files(data in prop::collection::vec(any::<u8>(), 0..0x60)) {
+            load_story(&data);
+        }
+
+        // Random stories with enough of a header to load, so that the decoders run.
+        #[test]
+        fn prop_random_stories(
+            version in 1u8..=8,
+            static_start in 0x40usize..0x200,
+            mut data in prop::collection::vec(any::<u8>(), 0x200..0x400),
+        ) {
+            data[VERSION_NUMBER] = version;
+            data[STATIC_MEMORY_START] = (static_start >> 8) as u8;
+            data[STATIC_MEMORY_START + 1] = (static_start & 0xff) as u8;
+            data[HIGH_MEMORY_MARK] = ((static_start + 1) >> 8) as u8;
+            data[HIGH_MEMORY_MARK + 1] = ((static_start + 1) & 0xff) as u8;
+            // No file length, so that the story is never truncated.
+            data[FILE_LENGTH] = 0;
+            data[FILE_LENGTH + 1] = 0;
+
+            load_story(&data);
+            decode_zstrings(&data);
+            decode_instructions(&data);
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::header::Header;
+use crate::rszzy::traits::{GlobalTable, Memory};
+use anyhow::{anyhow, Error};
+use fehler::throws;
+
+/// ZSpec 6.2 - there are 240 globals, numbered 0x10-0xff as variables.
+pub const NUM_GLOBALS: usize = 240;
+
+// Offset is location of global table from header.
+pub struct ZGlobalTable(ZOffset);
+
+impl ZGlobalTable {
+    #[throws]
+    pub fn new(memory: &impl Memory) -> ZGlobalTable {
+        let start = ZOffset::from(Header::new(memory).globals()?);
+        let last = start + (2 * NUM_GLOBALS - 1);
+
+        // ZSpec 6.2 - the table lives in dynamic memory so that the game can write it.
+        ensure!(
+            memory.in_dynamic_range(start) && memory.in_dynamic_range(last),
+            anyhow!(
+                "Global variable table, {} to {}, runs past the end of dynamic memory.",
+                start,
+                last
+            )
+        );
+
+        ZGlobalTable(start)
+    }
+
+    #[throws]
+    fn offset(&self, idx: u8) -> ZOffset {
+        ensure!(
+            usize::from(idx) < NUM_GLOBALS,
+            anyhow!(
+                "Global number, {}, is outside legal range, [0,{}).",
+                idx,
+                NUM_GLOBALS
+            )
+        );
+        self.0 + usize::from(idx) * 2
+    }
+}
+
+impl GlobalTable for ZGlobalTable {
+    #[throws]
+    fn read_global(&self, memory: &impl Memory, idx: u8) -> u16 {
+        memory.read_word(self.offset(idx)?)?
+    }
+
+    #[throws]
+    fn write_global(&self, memory: &mut impl Memory, idx: u8, val: u16) {
+        memory.write_word(self.offset(idx)?, val)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::constants::header_offset::GLOBAL_TABLE_START;
+    use crate::rszzy::traits::test::TestMemory;
+
+    const TABLE_START: usize = 0x40;
+
+    fn globals_memory(table_start: usize, static_start: usize) -> TestMemory {
+        let mut bytes = vec![0; 0x400];
+        bytes[GLOBAL_TABLE_START] = (table_start >> 8) as u8;
+        bytes[GLOBAL_TABLE_START + 1] = (table_start & 0xff) as u8;
+        TestMemory::new(bytes, static_start, static_start)
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut m = globals_memory(TABLE_START, 0x300);
+        let globals = ZGlobalTable::new(&m).unwrap();
+
+        assert_eq!(0, globals.read_global(&m, 0).unwrap());
+        globals.write_global(&mut m, 0, 0x1234).unwrap();
+        globals.write_global(&mut m, 239, 0xfedc).unwrap();
+
+        assert_eq!(0x1234, globals.read_global(&m, 0).unwrap());
+        assert_eq!(0xfedc, globals.read_global(&m, 239).unwrap());
+
+        // Globals are stored big-endian starting at the table address.
+        assert_eq!(0x12, m.read_byte(TABLE_START.into()).unwrap());
+        assert_eq!(0x34, m.read_byte((TABLE_START + 1).into()).unwrap());
+        assert_eq!(0xfe, m.read_byte((TABLE_START + 478).into()).unwrap());
+        assert_eq!(0xdc, m.read_byte((TABLE_START + 479).into()).unwrap());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        let mut m = globals_memory(TABLE_START, 0x300);
+        let globals = ZGlobalTable::new(&m).unwrap();
+
+        assert!(globals.read_global(&m, 240).is_err());
+        assert!(globals.write_global(&mut m, 255, 0).is_err());
+    }
+
+    #[test]
+    fn test_past_dynamic_memory() {
+        // Table fits exactly.
+        assert!(ZGlobalTable::new(&globals_memory(TABLE_START, TABLE_START + 480)).is_ok());
+        // Last byte falls in static memory.
+        assert!(ZGlobalTable::new(&globals_memory(TABLE_START, TABLE_START + 479)).is_err());
+        // Table starts in static memory.
+        assert!(ZGlobalTable::new(&globals_memory(0x380, 0x300)).is_err());
+    }
+}
@@ -1,12 +1,286 @@
-use crate::rszzy::addressing::ByteAddress;
-use crate::rszzy::constants::header_offset::START_PC;
+use crate::rszzy::addressing::{ByteAddress, PackedAddress, ZOffset};
+use crate::rszzy::constants::header_offset::*;
 use crate::rszzy::traits::Memory;
+use crate::rszzy::versions::{number_to_version, Version};
+use anyhow::Error;
+use fehler::throws;
 
-pub struct Header;
+/// A typed view of the story file header, as defined in ZSpec 11.
+/// Every accessor reads through to memory, so the view always reflects the
+/// current contents of the header.
+pub struct Header<'a, M> {
+    memory: &'a M,
+}
+
+impl<'a, M> Header<'a, M>
+where
+    M: Memory,
+{
+    pub fn new(memory: &'a M) -> Header<'a, M> {
+        Header { memory }
+    }
+
+    #[throws]
+    fn byte(&self, offset: usize) -> u8 {
+        self.memory.read_byte(ZOffset::from(offset))?
+    }
+
+    #[throws]
+    fn word(&self, offset: usize) -> u16 {
+        self.memory.read_word(offset)?
+    }
+
+    /// A header address, or None if it is zero (the table is absent).
+    #[throws]
+    fn optional_address(&self, offset: usize) -> Option<ByteAddress> {
+        match self.word(offset)? {
+            0 => None,
+            addr => Some(ByteAddress::raw(addr)),
+        }
+    }
+
+    /// ZSpec 11.1.1 - the version number, which must be one we know about.
+    #[throws]
+    pub fn version(&self) -> &'static Version {
+        number_to_version(self.byte(VERSION_NUMBER)?)?
+    }
+
+    /// ZSpec 11.1.2 - Flags 1. See `constants::flags1` for the bits.
+    #[throws]
+    pub fn flags1(&self) -> u8 {
+        self.byte(FLAGS1)?
+    }
+
+    /// ZSpec 11.1.3 - Flags 2. See `constants::flags2` for the bits.
+    #[throws]
+    pub fn flags2(&self) -> u16 {
+        self.word(FLAGS2)?
+    }
+
+    /// ZSpec 11.1.4 - the serial number, conventionally the compile date as YYMMDD.
+    /// Bytes which are not printable ASCII are shown as '?'.
+    #[throws]
+    pub fn serial(&self) -> String {
+        let mut serial = String::with_capacity(6);
+        for idx in 0..6 {
+            let byte = self.byte(SERIAL_NUMBER + idx)?;
+            serial.push(match byte {
+                0x20..=0x7e => char::from(byte),
+                _ => '?',
+            });
+        }
+        serial
+    }
+
+    /// ZSpec 5.5 - where execution begins. (In V6 this is the packed address of
+    /// the main routine instead; see `main_routine`.)
+    #[throws]
+    pub fn start_pc(&self) -> ByteAddress {
+        ByteAddress::raw(self.word(START_PC)?)
+    }
+
+    /// ZSpec 5.5.1 - in V6, the packed address of the main routine.
+    #[throws]
+    pub fn main_routine(&self) -> PackedAddress {
+        PackedAddress::from(self.word(START_PC)?)
+    }
+
+    #[throws]
+    pub fn dictionary(&self) -> ByteAddress {
+        ByteAddress::raw(self.word(DICTIONARY_START)?)
+    }
+
+    #[throws]
+    pub fn object_table(&self) -> ByteAddress {
+        ByteAddress::raw(self.word(OBJECT_TABLE_START)?)
+    }
+
+    #[throws]
+    pub fn globals(&self) -> ByteAddress {
+        ByteAddress::raw(self.word(GLOBAL_TABLE_START)?)
+    }
+
+    #[throws]
+    pub fn abbreviations(&self) -> ByteAddress {
+        ByteAddress::raw(self.word(ABBREV_TABLE_START)?)
+    }
+
+    /// ZSpec 11.1.6 - the length of the story file in bytes, or None if the header
+    /// doesn't give it (as in some early V1-3 stories).
+    #[throws]
+    pub fn file_length(&self) -> Option<usize> {
+        match self.word(FILE_LENGTH)? {
+            0 => None,
+            len => Some(usize::from(len) * self.version()?.file_length_multiplier),
+        }
+    }
+
+    #[throws]
+    pub fn checksum(&self) -> u16 {
+        self.word(CHECKSUM)?
+    }
+
+    /// ZSpec 10.5.2.1 - the table of extra characters which terminate input.
+    #[throws]
+    pub fn terminating_chars_table(&self) -> Option<ByteAddress> {
+        self.optional_address(TERMINATING_CHARS_TABLE)?
+    }
+
+    /// ZSpec 3.5.5 - the game's own alphabet table (V5+).
+    #[throws]
+    pub fn alphabet_table(&self) -> Option<ByteAddress> {
+        if !self.version()?.custom_alphabet {
+            return None;
+        }
+        self.optional_address(ALPHABET_TABLE_START)?
+    }
+
+    /// ZSpec 11.1.7 - the header extension table (V5+).
+    #[throws]
+    pub fn extension_table(&self) -> Option<ByteAddress> {
+        if !self.version()?.header_extension {
+            return None;
+        }
+        self.optional_address(HEADER_EXTENSION_START)?
+    }
+
+    /// ZSpec 11.1.7 - word `idx` of the header extension table. None if the story
+    /// has no extension table, or if the table is too short to contain the word.
+    #[throws]
+    pub fn extension_word(&self, idx: usize) -> Option<u16> {
+        let table = match self.extension_table()? {
+            None => return None,
+            Some(table) => ZOffset::from(table),
+        };
+
+        // Word 0 is the number of words which follow it.
+        let len = usize::from(self.memory.read_word(table)?);
+        if idx == 0 || idx > len {
+            return None;
+        }
+        Some(self.memory.read_word(table + 2 * idx)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::constants::flags1::STATUS_LINE_TIME;
+    use crate::rszzy::constants::flags2::TRANSCRIPTING;
+    use crate::rszzy::memory::test::{fake_memory_with, put_word};
+
+    #[test]
+    fn test_fields() {
+        let memory = fake_memory_with(3, 0x20000, 0x80, |bytes| {
+            bytes[FLAGS1] = STATUS_LINE_TIME;
+            put_word(bytes, START_PC, 0x4f05);
+            put_word(bytes, DICTIONARY_START, 0x3b21);
+            put_word(bytes, OBJECT_TABLE_START, 0x03c6);
+            put_word(bytes, GLOBAL_TABLE_START, 0x02b0);
+            put_word(bytes, FLAGS2, TRANSCRIPTING);
+            bytes[SERIAL_NUMBER..SERIAL_NUMBER + 6].copy_from_slice(b"840726");
+            put_word(bytes, ABBREV_TABLE_START, 0x01f0);
+            put_word(bytes, FILE_LENGTH, 0x8000);
+            put_word(bytes, CHECKSUM, 0xa129);
+            put_word(bytes, TERMINATING_CHARS_TABLE, 0x60);
+            // Ignored before V5.
+            put_word(bytes, ALPHABET_TABLE_START, 0x70);
+            put_word(bytes, HEADER_EXTENSION_START, 0x70);
+        });
+        let header = Header::new(&memory);
+
+        assert_eq!(3, header.version().unwrap().version_number);
+        assert_eq!(STATUS_LINE_TIME, header.flags1().unwrap());
+        assert_eq!(
+            0x4f05,
+            usize::from(ZOffset::from(header.start_pc().unwrap()))
+        );
+        assert_eq!(
+            0x3b21,
+            usize::from(ZOffset::from(header.dictionary().unwrap()))
+        );
+        assert_eq!(
+            0x03c6,
+            usize::from(ZOffset::from(header.object_table().unwrap()))
+        );
+        assert_eq!(
+            0x02b0,
+            usize::from(ZOffset::from(header.globals().unwrap()))
+        );
+        assert_eq!(TRANSCRIPTING, header.flags2().unwrap());
+        assert_eq!("840726", header.serial().unwrap());
+        assert_eq!(
+            0x01f0,
+            usize::from(ZOffset::from(header.abbreviations().unwrap()))
+        );
+        assert_eq!(Some(0x10000), header.file_length().unwrap());
+        assert_eq!(0xa129, header.checksum().unwrap());
+        let table = header.terminating_chars_table().unwrap().unwrap();
+        assert_eq!(0x60, usize::from(ZOffset::from(table)));
+        assert!(header.alphabet_table().unwrap().is_none());
+        assert!(header.extension_table().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_serial_and_file_length() {
+        let memory = fake_memory_with(5, 0x100, 0x80, |bytes| {
+            bytes[SERIAL_NUMBER..SERIAL_NUMBER + 6].copy_from_slice(b"12\x0034\xff");
+            put_word(bytes, FILE_LENGTH, 0x40);
+        });
+        let header = Header::new(&memory);
+        assert_eq!("12?34?", header.serial().unwrap());
+        assert_eq!(Some(0x100), header.file_length().unwrap());
+        assert!(header.terminating_chars_table().unwrap().is_none());
+
+        let memory = fake_memory_with(8, 0x100, 0x80, |bytes| {
+            put_word(bytes, FILE_LENGTH, 0x20);
+        });
+        assert_eq!(Some(0x100), Header::new(&memory).file_length().unwrap());
+
+        let memory = fake_memory_with(3, 0x100, 0x80, |_| {});
+        assert_eq!(None, Header::new(&memory).file_length().unwrap());
+    }
+
+    #[test]
+    fn test_bad_version() {
+        let mut memory = fake_memory_with(5, 0x100, 0x80, |bytes| {
+            put_word(bytes, FILE_LENGTH, 0x40);
+        });
+        memory
+            .write_byte_unchecked(ZOffset::from(VERSION_NUMBER), 0)
+            .unwrap();
+        let header = Header::new(&memory);
+        assert!(header.version().is_err());
+        assert!(header.file_length().is_err());
+        assert!(header.extension_word(1).is_err());
+    }
+
+    #[test]
+    fn test_extension_word() {
+        let memory = fake_memory_with(5, 0x100, 0x80, |bytes| {
+            bytes[HEADER_EXTENSION_START + 1] = 0x40;
+            bytes[0x41] = 2;
+            bytes[0x43] = 0x11;
+            bytes[0x45] = 0x22;
+        });
+        let header = Header::new(&memory);
+        assert_eq!(
+            0x40,
+            usize::from(ZOffset::from(header.extension_table().unwrap().unwrap()))
+        );
+        assert_eq!(None, header.extension_word(0).unwrap());
+        assert_eq!(Some(0x11), header.extension_word(1).unwrap());
+        assert_eq!(Some(0x22), header.extension_word(2).unwrap());
+        assert_eq!(None, header.extension_word(3).unwrap());
+
+        // There is no extension table before V5.
+        let memory = fake_memory_with(3, 0x100, 0x80, |bytes| {
+            bytes[HEADER_EXTENSION_START + 1] = 0x40;
+            bytes[0x41] = 2;
+        });
+        assert_eq!(None, Header::new(&memory).extension_word(1).unwrap());
 
-impl Header {
-    pub fn start_pc(memory: &impl Memory) -> ByteAddress {
-        let addr = memory.read_word(START_PC).unwrap();
-        ByteAddress::raw(addr)
+        let memory = fake_memory_with(5, 0x100, 0x80, |_| {});
+        assert_eq!(None, Header::new(&memory).extension_word(1).unwrap());
     }
 }
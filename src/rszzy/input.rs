@@ -0,0 +1,177 @@
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::header::Header;
+use crate::rszzy::lexer::buffer_text;
+use crate::rszzy::text::ZSCII;
+use crate::rszzy::traits::Memory;
+use crate::rszzy::versions::Version;
+use anyhow::Error;
+use fehler::throws;
+
+/// ZSpec 10.5.2.1 - in the terminating characters table, 255 stands for every
+/// function key.
+const ALL_FUNCTION_KEYS: u8 = 255;
+
+/// ZSpec 3.8 - the function keys: cursor keys, F1-F12, keypad 0-9, and mouse clicks.
+fn is_function_key(code: u16) -> bool {
+    matches!(code, 129..=154 | 252..=254)
+}
+
+/// ZSpec 15 (read) - the most characters the player may type into the text buffer.
+/// In V1-4, byte 0 of the buffer holds one more than that, leaving room for the zero
+/// at the end.
+#[throws]
+pub fn text_capacity(memory: &impl Memory, version: &Version, buffer: ZOffset) -> usize {
+    let size = usize::from(memory.read_byte(buffer)?);
+    if version.text_buffer_length {
+        size
+    } else {
+        size.saturating_sub(1)
+    }
+}
+
+/// ZSpec 15 (read) - in V5+, the game may leave text in the buffer, which the player
+/// then continues typing after.
+#[throws]
+pub fn preloaded_text(memory: &impl Memory, version: &Version, buffer: ZOffset) -> Vec<ZSCII> {
+    if !version.text_buffer_length {
+        return vec![];
+    }
+    let capacity = text_capacity(memory, version, buffer)?;
+    let (mut text, _) = buffer_text(memory, version, buffer)?;
+    text.truncate(capacity);
+    text
+}
+
+/// ZSpec 15 (read) - store the player's input in the text buffer, in the layout
+/// buffer_text reads: ending with a zero in V1-4, or after its length in V5+.
+#[throws]
+pub fn store_text(memory: &mut impl Memory, version: &Version, buffer: ZOffset, text: &[ZSCII]) {
+    let start = if version.text_buffer_length {
+        memory.write_byte(buffer + 1, text.len() as u8)?;
+        buffer + 2
+    } else {
+        memory.write_byte(buffer + 1 + text.len(), 0)?;
+        buffer + 1
+    };
+    for (idx, zscii) in text.iter().enumerate() {
+        memory.write_byte(start + idx, zscii.code() as u8)?;
+    }
+}
+
+/// ZSpec 10.5.2.1 - the function keys which end input as well as newline, from the
+/// table in the header (V5+). The table ends with a zero.
+#[throws]
+pub fn terminating_chars(memory: &impl Memory, version: &Version) -> Vec<ZSCII> {
+    let mut chars = vec![];
+    let table = match Header::new(memory).terminating_chars_table()? {
+        Some(table) if version.terminating_chars => ZOffset::from(table),
+        _ => return chars,
+    };
+
+    for idx in 0.. {
+        match memory.read_byte(table + idx)? {
+            0 => break,
+            ALL_FUNCTION_KEYS => {
+                chars = (129..=154).chain(252..=254).map(ZSCII::from).collect();
+                break;
+            }
+            // Anything but a function key is not allowed, and ignored.
+            code if is_function_key(u16::from(code)) => chars.push(ZSCII::from(u16::from(code))),
+            _ => {}
+        }
+    }
+    chars
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::constants::header_offset::TERMINATING_CHARS_TABLE;
+    use crate::rszzy::memory::test::{fake_memory_with, put_word};
+    use crate::rszzy::memory::ZMemory;
+    use crate::rszzy::text::test::zscii;
+    use crate::rszzy::versions::number_to_version;
+
+    const BUFFER: usize = 0x80;
+    const TABLE: usize = 0x100;
+
+    fn memory_with_table(version: u8, table: &[u8]) -> ZMemory {
+        fake_memory_with(version, 0x200, 0x180, |bytes| {
+            put_word(bytes, TERMINATING_CHARS_TABLE, TABLE as u16);
+            bytes[TABLE..TABLE + table.len()].copy_from_slice(table);
+        })
+    }
+
+    #[test]
+    fn test_capacity_and_preloaded() {
+        let mut memory = fake_memory_with(3, 0x200, 0x180, |bytes| {
+            bytes[BUFFER..BUFFER + 5].copy_from_slice(&[10, 2, b'h', b'i', 0]);
+        });
+        let v3 = number_to_version(3).unwrap();
+        assert_eq!(9, text_capacity(&memory, v3, BUFFER.into()).unwrap());
+        // V1-4 have no preloaded input.
+        assert!(preloaded_text(&memory, v3, BUFFER.into())
+            .unwrap()
+            .is_empty());
+
+        let v5 = number_to_version(5).unwrap();
+        assert_eq!(10, text_capacity(&memory, v5, BUFFER.into()).unwrap());
+        assert_eq!(
+            zscii("hi"),
+            preloaded_text(&memory, v5, BUFFER.into()).unwrap()
+        );
+
+        // Preloaded text longer than the buffer is cut short.
+        memory.write_byte(BUFFER.into(), 1).unwrap();
+        assert_eq!(
+            zscii("h"),
+            preloaded_text(&memory, v5, BUFFER.into()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_store_text() {
+        let v3 = number_to_version(3).unwrap();
+        let mut memory = fake_memory_with(3, 0x200, 0x180, |bytes| {
+            bytes[BUFFER..BUFFER + 6].copy_from_slice(&[10, 1, 2, 3, 4, 5]);
+        });
+        store_text(&mut memory, v3, BUFFER.into(), &zscii("go")).unwrap();
+        assert_eq!(
+            (zscii("go"), 1),
+            buffer_text(&memory, v3, BUFFER.into()).unwrap()
+        );
+        assert_eq!(0, memory.read_byte((BUFFER + 3).into()).unwrap());
+
+        let v5 = number_to_version(5).unwrap();
+        let mut memory = fake_memory_with(5, 0x200, 0x180, |bytes| {
+            bytes[BUFFER..BUFFER + 6].copy_from_slice(&[10, 1, 2, 3, 4, 5]);
+        });
+        store_text(&mut memory, v5, BUFFER.into(), &zscii("go")).unwrap();
+        assert_eq!(
+            (zscii("go"), 2),
+            buffer_text(&memory, v5, BUFFER.into()).unwrap()
+        );
+        // No zero at the end in V5.
+        assert_eq!(4, memory.read_byte((BUFFER + 4).into()).unwrap());
+    }
+
+    #[test]
+    fn test_terminating_chars() {
+        let v5 = number_to_version(5).unwrap();
+        let memory = memory_with_table(5, &[129, 13, 252, 0, 130]);
+        assert_eq!(
+            vec![ZSCII::from(129), ZSCII::from(252)],
+            terminating_chars(&memory, v5).unwrap()
+        );
+
+        let memory = memory_with_table(5, &[129, 255, 0]);
+        let chars = terminating_chars(&memory, v5).unwrap();
+        assert_eq!(29, chars.len());
+        assert!(chars.iter().all(|zscii| is_function_key(zscii.code())));
+
+        // The table is ignored before V5.
+        let memory = memory_with_table(4, &[129, 0]);
+        let v4 = number_to_version(4).unwrap();
+        assert!(terminating_chars(&memory, v4).unwrap().is_empty());
+    }
+}
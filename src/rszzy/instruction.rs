@@ -0,0 +1,495 @@
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::opcodes::{Opcode, OperandCount};
+use crate::rszzy::traits::Memory;
+use crate::rszzy::versions::Version;
+use anyhow::{Context, Error};
+use fehler::throws;
+
+/// ZSpec 4.3 - the four instruction forms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    Long,
+    Short,
+    Variable,
+    Extended,
+}
+
+/// ZSpec 4.2 - the encoding of a single operand before it is decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperandType {
+    LargeConstant,
+    SmallConstant,
+    Variable,
+    Omitted,
+}
+
+impl From<u8> for OperandType {
+    // Only the bottom two bits are considered.
+    fn from(bits: u8) -> OperandType {
+        match bits & 0b11 {
+            0b00 => OperandType::LargeConstant,
+            0b01 => OperandType::SmallConstant,
+            0b10 => OperandType::Variable,
+            _ => OperandType::Omitted,
+        }
+    }
+}
+
+/// ZSpec 4.2 - an operand, as read from the instruction stream.
+/// Variable operands hold the variable number, not its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    LargeConstant(u16),
+    SmallConstant(u8),
+    Variable(u8),
+}
+
+/// ZSpec 4.7.1 - where a branch goes if it is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BranchTarget {
+    ReturnFalse,
+    ReturnTrue,
+    Offset(i16),
+}
+
+/// ZSpec 4.7 - branch data following an instruction.
+/// The branch is taken when the condition evaluates to `on_true`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Branch {
+    pub on_true: bool,
+    pub target: BranchTarget,
+}
+
+/// A fully decoded instruction. See ZSpec 4.1 for the layout.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub offset: ZOffset,
+    pub form: Form,
+    pub opcode: Opcode,
+    pub operands: Vec<Operand>,
+    pub store: Option<u8>,
+    pub branch: Option<Branch>,
+
+    /// Location of the inline ZString for print and print_ret.
+    pub text: Option<ZOffset>,
+
+    /// Total number of bytes in the instruction, including any inline text.
+    pub length: usize,
+}
+
+impl Instruction {
+    /// The offset of the instruction immediately following this one.
+    pub fn next_offset(&self) -> ZOffset {
+        self.offset + self.length
+    }
+}
+
+/// Reads successive bytes from memory. Unlike Memory::read_byte, this will
+/// read from high memory, since that is where code lives.
+struct Cursor<'a, M> {
+    memory: &'a M,
+    start: usize,
+    pos: usize,
+}
+
+impl<'a, M> Cursor<'a, M>
+where
+    M: Memory,
+{
+    fn new(memory: &'a M, offset: ZOffset) -> Cursor<'a, M> {
+        let start = usize::from(offset);
+        Cursor {
+            memory,
+            start,
+            pos: start,
+        }
+    }
+
+    #[throws]
+    fn next_byte(&mut self) -> u8 {
+        let byte = self
+            .memory
+            .read_code_byte(ZOffset::from(self.pos))
+            .with_context(|| format!("Instruction at {}", ZOffset::from(self.start)))?;
+        self.pos += 1;
+        byte
+    }
+
+    #[throws]
+    fn next_word(&mut self) -> u16 {
+        let high_byte = u16::from(self.next_byte()?);
+        let low_byte = u16::from(self.next_byte()?);
+        (high_byte << 8) + low_byte
+    }
+
+    fn len(&self) -> usize {
+        self.pos - self.start
+    }
+}
+
+/// Decode the instruction at `offset`, as described in ZSpec 4.
+#[throws]
+pub fn decode<M>(memory: &M, version: &Version, offset: ZOffset) -> Instruction
+where
+    M: Memory,
+{
+    let mut cursor = Cursor::new(memory, offset);
+
+    let first = cursor.next_byte()?;
+
+    // ZSpec 4.3 - determine the form, the operand count, and the operand types.
+    let (form, count, number, types) = if first == 0xbe && version.extended_opcodes {
+        // ZSpec 4.3.4
+        let number = cursor.next_byte()?;
+        let types = read_type_byte(&mut cursor)?;
+        (Form::Extended, OperandCount::Ext, number, types)
+    } else {
+        match first >> 6 {
+            0b11 => {
+                // ZSpec 4.3.3
+                let count = if first & 0b0010_0000 == 0 {
+                    OperandCount::Op2
+                } else {
+                    OperandCount::Var
+                };
+                let number = first & 0b0001_1111;
+
+                // ZSpec 4.4.3.1 - call_vs2 and call_vn2 take a second type byte.
+                let mut types = read_type_byte(&mut cursor)?;
+                if count == OperandCount::Var && (number == 0x0c || number == 0x1a) {
+                    types.extend(read_type_byte(&mut cursor)?);
+                }
+                (Form::Variable, count, number, types)
+            }
+            0b10 => {
+                // ZSpec 4.3.1
+                let operand_type = OperandType::from(first >> 4);
+                let number = first & 0b0000_1111;
+                if operand_type == OperandType::Omitted {
+                    (Form::Short, OperandCount::Op0, number, vec![])
+                } else {
+                    (Form::Short, OperandCount::Op1, number, vec![operand_type])
+                }
+            }
+            _ => {
+                // ZSpec 4.3.2 - long form is always 2OP, types are encoded in bits 6 and 5.
+                let type_for_bit = |bit: u8| {
+                    if first & bit == 0 {
+                        OperandType::SmallConstant
+                    } else {
+                        OperandType::Variable
+                    }
+                };
+                let types = vec![type_for_bit(0b0100_0000), type_for_bit(0b0010_0000)];
+                (Form::Long, OperandCount::Op2, first & 0b0001_1111, types)
+            }
+        }
+    };
+
+    let opcode = Opcode::decode(count, number, version)?;
+
+    // ZSpec 4.5
+    let mut operands = Vec::with_capacity(types.len());
+    for operand_type in types {
+        let operand = match operand_type {
+            OperandType::LargeConstant => Operand::LargeConstant(cursor.next_word()?),
+            OperandType::SmallConstant => Operand::SmallConstant(cursor.next_byte()?),
+            OperandType::Variable => Operand::Variable(cursor.next_byte()?),
+            OperandType::Omitted => unreachable!("read_type_byte never returns Omitted"),
+        };
+        operands.push(operand);
+    }
+
+    // ZSpec 4.6
+    let store = if opcode.stores(version) {
+        Some(cursor.next_byte()?)
+    } else {
+        None
+    };
+
+    // ZSpec 4.7
+    let branch = if opcode.branches(version) {
+        Some(read_branch(&mut cursor)?)
+    } else {
+        None
+    };
+
+    // ZSpec 4.8
+    let text = if opcode.has_text() {
+        let text_offset = ZOffset::from(cursor.pos);
+        // ZSpec 3.2 - the last word of the string has its top bit set.
+        while cursor.next_word()? & 0x8000 == 0 {}
+        Some(text_offset)
+    } else {
+        None
+    };
+
+    Instruction {
+        offset,
+        form,
+        opcode,
+        operands,
+        store,
+        branch,
+        text,
+        length: cursor.len(),
+    }
+}
+
+/// ZSpec 4.4.3 - read a byte of operand types. The list ends at the first omitted operand.
+#[throws]
+fn read_type_byte<M>(cursor: &mut Cursor<M>) -> Vec<OperandType>
+where
+    M: Memory,
+{
+    let byte = cursor.next_byte()?;
+    [6, 4, 2, 0]
+        .iter()
+        .map(|shift| OperandType::from(byte >> shift))
+        .take_while(|t| *t != OperandType::Omitted)
+        .collect()
+}
+
+/// ZSpec 4.7 - read 1 or 2 bytes of branch data.
+#[throws]
+fn read_branch<M>(cursor: &mut Cursor<M>) -> Branch
+where
+    M: Memory,
+{
+    let first = cursor.next_byte()?;
+    let on_true = first & 0b1000_0000 != 0;
+
+    let offset = if first & 0b0100_0000 != 0 {
+        // ZSpec 4.7.1 - 6-bit unsigned offset.
+        i16::from(first & 0b0011_1111)
+    } else {
+        // ZSpec 4.7.1 - 14-bit signed offset.
+        let second = cursor.next_byte()?;
+        let raw = (u16::from(first & 0b0011_1111) << 8) | u16::from(second);
+        // Sign-extend from 14 bits by shifting into the top of an i16 and back.
+        ((raw << 2) as i16) >> 2
+    };
+
+    let target = match offset {
+        0 => BranchTarget::ReturnFalse,
+        1 => BranchTarget::ReturnTrue,
+        _ => BranchTarget::Offset(offset),
+    };
+
+    Branch { on_true, target }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::traits::test::TestMemory;
+    use crate::rszzy::versions::number_to_version;
+
+    fn decode_bytes(version: u8, bytes: &[u8]) -> Instruction {
+        let memory = TestMemory::new(bytes.to_vec(), 0, 0);
+        decode(
+            &memory,
+            number_to_version(version).unwrap(),
+            ZOffset::from(0usize),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_long_form() {
+        // add L01 #05 -> sp
+        let instr = decode_bytes(3, &[0x54, 0x01, 0x05, 0x00]);
+        assert_eq!(Form::Long, instr.form);
+        assert_eq!(Opcode::Add, instr.opcode);
+        assert_eq!(
+            vec![Operand::Variable(0x01), Operand::SmallConstant(0x05)],
+            instr.operands
+        );
+        assert_eq!(Some(0x00), instr.store);
+        assert_eq!(None, instr.branch);
+        assert_eq!(4, instr.length);
+    }
+
+    #[test]
+    fn test_short_form() {
+        // jz G10 ?~+5 (one-byte branch, branch on false)
+        let instr = decode_bytes(3, &[0xa0, 0x20, 0x45]);
+        assert_eq!(Form::Short, instr.form);
+        assert_eq!(Opcode::Jz, instr.opcode);
+        assert_eq!(vec![Operand::Variable(0x20)], instr.operands);
+        assert_eq!(
+            Some(Branch {
+                on_true: false,
+                target: BranchTarget::Offset(5)
+            }),
+            instr.branch
+        );
+        assert_eq!(3, instr.length);
+
+        // jump #fffe (large constant)
+        let instr = decode_bytes(3, &[0x8c, 0xff, 0xfe]);
+        assert_eq!(Opcode::Jump, instr.opcode);
+        assert_eq!(vec![Operand::LargeConstant(0xfffe)], instr.operands);
+        assert_eq!(3, instr.length);
+
+        // rtrue
+        let instr = decode_bytes(3, &[0xb0]);
+        assert_eq!(Opcode::Rtrue, instr.opcode);
+        assert!(instr.operands.is_empty());
+        assert_eq!(1, instr.length);
+    }
+
+    #[test]
+    fn test_branch_targets() {
+        // je #01 #02 ?rtrue
+        let instr = decode_bytes(3, &[0x01, 0x01, 0x02, 0xc1]);
+        assert_eq!(
+            Some(Branch {
+                on_true: true,
+                target: BranchTarget::ReturnTrue
+            }),
+            instr.branch
+        );
+
+        // je #01 #02 ?~rfalse
+        let instr = decode_bytes(3, &[0x01, 0x01, 0x02, 0x40]);
+        assert_eq!(
+            Some(Branch {
+                on_true: false,
+                target: BranchTarget::ReturnFalse
+            }),
+            instr.branch
+        );
+
+        // je #01 #02 ?-2 (two-byte branch)
+        let instr = decode_bytes(3, &[0x01, 0x01, 0x02, 0xbf, 0xfe]);
+        assert_eq!(
+            Some(Branch {
+                on_true: true,
+                target: BranchTarget::Offset(-2)
+            }),
+            instr.branch
+        );
+        assert_eq!(5, instr.length);
+
+        // je #01 #02 ?+0x1000 (two-byte branch)
+        let instr = decode_bytes(3, &[0x01, 0x01, 0x02, 0x90, 0x00]);
+        assert_eq!(
+            Some(Branch {
+                on_true: true,
+                target: BranchTarget::Offset(0x1000)
+            }),
+            instr.branch
+        );
+    }
+
+    #[test]
+    fn test_variable_form() {
+        // je with four operands in variable form: je G10 #1234 #05 L02 ?+10
+        let instr = decode_bytes(
+            3,
+            &[0xc1, 0b10_00_01_10, 0x10, 0x12, 0x34, 0x05, 0x02, 0xca],
+        );
+        assert_eq!(Form::Variable, instr.form);
+        assert_eq!(Opcode::Je, instr.opcode);
+        assert_eq!(
+            vec![
+                Operand::Variable(0x10),
+                Operand::LargeConstant(0x1234),
+                Operand::SmallConstant(0x05),
+                Operand::Variable(0x02)
+            ],
+            instr.operands
+        );
+        assert_eq!(8, instr.length);
+
+        // call #1234 #05 -> sp, with the rest omitted
+        let instr = decode_bytes(3, &[0xe0, 0b00_01_11_11, 0x12, 0x34, 0x05, 0x00]);
+        assert_eq!(Opcode::CallVs, instr.opcode);
+        assert_eq!(
+            vec![Operand::LargeConstant(0x1234), Operand::SmallConstant(0x05)],
+            instr.operands
+        );
+        assert_eq!(Some(0x00), instr.store);
+        assert_eq!(6, instr.length);
+    }
+
+    #[test]
+    fn test_call_vs2() {
+        let instr = decode_bytes(
+            5,
+            &[
+                0xec,
+                0b00_01_01_01,
+                0b01_01_11_11,
+                0x12,
+                0x34,
+                1,
+                2,
+                3,
+                4,
+                5,
+                0x10,
+            ],
+        );
+        assert_eq!(Opcode::CallVs2, instr.opcode);
+        assert_eq!(6, instr.operands.len());
+        assert_eq!(Operand::SmallConstant(5), instr.operands[5]);
+        assert_eq!(Some(0x10), instr.store);
+        assert_eq!(11, instr.length);
+    }
+
+    #[test]
+    fn test_extended_form() {
+        // log_shift #0001 #03 -> sp
+        let instr = decode_bytes(5, &[0xbe, 0x02, 0b00_01_11_11, 0x00, 0x01, 0x03, 0x00]);
+        assert_eq!(Form::Extended, instr.form);
+        assert_eq!(Opcode::LogShift, instr.opcode);
+        assert_eq!(
+            vec![Operand::LargeConstant(1), Operand::SmallConstant(3)],
+            instr.operands
+        );
+        assert_eq!(Some(0x00), instr.store);
+        assert_eq!(7, instr.length);
+    }
+
+    #[test]
+    fn test_extended_illegal_in_v3() {
+        let memory = TestMemory::new(vec![0xbe, 0x02, 0x1f, 0x01, 0x03, 0x00], 0, 0);
+        assert!(decode(
+            &memory,
+            number_to_version(3).unwrap(),
+            ZOffset::from(0usize)
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_inline_text() {
+        // print "abc..." with two words of text.
+        let instr = decode_bytes(3, &[0xb2, 0x18, 0xe8, 0x98, 0xe8, 0xb0]);
+        assert_eq!(Opcode::Print, instr.opcode);
+        assert_eq!(1, usize::from(instr.text.unwrap()));
+        assert_eq!(5, instr.length);
+        assert_eq!(5, usize::from(instr.next_offset()));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let memory = TestMemory::new(vec![0x54, 0x01], 0, 0);
+        assert!(decode(
+            &memory,
+            number_to_version(3).unwrap(),
+            ZOffset::from(0usize)
+        )
+        .is_err());
+
+        let memory = TestMemory::new(vec![0xb2, 0x18, 0xe8], 0, 0);
+        assert!(decode(
+            &memory,
+            number_to_version(3).unwrap(),
+            ZOffset::from(0usize)
+        )
+        .is_err());
+    }
+}
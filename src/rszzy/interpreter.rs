@@ -0,0 +1,353 @@
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::constants::flags1;
+use crate::rszzy::constants::flags2;
+use crate::rszzy::constants::header_offset::*;
+use crate::rszzy::header::Header;
+use crate::rszzy::traits::Memory;
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::{throw, throws};
+
+/// ZSpec 11.1.3.1 - interpreter numbers. Some games behave differently depending
+/// on which interpreter they think they are running on.
+pub mod interpreter_number {
+    pub const DECSYSTEM_20: u8 = 1;
+    pub const APPLE_IIE: u8 = 2;
+    pub const MACINTOSH: u8 = 3;
+    pub const AMIGA: u8 = 4;
+    pub const ATARI_ST: u8 = 5;
+    pub const IBM_PC: u8 = 6;
+    pub const COMMODORE_128: u8 = 7;
+    pub const COMMODORE_64: u8 = 8;
+    pub const APPLE_IIC: u8 = 9;
+    pub const APPLE_IIGS: u8 = 10;
+    pub const TANDY_COLOR: u8 = 11;
+
+    /// The names accepted for each number by `parse_interpreter_number`.
+    pub const NAMES: &[(&str, u8)] = &[
+        ("decsystem-20", DECSYSTEM_20),
+        ("apple-iie", APPLE_IIE),
+        ("macintosh", MACINTOSH),
+        ("amiga", AMIGA),
+        ("atari-st", ATARI_ST),
+        ("ibm-pc", IBM_PC),
+        ("commodore-128", COMMODORE_128),
+        ("commodore-64", COMMODORE_64),
+        ("apple-iic", APPLE_IIC),
+        ("apple-iigs", APPLE_IIGS),
+        ("tandy-color", TANDY_COLOR),
+    ];
+}
+
+/// An interpreter number given either as a number, or as the name of the machine,
+/// such as "amiga" or "ibm-pc".
+#[throws]
+pub fn parse_interpreter_number(name: &str) -> u8 {
+    if let Ok(number) = name.parse::<u8>() {
+        return number;
+    }
+    let name = name.to_lowercase().replace('_', "-");
+    match interpreter_number::NAMES.iter().find(|(n, _)| *n == name) {
+        Some((_, number)) => *number,
+        None => throw!(anyhow!(
+            "Unknown interpreter: {}. Give a number, or one of: {}",
+            name,
+            interpreter_number::NAMES
+                .iter()
+                .map(|(n, _)| *n)
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+/// What the interpreter tells the game it can do.
+/// ZSpec 11.1.2 (Flags 1) and 11.1.3 (Flags 2).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    // V1-3
+    pub status_line: bool,
+    pub split_screen: bool,
+    pub variable_pitch_default: bool,
+
+    // V4+
+    pub colours: bool,
+    pub pictures: bool,
+    pub boldface: bool,
+    pub italic: bool,
+    pub fixed_space: bool,
+    pub sound_effects: bool,
+    pub timed_input: bool,
+
+    // V5+ - the game asks for these in Flags 2, and the interpreter clears the
+    // bits for those it can't provide.
+    pub undo: bool,
+    pub mouse: bool,
+    pub menus: bool,
+}
+
+impl Default for Capabilities {
+    fn default() -> Capabilities {
+        // Only what the interpreter implements. Turn each one on along with the
+        // opcodes behind it (show_status, split_window, set_text_style...).
+        Capabilities {
+            status_line: false,
+            split_screen: false,
+            variable_pitch_default: false,
+            colours: false,
+            pictures: false,
+            boldface: false,
+            italic: false,
+            fixed_space: false,
+            sound_effects: false,
+            timed_input: true,
+            undo: false,
+            mouse: false,
+            menus: false,
+        }
+    }
+}
+
+/// The interpreter's side of the header: everything ZSpec 11 says the interpreter
+/// must fill in when a story is loaded, and again after @restart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterpreterConfig {
+    pub capabilities: Capabilities,
+
+    pub interpreter_number: u8,
+    pub interpreter_version: u8,
+
+    /// ZSpec 8.4 - 255 lines means the screen never needs to pause.
+    pub screen_height_lines: u8,
+    pub screen_width_chars: u8,
+    /// The size of a character in screen units.
+    pub font_width_units: u8,
+    pub font_height_units: u8,
+
+    /// ZSpec 8.3.1 - colour numbers.
+    pub default_background: u8,
+    pub default_foreground: u8,
+
+    /// ZSpec 11.1.5 - the revision of the standard the interpreter claims, as
+    /// (major, minor).
+    pub standard_revision: (u8, u8),
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> InterpreterConfig {
+        InterpreterConfig {
+            capabilities: Capabilities::default(),
+            interpreter_number: interpreter_number::IBM_PC,
+            interpreter_version: b'A',
+            screen_height_lines: 25,
+            screen_width_chars: 80,
+            font_width_units: 1,
+            font_height_units: 1,
+            // ZSpec 8.3.1 - black on white.
+            default_background: 9,
+            default_foreground: 2,
+            standard_revision: (1, 1),
+        }
+    }
+}
+
+impl InterpreterConfig {
+    /// Write the interpreter's fields into the header of `memory`. Fields which the
+    /// game owns, such as the Flags 1 status line type in V1-3, are left alone.
+    #[throws]
+    pub fn apply(&self, memory: &mut impl Memory, version: &Version) {
+        let caps = &self.capabilities;
+
+        let old_flags1 = Header::new(&*memory).flags1()?;
+        let new_flags1 = if version.interpreter_header {
+            // ZSpec 11.1.2 - in V4+, all of Flags 1 belongs to the interpreter.
+            bits(&[
+                (flags1::COLOURS, caps.colours),
+                (flags1::PICTURES, caps.pictures),
+                (flags1::BOLDFACE, caps.boldface),
+                (flags1::ITALIC, caps.italic),
+                (flags1::FIXED_SPACE, caps.fixed_space),
+                (flags1::SOUND_EFFECTS, caps.sound_effects),
+                (flags1::TIMED_INPUT, caps.timed_input),
+            ])
+        } else {
+            let interpreter_bits = flags1::STATUS_LINE_UNAVAILABLE
+                | flags1::SCREEN_SPLITTING
+                | flags1::VARIABLE_PITCH_DEFAULT;
+            (old_flags1 & !interpreter_bits)
+                | bits(&[
+                    (flags1::STATUS_LINE_UNAVAILABLE, !caps.status_line),
+                    (flags1::SCREEN_SPLITTING, caps.split_screen),
+                    (flags1::VARIABLE_PITCH_DEFAULT, caps.variable_pitch_default),
+                ])
+        };
+        memory.write_byte_unchecked(ZOffset::from(FLAGS1), new_flags1)?;
+
+        // ZSpec 11.1.3 - clear the requests in Flags 2 which can't be granted. Bits
+        // which the story's version doesn't define are left alone.
+        let mut unsupported = 0;
+        for (bit, supported) in &[
+            (flags2::PICTURES, caps.pictures),
+            (flags2::UNDO, caps.undo),
+            (flags2::MOUSE, caps.mouse),
+            (flags2::COLOURS, caps.colours),
+            (flags2::SOUND_EFFECTS, caps.sound_effects),
+            (flags2::MENUS, caps.menus),
+        ] {
+            if !supported {
+                unsupported |= bit;
+            }
+        }
+        let old_flags2 = Header::new(&*memory).flags2()?;
+        let refused = unsupported & version.flags2_requests;
+        memory.write_word_unchecked(FLAGS2, old_flags2 & !refused)?;
+
+        if version.interpreter_header {
+            memory.write_byte_unchecked(INTERPRETER_NUMBER.into(), self.interpreter_number)?;
+            memory.write_byte_unchecked(INTERPRETER_VERSION.into(), self.interpreter_version)?;
+            memory.write_byte_unchecked(SCREEN_HEIGHT_LINES.into(), self.screen_height_lines)?;
+            memory.write_byte_unchecked(SCREEN_WIDTH_CHARS.into(), self.screen_width_chars)?;
+        }
+
+        if version.screen_units {
+            let width = u16::from(self.screen_width_chars) * u16::from(self.font_width_units);
+            let height = u16::from(self.screen_height_lines) * u16::from(self.font_height_units);
+            memory.write_word_unchecked(SCREEN_WIDTH_UNITS, width)?;
+            memory.write_word_unchecked(SCREEN_HEIGHT_UNITS, height)?;
+
+            let (first, second) = if version.font_height_first {
+                (self.font_height_units, self.font_width_units)
+            } else {
+                (self.font_width_units, self.font_height_units)
+            };
+            memory.write_byte_unchecked(FONT_WIDTH_UNITS.into(), first)?;
+            memory.write_byte_unchecked(FONT_HEIGHT_UNITS.into(), second)?;
+
+            memory.write_byte_unchecked(DEFAULT_BACKGROUND.into(), self.default_background)?;
+            memory.write_byte_unchecked(DEFAULT_FOREGROUND.into(), self.default_foreground)?;
+        }
+
+        let (major, minor) = self.standard_revision;
+        memory.write_byte_unchecked(STANDARD_REVISION.into(), major)?;
+        memory.write_byte_unchecked((STANDARD_REVISION + 1).into(), minor)?;
+    }
+}
+
+fn bits(flags: &[(u8, bool)]) -> u8 {
+    flags
+        .iter()
+        .filter(|(_, set)| *set)
+        .fold(0, |acc, (bit, _)| acc | bit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::memory::test::fake_memory_with;
+    use crate::rszzy::versions::number_to_version;
+
+    #[test]
+    fn test_apply_v3() {
+        let mut memory = fake_memory_with(3, 0x100, 0x80, |bytes| {
+            // The game's status line type, and a stale interpreter bit.
+            bytes[FLAGS1] = flags1::STATUS_LINE_TIME | flags1::SCREEN_SPLITTING;
+            bytes[FLAGS2 + 1] = (flags2::TRANSCRIPTING | flags2::UNDO) as u8;
+        });
+        let config = InterpreterConfig::default();
+        config
+            .apply(&mut memory, number_to_version(3).unwrap())
+            .unwrap();
+
+        let header = Header::new(&memory);
+        assert_eq!(
+            flags1::STATUS_LINE_TIME | flags1::STATUS_LINE_UNAVAILABLE,
+            header.flags1().unwrap()
+        );
+        // Bit 4 only asks for undo from V5 on.
+        assert_eq!(
+            flags2::TRANSCRIPTING | flags2::UNDO,
+            header.flags2().unwrap()
+        );
+        // V1-3 have no interpreter number or screen size.
+        assert_eq!(0, memory.read_byte(INTERPRETER_NUMBER.into()).unwrap());
+        assert_eq!(0, memory.read_byte(SCREEN_WIDTH_CHARS.into()).unwrap());
+        assert_eq!(1, memory.read_byte(STANDARD_REVISION.into()).unwrap());
+        assert_eq!(1, memory.read_byte((STANDARD_REVISION + 1).into()).unwrap());
+    }
+
+    #[test]
+    fn test_apply_v5() {
+        let mut memory = fake_memory_with(5, 0x100, 0x80, |bytes| {
+            bytes[FLAGS1] = 0xff;
+            bytes[FLAGS2 + 1] = (flags2::UNDO | flags2::MOUSE | flags2::COLOURS) as u8;
+        });
+        let config = InterpreterConfig {
+            capabilities: Capabilities {
+                colours: true,
+                undo: true,
+                ..Capabilities::default()
+            },
+            interpreter_number: interpreter_number::AMIGA,
+            interpreter_version: b'C',
+            screen_height_lines: 24,
+            screen_width_chars: 64,
+            font_width_units: 2,
+            font_height_units: 3,
+            ..InterpreterConfig::default()
+        };
+        config
+            .apply(&mut memory, number_to_version(5).unwrap())
+            .unwrap();
+
+        let header = Header::new(&memory);
+        assert_eq!(
+            flags1::COLOURS | flags1::TIMED_INPUT,
+            header.flags1().unwrap()
+        );
+        assert_eq!(flags2::UNDO | flags2::COLOURS, header.flags2().unwrap());
+        assert_eq!(
+            interpreter_number::AMIGA,
+            memory.read_byte(INTERPRETER_NUMBER.into()).unwrap()
+        );
+        assert_eq!(b'C', memory.read_byte(INTERPRETER_VERSION.into()).unwrap());
+        assert_eq!(24, memory.read_byte(SCREEN_HEIGHT_LINES.into()).unwrap());
+        assert_eq!(64, memory.read_byte(SCREEN_WIDTH_CHARS.into()).unwrap());
+        assert_eq!(128, memory.read_word(SCREEN_WIDTH_UNITS).unwrap());
+        assert_eq!(72, memory.read_word(SCREEN_HEIGHT_UNITS).unwrap());
+        assert_eq!(2, memory.read_byte(FONT_WIDTH_UNITS.into()).unwrap());
+        assert_eq!(3, memory.read_byte(FONT_HEIGHT_UNITS.into()).unwrap());
+        assert_eq!(9, memory.read_byte(DEFAULT_BACKGROUND.into()).unwrap());
+        assert_eq!(2, memory.read_byte(DEFAULT_FOREGROUND.into()).unwrap());
+    }
+
+    #[test]
+    fn test_apply_v6_font() {
+        let mut memory = fake_memory_with(6, 0x100, 0x80, |_| {});
+        let config = InterpreterConfig {
+            font_width_units: 2,
+            font_height_units: 3,
+            ..InterpreterConfig::default()
+        };
+        config
+            .apply(&mut memory, number_to_version(6).unwrap())
+            .unwrap();
+        assert_eq!(3, memory.read_byte(FONT_WIDTH_UNITS.into()).unwrap());
+        assert_eq!(2, memory.read_byte(FONT_HEIGHT_UNITS.into()).unwrap());
+    }
+
+    #[test]
+    fn test_parse_interpreter_number() {
+        assert_eq!(4, parse_interpreter_number("4").unwrap());
+        assert_eq!(200, parse_interpreter_number("200").unwrap());
+        assert_eq!(
+            interpreter_number::AMIGA,
+            parse_interpreter_number("amiga").unwrap()
+        );
+        assert_eq!(
+            interpreter_number::IBM_PC,
+            parse_interpreter_number("IBM_PC").unwrap()
+        );
+        assert!(parse_interpreter_number("zx-spectrum").is_err());
+        assert!(parse_interpreter_number("256").is_err());
+    }
+}
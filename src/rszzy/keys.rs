@@ -0,0 +1,294 @@
+use crate::rszzy::text::ZSCII;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+use std::collections::VecDeque;
+
+/// ZSpec 3.8 - the input-only ZSCII codes.
+pub mod zscii_key {
+    pub const DELETE: u16 = 8;
+    pub const NEWLINE: u16 = 13;
+    pub const ESCAPE: u16 = 27;
+    pub const CURSOR_UP: u16 = 129;
+    pub const CURSOR_DOWN: u16 = 130;
+    pub const CURSOR_LEFT: u16 = 131;
+    pub const CURSOR_RIGHT: u16 = 132;
+    /// F1-F12 are 133-144.
+    pub const F1: u16 = 133;
+    /// Keypad 0-9 are 145-154.
+    pub const KEYPAD_0: u16 = 145;
+}
+
+/// A key pressed by the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// A key which types a character.
+    Char(char),
+    /// ZSpec 3.8 - delete, newline, escape, or a cursor, function or keypad key.
+    Special(ZSCII),
+}
+
+fn special(code: u16) -> Key {
+    Key::Special(ZSCII::from(code))
+}
+
+/// The result of decoding bytes from a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decoded {
+    /// A key, and the number of bytes it took.
+    Key(Key, usize),
+    /// A complete escape sequence, of the given length, for a key the Z-machine
+    /// doesn't have.
+    Ignored(usize),
+    /// The bytes are the start of a key. If no more arrive, a lone escape is the
+    /// escape key.
+    Incomplete,
+}
+
+/// The byte which starts an escape sequence.
+pub const ESC: u8 = 0x1b;
+
+/// Decode the first key in `bytes`, as sent by an xterm-like terminal. Cursor keys
+/// and the keypad may come in either normal or application mode.
+pub fn decode_key(bytes: &[u8]) -> Decoded {
+    use zscii_key::*;
+
+    match bytes {
+        [] => Decoded::Incomplete,
+        [b'\r', ..] | [b'\n', ..] => Decoded::Key(special(NEWLINE), 1),
+        [0x7f, ..] | [0x08, ..] => Decoded::Key(special(DELETE), 1),
+        [ESC] => Decoded::Incomplete,
+        // SS3 - application cursor keys, F1-F4, and the application keypad.
+        [ESC, b'O'] => Decoded::Incomplete,
+        [ESC, b'O', last, ..] => match ss3_key(*last) {
+            Some(key) => Decoded::Key(key, 3),
+            None => Decoded::Ignored(3),
+        },
+        // CSI - parameters, then a final byte from 0x40 to 0x7e.
+        [ESC, b'[', rest @ ..] => match rest.iter().position(|b| (0x40..=0x7e).contains(b)) {
+            None => Decoded::Incomplete,
+            Some(end) => match csi_key(&rest[..end], rest[end]) {
+                Some(key) => Decoded::Key(key, end + 3),
+                None => Decoded::Ignored(end + 3),
+            },
+        },
+        [ESC, ..] => Decoded::Key(special(ESCAPE), 1),
+        _ => {
+            // UTF-8 is at most 4 bytes long.
+            let len = std::cmp::min(bytes.len(), 4);
+            let (valid, error) = match std::str::from_utf8(&bytes[..len]) {
+                Ok(text) => (text, None),
+                Err(err) => (
+                    // Safe, since the error says this much is valid.
+                    std::str::from_utf8(&bytes[..err.valid_up_to()]).unwrap(),
+                    Some(err),
+                ),
+            };
+            match valid.chars().next() {
+                Some(ch) => Decoded::Key(Key::Char(ch), ch.len_utf8()),
+                None => match error.and_then(|err| err.error_len()) {
+                    Some(bad) => Decoded::Ignored(bad),
+                    None => Decoded::Incomplete,
+                },
+            }
+        }
+    }
+}
+
+fn ss3_key(last: u8) -> Option<Key> {
+    use zscii_key::*;
+
+    let code = match last {
+        b'A' => CURSOR_UP,
+        b'B' => CURSOR_DOWN,
+        b'D' => CURSOR_LEFT,
+        b'C' => CURSOR_RIGHT,
+        b'P'..=b'S' => F1 + u16::from(last - b'P'),
+        b'p'..=b'y' => KEYPAD_0 + u16::from(last - b'p'),
+        b'M' => NEWLINE,
+        _ => return None,
+    };
+    Some(special(code))
+}
+
+fn csi_key(params: &[u8], last: u8) -> Option<Key> {
+    use zscii_key::*;
+
+    let code = match (params, last) {
+        (_, b'A') => CURSOR_UP,
+        (_, b'B') => CURSOR_DOWN,
+        (_, b'D') => CURSOR_LEFT,
+        (_, b'C') => CURSOR_RIGHT,
+        (b"3", b'~') => DELETE,
+        (b"11", b'~') => F1,
+        (b"12", b'~') => F1 + 1,
+        (b"13", b'~') => F1 + 2,
+        (b"14", b'~') => F1 + 3,
+        (b"15", b'~') => F1 + 4,
+        (b"17", b'~') => F1 + 5,
+        (b"18", b'~') => F1 + 6,
+        (b"19", b'~') => F1 + 7,
+        (b"20", b'~') => F1 + 8,
+        (b"21", b'~') => F1 + 9,
+        (b"23", b'~') => F1 + 10,
+        (b"24", b'~') => F1 + 11,
+        _ => return None,
+    };
+    Some(special(code))
+}
+
+/// What happens next in a script of input, for playing without a keyboard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScriptKey {
+    Key(Key),
+    /// The player waits until any timer runs out.
+    Wait,
+}
+
+/// The name of a key in a script, as in `{up}`.
+fn named_key(name: &str) -> Option<ScriptKey> {
+    use zscii_key::*;
+
+    let code = match name {
+        "wait" => return Some(ScriptKey::Wait),
+        "del" | "delete" | "backspace" => DELETE,
+        "enter" | "return" => NEWLINE,
+        "esc" | "escape" => ESCAPE,
+        "up" => CURSOR_UP,
+        "down" => CURSOR_DOWN,
+        "left" => CURSOR_LEFT,
+        "right" => CURSOR_RIGHT,
+        _ => {
+            let (prefix, first, count) = if name.starts_with("kp") {
+                ("kp", KEYPAD_0, 10)
+            } else if name.starts_with('f') {
+                ("f", F1 - 1, 13)
+            } else {
+                return None;
+            };
+            match name[prefix.len()..].parse::<u16>() {
+                Ok(n) if (prefix == "kp" || n > 0) && n < count => first + n,
+                _ => return None,
+            }
+        }
+    };
+    Some(ScriptKey::Key(special(code)))
+}
+
+/// Parse a script of input. Text is typed as it stands, and each line ends with
+/// enter. Other keys are named in braces: `{up}`, `{down}`, `{left}`, `{right}`,
+/// `{f1}`-`{f12}`, `{kp0}`-`{kp9}`, `{esc}`, `{del}` and `{enter}`. `{wait}` lets a
+/// timer run out, and `{{` types a brace.
+#[throws]
+pub fn parse_script(script: &str) -> VecDeque<ScriptKey> {
+    let mut keys = VecDeque::new();
+    let mut chars = script.chars().peekable();
+    while let Some(ch) = chars.next() {
+        let key = match ch {
+            '\r' => continue,
+            '\n' => ScriptKey::Key(special(zscii_key::NEWLINE)),
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                ScriptKey::Key(Key::Char('{'))
+            }
+            '{' => {
+                let name = chars
+                    .by_ref()
+                    .take_while(|ch| *ch != '}')
+                    .collect::<String>();
+                named_key(&name.to_lowercase())
+                    .ok_or_else(|| anyhow!("Unknown key in script: {{{}}}", name))?
+            }
+            _ => ScriptKey::Key(Key::Char(ch)),
+        };
+        keys.push_back(key);
+    }
+    keys
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use zscii_key::*;
+
+    fn key(bytes: &[u8]) -> Decoded {
+        decode_key(bytes)
+    }
+
+    #[test]
+    fn test_plain_keys() {
+        assert_eq!(Decoded::Key(Key::Char('a'), 1), key(b"abc"));
+        assert_eq!(Decoded::Key(special(NEWLINE), 1), key(b"\r"));
+        assert_eq!(Decoded::Key(special(NEWLINE), 1), key(b"\n"));
+        assert_eq!(Decoded::Key(special(DELETE), 1), key(b"\x7f"));
+        assert_eq!(Decoded::Key(special(DELETE), 1), key(b"\x08"));
+        assert_eq!(Decoded::Key(Key::Char('é'), 2), key("éa".as_bytes()));
+        // Part of a character, or an invalid one.
+        assert_eq!(Decoded::Incomplete, key(&"é".as_bytes()[..1]));
+        assert_eq!(Decoded::Ignored(1), key(b"\xff"));
+        assert_eq!(Decoded::Incomplete, key(b""));
+    }
+
+    #[test]
+    fn test_escape_sequences() {
+        let cases: &[(&[u8], u16)] = &[
+            (b"\x1b[A", CURSOR_UP),
+            (b"\x1b[B", CURSOR_DOWN),
+            (b"\x1b[D", CURSOR_LEFT),
+            (b"\x1bOC", CURSOR_RIGHT),
+            (b"\x1b[1;5A", CURSOR_UP),
+            (b"\x1bOP", F1),
+            (b"\x1bOS", F1 + 3),
+            (b"\x1b[11~", F1),
+            (b"\x1b[15~", F1 + 4),
+            (b"\x1b[24~", F1 + 11),
+            (b"\x1bOp", KEYPAD_0),
+            (b"\x1bOy", KEYPAD_0 + 9),
+            (b"\x1bOM", NEWLINE),
+            (b"\x1b[3~", DELETE),
+        ];
+        for (bytes, code) in cases {
+            assert_eq!(
+                Decoded::Key(special(*code), bytes.len()),
+                key(bytes),
+                "{:?}",
+                bytes
+            );
+        }
+
+        // Keys the Z-machine doesn't have, such as page up.
+        assert_eq!(Decoded::Ignored(4), key(b"\x1b[5~x"));
+        assert_eq!(Decoded::Ignored(3), key(b"\x1bOZ"));
+
+        // Escape on its own, or followed by something other than a sequence.
+        assert_eq!(Decoded::Incomplete, key(b"\x1b"));
+        assert_eq!(Decoded::Incomplete, key(b"\x1b["));
+        assert_eq!(Decoded::Incomplete, key(b"\x1b[1"));
+        assert_eq!(Decoded::Key(special(ESCAPE), 1), key(b"\x1bx"));
+    }
+
+    #[test]
+    fn test_parse_script() {
+        let keys = parse_script("go {{n}\r\n{UP}{f12}{kp9}{wait}").unwrap();
+        assert_eq!(
+            vec![
+                ScriptKey::Key(Key::Char('g')),
+                ScriptKey::Key(Key::Char('o')),
+                ScriptKey::Key(Key::Char(' ')),
+                ScriptKey::Key(Key::Char('{')),
+                ScriptKey::Key(Key::Char('n')),
+                ScriptKey::Key(Key::Char('}')),
+                ScriptKey::Key(special(NEWLINE)),
+                ScriptKey::Key(special(CURSOR_UP)),
+                ScriptKey::Key(special(F1 + 11)),
+                ScriptKey::Key(special(KEYPAD_0 + 9)),
+                ScriptKey::Wait,
+            ],
+            keys.into_iter().collect::<Vec<_>>()
+        );
+
+        assert!(parse_script("{f0}").is_err());
+        assert!(parse_script("{f13}").is_err());
+        assert!(parse_script("{kp10}").is_err());
+        assert!(parse_script("{home}").is_err());
+    }
+}
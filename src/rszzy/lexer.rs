@@ -0,0 +1,278 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::text::ZSCII;
+use crate::rszzy::traits::{Dictionary, Memory};
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+
+const SPACE: u16 = 32;
+
+/// A word found by lexical analysis, as a range of the text it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Word {
+    pub start: usize,
+    pub len: usize,
+}
+
+/// ZSpec 15 (read) - the text in a text buffer, and the position of its first
+/// character in the buffer. Byte 0 holds the size of the buffer. In V1-4 the text
+/// follows, ending with a zero; in V5+ byte 1 holds its length, and it follows that.
+#[throws]
+pub fn buffer_text(
+    memory: &impl Memory,
+    version: &Version,
+    buffer: ZOffset,
+) -> (Vec<ZSCII>, usize) {
+    let read = |idx: usize| -> Result<ZSCII, Error> {
+        Ok(ZSCII::from(u16::from(memory.read_byte(buffer + idx)?)))
+    };
+
+    let mut text = vec![];
+    if version.text_buffer_length {
+        let len = usize::from(memory.read_byte(buffer + 1)?);
+        for idx in 0..len {
+            text.push(read(2 + idx)?);
+        }
+        (text, 2)
+    } else {
+        let max = usize::from(memory.read_byte(buffer)?);
+        for idx in 0..max {
+            let zscii = read(1 + idx)?;
+            if zscii.code() == 0 {
+                break;
+            }
+            text.push(zscii);
+        }
+        (text, 1)
+    }
+}
+
+/// ZSpec 13.6.1 - split `text` into words. Spaces separate words and are thrown
+/// away; each separator is a word of its own.
+pub fn split_words(text: &[ZSCII], separators: &[ZSCII]) -> Vec<Word> {
+    let mut words = vec![];
+    let mut start = None;
+    for (idx, zscii) in text.iter().enumerate() {
+        let is_separator = separators.contains(zscii);
+        if zscii.code() == SPACE || is_separator {
+            if let Some(start) = start.take() {
+                words.push(Word {
+                    start,
+                    len: idx - start,
+                });
+            }
+            if is_separator {
+                words.push(Word { start: idx, len: 1 });
+            }
+        } else if start.is_none() {
+            start = Some(idx);
+        }
+    }
+    if let Some(start) = start {
+        words.push(Word {
+            start,
+            len: text.len() - start,
+        });
+    }
+    words
+}
+
+/// ZSpec 13.6 - the lexical analysis done by read and @tokenise. Each word of the
+/// text buffer is looked up in `dictionary`, and described in the parse buffer.
+///
+/// ZSpec 15 (read) - byte 0 of the parse buffer holds the most words it has room
+/// for. Byte 1 gets the number of words, and is followed by 4 bytes for each word:
+/// its dictionary address (or 0 if it isn't there), its length, and its position
+/// in the text buffer. If `keep_unknown` is set, the 4 bytes for words which aren't
+/// in the dictionary are left as they were.
+#[throws]
+pub fn tokenise(
+    memory: &mut impl Memory,
+    version: &Version,
+    dictionary: &impl Dictionary,
+    text_buffer: ZOffset,
+    parse_buffer: ZOffset,
+    keep_unknown: bool,
+) {
+    let (text, text_start) = buffer_text(memory, version, text_buffer)?;
+    let max_words = usize::from(memory.read_byte(parse_buffer)?);
+
+    let words = split_words(&text, dictionary.separators());
+    let count = std::cmp::min(words.len(), max_words);
+    memory.write_byte(parse_buffer + 1, count as u8)?;
+
+    for (idx, word) in words.iter().take(count).enumerate() {
+        let text = &text[word.start..word.start + word.len];
+        let entry = dictionary.find_word(memory, version, text)?;
+        if entry.is_none() && keep_unknown {
+            continue;
+        }
+
+        let address = match entry {
+            Some(entry) => usize::from(entry),
+            None => 0,
+        };
+        let position = text_start + word.start;
+        ensure!(
+            position <= 0xff,
+            anyhow!(
+                "Word at position {} is too far into the text buffer",
+                position
+            )
+        );
+
+        let block = parse_buffer + 2 + idx * 4;
+        memory.write_word(block, address as u16)?;
+        memory.write_byte(block + 2, word.len as u8)?;
+        memory.write_byte(block + 3, position as u8)?;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::dictionary::test::dictionary_bytes;
+    use crate::rszzy::dictionary::ZDictionary;
+    use crate::rszzy::memory::test::fake_memory_with;
+    use crate::rszzy::memory::ZMemory;
+    use crate::rszzy::text::test::zscii;
+    use crate::rszzy::versions::number_to_version;
+
+    const DICT: usize = 0x200;
+    const TEXT: usize = 0x80;
+    const PARSE: usize = 0xc0;
+
+    // A story whose dictionary has the separators ".," and the words "go",
+    // "north" and ",", sorted unless `unsorted`. Dynamic memory ends at 0x100.
+    fn story(version: u8, unsorted: bool) -> ZMemory {
+        let dict = dictionary_bytes(version, ".,", &["go", "north", ","], unsorted);
+        fake_memory_with(version, 0x400, 0x100, |bytes| {
+            bytes[DICT..DICT + dict.len()].copy_from_slice(&dict);
+        })
+    }
+
+    fn set_text(memory: &mut ZMemory, version: u8, text: &str) {
+        memory.write_byte(TEXT.into(), 40).unwrap();
+        let start = if number_to_version(version).unwrap().text_buffer_length {
+            memory
+                .write_byte((TEXT + 1).into(), text.len() as u8)
+                .unwrap();
+            TEXT + 2
+        } else {
+            memory
+                .write_byte((TEXT + 1 + text.len()).into(), 0)
+                .unwrap();
+            TEXT + 1
+        };
+        for (idx, byte) in text.bytes().enumerate() {
+            memory.write_byte((start + idx).into(), byte).unwrap();
+        }
+    }
+
+    // The parse buffer, as (address, length, position) for each word.
+    fn parsed(memory: &ZMemory) -> Vec<(u16, u8, u8)> {
+        let count = usize::from(memory.read_byte((PARSE + 1).into()).unwrap());
+        (0..count)
+            .map(|idx| {
+                let block = ZOffset::from(PARSE + 2 + idx * 4);
+                (
+                    memory.read_word(block).unwrap(),
+                    memory.read_byte(block + 2).unwrap(),
+                    memory.read_byte(block + 3).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn run(memory: &mut ZMemory, version: u8, max_words: u8, keep_unknown: bool) {
+        let v = number_to_version(version).unwrap();
+        let dict = ZDictionary::at(memory, v, DICT.into()).unwrap();
+        memory.write_byte(PARSE.into(), max_words).unwrap();
+        tokenise(memory, v, &dict, TEXT.into(), PARSE.into(), keep_unknown).unwrap();
+    }
+
+    fn address(memory: &ZMemory, version: u8, word: &str) -> u16 {
+        let v = number_to_version(version).unwrap();
+        let dict = ZDictionary::at(memory, v, DICT.into()).unwrap();
+        let entry = dict.find_word(memory, v, &zscii(word)).unwrap().unwrap();
+        usize::from(entry) as u16
+    }
+
+    #[test]
+    fn test_split_words() {
+        let words = |text: &str| {
+            split_words(&zscii(text), &zscii(".,"))
+                .iter()
+                .map(|word| (word.start, word.len))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(Vec::<(usize, usize)>::new(), words(""));
+        assert_eq!(Vec::<(usize, usize)>::new(), words("   "));
+        assert_eq!(vec![(0, 2)], words("go"));
+        assert_eq!(vec![(2, 2), (6, 5)], words("  go  north "));
+        assert_eq!(vec![(0, 4), (4, 1), (6, 2), (8, 1)], words("fred, go."));
+        assert_eq!(vec![(0, 1), (1, 1)], words(".."));
+    }
+
+    #[test]
+    fn test_buffer_text() {
+        let mut memory = story(3, false);
+        set_text(&mut memory, 3, "go north");
+        let v3 = number_to_version(3).unwrap();
+        let (text, start) = buffer_text(&memory, v3, TEXT.into()).unwrap();
+        assert_eq!(zscii("go north"), text);
+        assert_eq!(1, start);
+
+        let mut memory = story(5, false);
+        set_text(&mut memory, 5, "go north");
+        let v5 = number_to_version(5).unwrap();
+        let (text, start) = buffer_text(&memory, v5, TEXT.into()).unwrap();
+        assert_eq!(zscii("go north"), text);
+        assert_eq!(2, start);
+    }
+
+    #[test]
+    fn test_tokenise() {
+        for &(version, start) in &[(3, 1), (5, 2)] {
+            for unsorted in &[false, true] {
+                let mut memory = story(version, *unsorted);
+                set_text(&mut memory, version, "go north, xyzzy");
+                run(&mut memory, version, 10, false);
+                assert_eq!(
+                    vec![
+                        (address(&memory, version, "go"), 2, start),
+                        (address(&memory, version, "north"), 5, start + 3),
+                        (address(&memory, version, ","), 1, start + 8),
+                        (0, 5, start + 10),
+                    ],
+                    parsed(&memory)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_too_many_words() {
+        let mut memory = story(5, false);
+        set_text(&mut memory, 5, "go north go");
+        run(&mut memory, 5, 2, false);
+        assert_eq!(2, parsed(&memory).len());
+        // The block for the third word is untouched.
+        assert_eq!(0, memory.read_byte((PARSE + 2 + 2 * 4 + 2).into()).unwrap());
+    }
+
+    #[test]
+    fn test_keep_unknown() {
+        let mut memory = story(5, false);
+        set_text(&mut memory, 5, "xyzzy north");
+        for idx in 0..4 {
+            memory.write_byte((PARSE + 2 + idx).into(), 0xaa).unwrap();
+        }
+        run(&mut memory, 5, 10, true);
+        assert_eq!(
+            vec![(0xaaaa, 0xaa, 0xaa), (address(&memory, 5, "north"), 5, 8)],
+            parsed(&memory)
+        );
+    }
+}
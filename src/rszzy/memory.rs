@@ -1,7 +1,7 @@
 use crate::ensure;
 use crate::rszzy::addressing::ZOffset;
 use crate::rszzy::constants::header_offset::{
-    HIGH_MEMORY_MARK, STATIC_MEMORY_START, VERSION_NUMBER,
+    FILE_LENGTH, HEADER_SIZE, HIGH_MEMORY_MARK, STATIC_MEMORY_START, VERSION_NUMBER,
 };
 use crate::rszzy::traits::Memory;
 use crate::rszzy::versions::number_to_version;
@@ -10,6 +10,16 @@ use fehler::throws;
 use std::io::Read;
 use std::ops::Range;
 
+/// How strictly to enforce the rules that games are known to break.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Strictness {
+    /// Fail with an error.
+    Strict,
+    /// Log a warning and carry on as best we can.
+    #[default]
+    Lenient,
+}
+
 /// Concrete model of the ZMachine memory as defined in ZSpec 1.
 ///
 /// Manages
@@ -21,38 +31,55 @@ pub struct ZMemory {
 
     dynamic_range: Range<usize>,
     static_range: Range<usize>,
+
+    strictness: Strictness,
+    // Header bytes already warned about under Strictness::Lenient, one bit each.
+    header_warnings: u64,
+    // Warnings not yet taken by the processor.
+    warnings: Vec<String>,
 }
 
 mod bytes {
+    use anyhow::{anyhow, Error};
+    use fehler::throws;
+
     #[inline]
+    #[throws]
     pub fn byte_from_slice<I>(slice: &[u8], idx: I) -> u8
     where
         I: Into<usize> + Copy,
     {
-        slice[idx.into()]
+        *slice
+            .get(idx.into())
+            .ok_or_else(|| anyhow!("Reading past end of story: 0x{:x}", idx.into()))?
     }
 
     #[inline]
+    #[throws]
     pub fn byte_to_slice<I>(slice: &mut [u8], idx: I, val: u8)
     where
         I: Into<usize> + Copy,
     {
-        slice[idx.into()] = val;
+        *slice
+            .get_mut(idx.into())
+            .ok_or_else(|| anyhow!("Writing past end of story: 0x{:x}", idx.into()))? = val;
     }
 
     #[inline]
+    #[throws]
     pub fn word_from_slice<I>(slice: &[u8], idx: I) -> u16
     where
         I: Into<usize> + Copy,
     {
-        let high_byte = u16::from(byte_from_slice(slice, idx));
-        let low_byte = u16::from(byte_from_slice(slice, idx.into() + 1));
+        let high_byte = u16::from(byte_from_slice(slice, idx)?);
+        let low_byte = u16::from(byte_from_slice(slice, idx.into() + 1)?);
 
         (high_byte << 8) + low_byte
     }
 
     #[cfg(test)]
     #[inline]
+    #[throws]
     pub fn word_to_slice<I>(slice: &mut [u8], idx: I, val: u16)
     where
         I: Into<usize> + Copy,
@@ -61,8 +88,8 @@ mod bytes {
         let low_byte = (val & 0xff) as u8;
 
         // big-endian
-        byte_to_slice(slice, idx, high_byte);
-        byte_to_slice(slice, idx.into() + 1, low_byte);
+        byte_to_slice(slice, idx, high_byte)?;
+        byte_to_slice(slice, idx.into() + 1, low_byte)?;
     }
 }
 
@@ -75,7 +102,16 @@ impl ZMemory {
         let mut bytes = Vec::new();
         rdr.read_to_end(&mut bytes)?;
 
-        let version_number = bytes::byte_from_slice(&bytes, VERSION_NUMBER);
+        ensure!(
+            bytes.len() >= HEADER_SIZE,
+            anyhow!(
+                "Story is {} bytes, too short to contain a header of {} bytes",
+                bytes.len(),
+                HEADER_SIZE
+            )
+        );
+
+        let version_number = bytes::byte_from_slice(&bytes, VERSION_NUMBER)?;
         let version = number_to_version(version_number)?;
 
         ensure!(
@@ -87,13 +123,26 @@ impl ZMemory {
             )
         );
 
+        // ZSpec 11.1.6 - the file may be padded, but it must not be shorter than the
+        // length given in the header.
+        let header_length = usize::from(bytes::word_from_slice(&bytes, FILE_LENGTH)?)
+            * version.file_length_multiplier;
+        ensure!(
+            header_length <= bytes.len(),
+            anyhow!(
+                "Story is truncated. Header length: {}. Actual: {}.",
+                header_length,
+                bytes.len()
+            )
+        );
+
         // ZSpec 1.1
         // - definition of three regions (dynamic, static, high)
         // - dynamic memory must have at least 64 bytes
         // - dynamic memory cannot overlap high memory
-        let start_of_static = usize::from(bytes::word_from_slice(&bytes, STATIC_MEMORY_START));
+        let start_of_static = usize::from(bytes::word_from_slice(&bytes, STATIC_MEMORY_START)?);
         let end_of_static = std::cmp::min(0xffff, bytes.len());
-        let start_of_high = usize::from(bytes::word_from_slice(&bytes, HIGH_MEMORY_MARK));
+        let start_of_high = usize::from(bytes::word_from_slice(&bytes, HIGH_MEMORY_MARK)?);
 
         ensure!(
             start_of_static >= 64,
@@ -103,6 +152,15 @@ impl ZMemory {
             )
         );
 
+        ensure!(
+            start_of_static <= bytes.len(),
+            anyhow!(
+                "Dynamic memory ends at {}, past the end of the story at {}",
+                start_of_static,
+                bytes.len()
+            )
+        );
+
         ensure!(
             start_of_static < start_of_high,
             anyhow!(
@@ -116,20 +174,49 @@ impl ZMemory {
             bytes,
             dynamic_range: 0..start_of_static,
             static_range: start_of_static..end_of_static,
+            strictness: Strictness::default(),
+            header_warnings: 0,
+            warnings: Vec::new(),
         }
     }
+
+    pub fn set_strictness(&mut self, strictness: Strictness) {
+        self.strictness = strictness;
+    }
 }
 
 impl Memory for ZMemory {
     #[throws]
     fn slice_at(&self, idx: ZOffset) -> &[u8] {
-        &self.bytes.as_slice()[usize::from(idx)..]
+        self.bytes
+            .get(usize::from(idx)..)
+            .ok_or_else(|| anyhow!("Slice starts past end of memory: {}", idx))?
     }
 
     fn memory_size(&self) -> usize {
         self.bytes.len()
     }
 
+    fn strictness(&self) -> Strictness {
+        self.strictness
+    }
+
+    fn first_header_warning(&mut self, offset: ZOffset) -> bool {
+        // The header is 64 bytes, so each has a bit.
+        let bit = 1u64.checked_shl(usize::from(offset) as u32).unwrap_or(0);
+        let first = self.header_warnings & bit == 0;
+        self.header_warnings |= bit;
+        first
+    }
+
+    fn warn(&mut self, warning: String) {
+        self.warnings.push(warning);
+    }
+
+    fn take_warnings(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.warnings)
+    }
+
     fn in_dynamic_range(&self, idx: ZOffset) -> bool {
         self.dynamic_range.contains(&usize::from(idx))
     }
@@ -140,58 +227,129 @@ impl Memory for ZMemory {
 
     #[throws]
     fn read_byte_unchecked(&self, offset: ZOffset) -> u8 {
-        bytes::byte_from_slice(&self.bytes, offset)
+        bytes::byte_from_slice(&self.bytes, offset)?
     }
 
     #[throws]
     fn write_byte_unchecked(&mut self, offset: ZOffset, val: u8) {
-        bytes::byte_to_slice(&mut self.bytes, offset, val);
+        bytes::byte_to_slice(&mut self.bytes, offset, val)?;
     }
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
 
     const FAKE_STATIC_START: usize = 0x50;
     const FAKE_HIGH_START: usize = 0x200;
 
-    fn fake_memory(size: usize) -> ZMemory {
+    pub fn fake_memory(size: usize) -> ZMemory {
+        fake_memory_with(3, size, FAKE_STATIC_START, |_| {})
+    }
+
+    /// A fake story of the given version and size, with dynamic memory ending at
+    /// `static_start`. `init` may fill in the story's bytes before it is loaded.
+    pub fn fake_memory_with<F>(version: u8, size: usize, static_start: usize, init: F) -> ZMemory
+    where
+        F: FnOnce(&mut [u8]),
+    {
         let mut v = vec![0; size];
-        bytes::byte_to_slice(&mut v, 0usize, 3); // Version 3
-        bytes::word_to_slice(&mut v, STATIC_MEMORY_START, FAKE_STATIC_START as u16);
-        bytes::word_to_slice(&mut v, HIGH_MEMORY_MARK, FAKE_HIGH_START as u16);
+        bytes::byte_to_slice(&mut v, VERSION_NUMBER, version).unwrap();
+        bytes::word_to_slice(&mut v, STATIC_MEMORY_START, static_start as u16).unwrap();
+        bytes::word_to_slice(
+            &mut v,
+            HIGH_MEMORY_MARK,
+            std::cmp::max(FAKE_HIGH_START, static_start + 1) as u16,
+        )
+        .unwrap();
+        init(&mut v);
         ZMemory::from_reader(<&[u8]>::from(&v)).unwrap()
     }
 
+    /// Write `val` into a fake story's bytes, big-endian, at `at`.
+    pub fn put_word(bytes: &mut [u8], at: usize, val: u16) {
+        bytes::word_to_slice(bytes, at, val).unwrap();
+    }
+
     #[test]
     fn test_size() {
-        let m = fake_memory(52);
-        assert_eq!(52, m.memory_size());
+        let m = fake_memory(0x60);
+        assert_eq!(0x60, m.memory_size());
     }
 
     #[test]
     fn test_ranges() {
         let m = fake_memory(0x1000);
 
-        assert_eq!(true, m.in_dynamic_range(0.into()));
+        assert_eq!(true, m.in_dynamic_range(0usize.into()));
         assert_eq!(true, m.in_dynamic_range((FAKE_STATIC_START - 1).into()));
         assert_eq!(false, m.in_dynamic_range(FAKE_STATIC_START.into()));
 
         assert_eq!(false, m.in_static_range((FAKE_STATIC_START - 1).into()));
         assert_eq!(true, m.in_static_range(FAKE_STATIC_START.into()));
-        assert_eq!(true, m.in_static_range(0x1ff.into()));
-        assert_eq!(true, m.in_static_range(0x200.into()));
-        assert_eq!(true, m.in_static_range(0x0fff.into()));
-        assert_eq!(false, m.in_static_range(0x1000.into()));
+        assert_eq!(true, m.in_static_range(0x1ffusize.into()));
+        assert_eq!(true, m.in_static_range(0x200usize.into()));
+        assert_eq!(true, m.in_static_range(0x0fffusize.into()));
+        assert_eq!(false, m.in_static_range(0x1000usize.into()));
+    }
+
+    #[test]
+    fn test_bad_stories() {
+        let load = |bytes: &[u8]| ZMemory::from_reader(bytes).is_err();
+        assert!(load(&[]));
+        assert!(load(&[3; 0x20]));
+
+        let mut v = vec![0; 0x100];
+        bytes::byte_to_slice(&mut v, VERSION_NUMBER, 3).unwrap();
+        bytes::word_to_slice(&mut v, STATIC_MEMORY_START, 0x80).unwrap();
+        bytes::word_to_slice(&mut v, HIGH_MEMORY_MARK, 0x90).unwrap();
+        assert!(!load(&v));
+
+        // Truncated: the header says 0x102 bytes.
+        let mut truncated = v.clone();
+        bytes::word_to_slice(&mut truncated, FILE_LENGTH, 0x81).unwrap();
+        assert!(load(&truncated));
+        // Padded is fine.
+        bytes::word_to_slice(&mut truncated, FILE_LENGTH, 0x7f).unwrap();
+        assert!(!load(&truncated));
+
+        // Dynamic memory past the end of the file.
+        let mut short = v;
+        bytes::word_to_slice(&mut short, STATIC_MEMORY_START, 0x101).unwrap();
+        bytes::word_to_slice(&mut short, HIGH_MEMORY_MARK, 0x102).unwrap();
+        assert!(load(&short));
+    }
+
+    #[test]
+    fn test_strict_header() {
+        let mut m = fake_memory(0x1000);
+        assert_eq!(Strictness::Lenient, m.strictness());
+        assert!(m.write_byte(VERSION_NUMBER.into(), 5).is_ok());
+        assert_eq!(3, m.read_byte(VERSION_NUMBER.into()).unwrap());
+        assert_eq!(
+            vec!["Ignoring write to read-only header bits 0x06 at ZO:0x0".to_string()],
+            m.take_warnings()
+        );
+        assert!(m.take_warnings().is_empty());
+        // Each header byte is only warned about once.
+        assert!(m.write_byte(VERSION_NUMBER.into(), 4).is_ok());
+        assert!(m.take_warnings().is_empty());
+        assert!(!m.first_header_warning(VERSION_NUMBER.into()));
+        assert!(m.first_header_warning(0x11usize.into()));
+        assert!(!m.first_header_warning(0x11usize.into()));
+
+        m.set_strictness(Strictness::Strict);
+        assert!(m.write_byte(VERSION_NUMBER.into(), 5).is_err());
+        assert_eq!(3, m.read_byte(VERSION_NUMBER.into()).unwrap());
+        assert!(m.write_byte(0x11usize.into(), 1).is_ok());
     }
 
     #[test]
     fn test_read_write() {
         let mut m = fake_memory(0x1000);
 
-        assert_eq!(0, m.read_byte_unchecked(0x34.into()).unwrap());
-        assert!(m.write_byte_unchecked(0x34.into(), 87).is_ok());
-        assert_eq!(87, m.read_byte_unchecked(0x34.into()).unwrap());
+        assert_eq!(0, m.read_byte_unchecked(0x34usize.into()).unwrap());
+        assert!(m.write_byte_unchecked(0x34usize.into(), 87).is_ok());
+        assert_eq!(87, m.read_byte_unchecked(0x34usize.into()).unwrap());
     }
 }
@@ -0,0 +1,803 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::header::Header;
+use crate::rszzy::text::ZString;
+use crate::rszzy::traits::{AbbrevTable, Memory, ObjectTable};
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+
+/// ZSpec 12.3 - the shape of the object table, which differs between V1-3 and V4+.
+pub struct ObjectLayout {
+    pub max_attributes: u16,
+    pub max_objects: u16,
+    pub max_properties: u16,
+
+    // Size in bytes of each object entry.
+    entry_size: usize,
+    // Size in bytes of the parent/sibling/child links.
+    link_size: usize,
+    // Whether property size bytes use the V4+ encoding, which may take two bytes.
+    long_sizes: bool,
+}
+
+/// ZSpec 12.3.1 - V1-3: 32 attributes, byte links.
+pub const SMALL_LAYOUT: ObjectLayout = ObjectLayout {
+    max_attributes: 32,
+    max_objects: 255,
+    max_properties: 31,
+    entry_size: 9,
+    link_size: 1,
+    long_sizes: false,
+};
+
+/// ZSpec 12.3.2 - V4+: 48 attributes, word links.
+pub const LARGE_LAYOUT: ObjectLayout = ObjectLayout {
+    max_attributes: 48,
+    max_objects: 65535,
+    max_properties: 63,
+    entry_size: 14,
+    link_size: 2,
+    long_sizes: true,
+};
+
+impl ObjectLayout {
+    fn attribute_bytes(&self) -> usize {
+        usize::from(self.max_attributes / 8)
+    }
+
+    fn defaults_size(&self) -> usize {
+        2 * usize::from(self.max_properties)
+    }
+}
+
+/// One entry in an object's property list.
+struct PropertyEntry {
+    number: u16,
+    len: u16,
+    // The first byte of the property's data, just past the size byte(s).
+    data: ZOffset,
+}
+
+/// Concrete object table, located by the header.
+pub struct ZObjectTable {
+    // Start of the property defaults table.
+    base: ZOffset,
+    layout: &'static ObjectLayout,
+    version: &'static Version,
+
+    // The number of objects, inferred from the location of the lowest property table.
+    object_count: u16,
+}
+
+impl ZObjectTable {
+    #[throws]
+    pub fn new(memory: &impl Memory, version: &'static Version) -> ZObjectTable {
+        let base = ZOffset::from(Header::new(memory).object_table()?);
+        let layout = version.object_layout;
+
+        let mut table = ZObjectTable {
+            base,
+            layout,
+            version,
+            object_count: layout.max_objects,
+        };
+
+        // ZSpec 12.5 - there is no object count in the story file, but by convention, the
+        // property tables follow the last object entry. So the entries end where the
+        // lowest property table begins. Pointers into or before the entries can't mark
+        // their end, and are left for the object's own accessors to reject.
+        let entries_start = usize::from(table.entry_offset(1)?);
+        let mut lowest_props = memory.memory_size();
+        let mut count = 0;
+        while count < layout.max_objects {
+            let entries_end = entries_start + (usize::from(count) + 1) * layout.entry_size;
+            if entries_end > lowest_props {
+                break;
+            }
+            let props = match table.property_table(memory, count + 1) {
+                Ok(props) => usize::from(props),
+                Err(_) => break,
+            };
+            if props >= entries_end {
+                lowest_props = std::cmp::min(lowest_props, props);
+            }
+            count += 1;
+        }
+        table.object_count = count;
+
+        table
+    }
+
+    #[throws]
+    fn entry_offset(&self, obj: u16) -> ZOffset {
+        ensure!(
+            obj >= 1 && obj <= self.object_count,
+            anyhow!(
+                "Object {} is outside the object table, [1,{}]",
+                obj,
+                self.object_count
+            )
+        );
+        self.base + self.layout.defaults_size() + usize::from(obj - 1) * self.layout.entry_size
+    }
+
+    // `idx` is 0 for parent, 1 for sibling, 2 for child.
+    #[throws]
+    fn link_offset(&self, obj: u16, idx: usize) -> ZOffset {
+        self.entry_offset(obj)? + self.layout.attribute_bytes() + idx * self.layout.link_size
+    }
+
+    #[throws]
+    fn read_link(&self, memory: &impl Memory, obj: u16, idx: usize) -> u16 {
+        let offset = self.link_offset(obj, idx)?;
+        if self.layout.link_size == 1 {
+            u16::from(memory.read_byte(offset)?)
+        } else {
+            memory.read_word(offset)?
+        }
+    }
+
+    #[throws]
+    fn write_link(&self, memory: &mut impl Memory, obj: u16, idx: usize, val: u16) {
+        let offset = self.link_offset(obj, idx)?;
+        if self.layout.link_size == 1 {
+            ensure!(
+                val <= self.layout.max_objects,
+                anyhow!("Object {} does not fit in a byte link", val)
+            );
+            memory.write_byte(offset, val as u8)?;
+        } else {
+            memory.write_word(offset, val)?;
+        }
+    }
+
+    #[throws]
+    fn attribute_location(&self, obj: u16, attr: u16) -> (ZOffset, u8) {
+        ensure!(
+            attr < self.layout.max_attributes,
+            anyhow!(
+                "Attribute {} is outside legal range, [0,{})",
+                attr,
+                self.layout.max_attributes
+            )
+        );
+        // ZSpec 12.3.1 - attribute 0 is the high bit of the first byte.
+        let offset = self.entry_offset(obj)? + usize::from(attr / 8);
+        let mask = 0x80 >> (attr % 8);
+        (offset, mask)
+    }
+
+    /// ZSpec 12.4.1, 12.4.2 - decode the size byte(s) at `offset`.
+    /// Returns None at the size byte of 0 which ends the list.
+    #[throws]
+    fn property_entry(&self, memory: &impl Memory, offset: ZOffset) -> Option<PropertyEntry> {
+        let size = memory.read_byte(offset)?;
+        if size == 0 {
+            return None;
+        }
+
+        let entry = if !self.layout.long_sizes {
+            // ZSpec 12.4.1 - 32 times the length minus 1, plus the property number.
+            PropertyEntry {
+                number: u16::from(size & 0x1f),
+                len: u16::from(size >> 5) + 1,
+                data: offset + 1,
+            }
+        } else if size & 0x80 != 0 {
+            // ZSpec 12.4.2.1 - the length is in the second byte, where 0 means 64.
+            let len = memory.read_byte(offset + 1)? & 0x3f;
+            PropertyEntry {
+                number: u16::from(size & 0x3f),
+                len: if len == 0 { 64 } else { u16::from(len) },
+                data: offset + 2,
+            }
+        } else {
+            // ZSpec 12.4.2.2 - bit 6 chooses between 1 and 2 bytes of data.
+            PropertyEntry {
+                number: u16::from(size & 0x3f),
+                len: if size & 0x40 != 0 { 2 } else { 1 },
+                data: offset + 1,
+            }
+        };
+        Some(entry)
+    }
+
+    /// The property list of `obj`, in the order stored (descending property number).
+    #[throws]
+    fn properties(&self, memory: &impl Memory, obj: u16) -> Vec<PropertyEntry> {
+        // ZSpec 12.4 - the list follows the short name.
+        let props = self.property_table(memory, obj)?;
+        let mut offset = props + 1 + 2 * usize::from(memory.read_byte(props)?);
+
+        let mut entries = Vec::new();
+        while let Some(entry) = self.property_entry(memory, offset)? {
+            // Each property appears at most once, so a longer list must be corrupt.
+            ensure!(
+                entries.len() < usize::from(self.layout.max_properties),
+                anyhow!(
+                    "Property list of object {} at {} is not terminated",
+                    obj,
+                    props
+                )
+            );
+            offset = entry.data + usize::from(entry.len);
+            entries.push(entry);
+        }
+        entries
+    }
+
+    #[throws]
+    fn check_property(&self, prop: u16) {
+        ensure!(
+            prop >= 1 && prop <= self.layout.max_properties,
+            anyhow!(
+                "Property {} is outside legal range, [1,{}]",
+                prop,
+                self.layout.max_properties
+            )
+        );
+    }
+}
+
+impl ObjectTable for ZObjectTable {
+    #[throws]
+    fn parent(&self, memory: &impl Memory, obj: u16) -> u16 {
+        self.read_link(memory, obj, 0)?
+    }
+
+    #[throws]
+    fn sibling(&self, memory: &impl Memory, obj: u16) -> u16 {
+        self.read_link(memory, obj, 1)?
+    }
+
+    #[throws]
+    fn child(&self, memory: &impl Memory, obj: u16) -> u16 {
+        self.read_link(memory, obj, 2)?
+    }
+
+    #[throws]
+    fn set_parent(&self, memory: &mut impl Memory, obj: u16, val: u16) {
+        self.write_link(memory, obj, 0, val)?;
+    }
+
+    #[throws]
+    fn set_sibling(&self, memory: &mut impl Memory, obj: u16, val: u16) {
+        self.write_link(memory, obj, 1, val)?;
+    }
+
+    #[throws]
+    fn set_child(&self, memory: &mut impl Memory, obj: u16, val: u16) {
+        self.write_link(memory, obj, 2, val)?;
+    }
+
+    #[throws]
+    fn attribute(&self, memory: &impl Memory, obj: u16, attr: u16) -> bool {
+        let (offset, mask) = self.attribute_location(obj, attr)?;
+        memory.read_byte(offset)? & mask != 0
+    }
+
+    #[throws]
+    fn set_attribute(&self, memory: &mut impl Memory, obj: u16, attr: u16, val: bool) {
+        let (offset, mask) = self.attribute_location(obj, attr)?;
+        let byte = memory.read_byte(offset)?;
+        let byte = if val { byte | mask } else { byte & !mask };
+        memory.write_byte(offset, byte)?;
+    }
+
+    #[throws]
+    fn default_property(&self, memory: &impl Memory, prop: u16) -> u16 {
+        self.check_property(prop)?;
+        memory.read_word(self.base + 2 * usize::from(prop - 1))?
+    }
+
+    #[throws]
+    fn property_table(&self, memory: &impl Memory, obj: u16) -> ZOffset {
+        let offset = self.link_offset(obj, 3)?;
+        ZOffset::from(memory.read_word(offset)?)
+    }
+
+    #[throws]
+    fn short_name<'a, M, A>(&self, memory: &'a M, abbrevs: &'a A, obj: u16) -> ZString<'a, M, A>
+    where
+        M: Memory,
+        A: AbbrevTable,
+    {
+        let props = self.property_table(memory, obj)?;
+        // ZSpec 12.4 - a length byte, counting words, followed by the encoded name.
+        let len = 2 * usize::from(memory.read_byte(props)?);
+        let start = props + 1;
+        ensure!(
+            usize::from(start) + len <= memory.memory_size(),
+            anyhow!(
+                "Short name of object {} at {} runs past end of memory",
+                obj,
+                start
+            )
+        );
+        ZString::new(
+            memory,
+            self.version,
+            abbrevs,
+            &memory.slice_at(start)?[..len],
+        )?
+    }
+
+    #[throws]
+    fn property_data(&self, memory: &impl Memory, obj: u16, prop: u16) -> Option<(ZOffset, u16)> {
+        self.check_property(prop)?;
+        self.properties(memory, obj)?
+            .into_iter()
+            .find(|entry| entry.number == prop)
+            .map(|entry| (entry.data, entry.len))
+    }
+
+    #[throws]
+    fn property_length(&self, memory: &impl Memory, addr: ZOffset) -> u16 {
+        if usize::from(addr) == 0 {
+            return 0;
+        }
+        // The byte before the data is either the only size byte, or the second of two.
+        // In V4+ the second size byte always has its top bit set. (ZSpec 12.4.2.1)
+        let size = memory.read_byte(ZOffset::from(usize::from(addr) - 1))?;
+        if !self.layout.long_sizes {
+            u16::from(size >> 5) + 1
+        } else if size & 0x80 != 0 {
+            match size & 0x3f {
+                0 => 64,
+                len => u16::from(len),
+            }
+        } else if size & 0x40 != 0 {
+            2
+        } else {
+            1
+        }
+    }
+
+    #[throws]
+    fn next_property(&self, memory: &impl Memory, obj: u16, prop: u16) -> u16 {
+        let entries = self.properties(memory, obj)?;
+        let next = if prop == 0 {
+            entries.first()
+        } else {
+            let idx = entries
+                .iter()
+                .position(|entry| entry.number == prop)
+                .ok_or_else(|| anyhow!("Object {} does not have property {}", obj, prop))?;
+            entries.get(idx + 1)
+        };
+        next.map_or(0, |entry| entry.number)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::abbrevs::ZAbbrevTable;
+    use crate::rszzy::constants::header_offset::OBJECT_TABLE_START;
+    use crate::rszzy::memory::test::{fake_memory_with, put_word};
+    use crate::rszzy::memory::ZMemory;
+    use crate::rszzy::traits::test::TestMemory;
+    use crate::rszzy::versions::number_to_version;
+    use std::convert::TryFrom;
+
+    const TABLE: usize = 0x100;
+
+    // Three objects in a V3 table: 1 is the parent of 2 and 3.
+    // Property tables start at 0x200, with object 2 named "abc".
+    fn v3_memory() -> TestMemory {
+        let mut bytes = vec![0; 0x400];
+        bytes[0] = 3;
+        put_word(&mut bytes, OBJECT_TABLE_START, TABLE as u16);
+
+        // Defaults: property n has value 0x100 + n.
+        for prop in 1..=31 {
+            put_word(&mut bytes, TABLE + 2 * (prop - 1), 0x100 + prop as u16);
+        }
+
+        let entries = TABLE + 62;
+        let entry = |obj: usize| entries + (obj - 1) * 9;
+
+        // Object 1: attributes 0 and 31, child 2.
+        bytes[entry(1)] = 0x80;
+        bytes[entry(1) + 3] = 0x01;
+        bytes[entry(1) + 6] = 2;
+        put_word(&mut bytes, entry(1) + 7, 0x200);
+
+        // Object 2: attribute 9, parent 1, sibling 3.
+        bytes[entry(2) + 1] = 0x40;
+        bytes[entry(2) + 4] = 1;
+        bytes[entry(2) + 5] = 3;
+        put_word(&mut bytes, entry(2) + 7, 0x210);
+
+        // Object 3: parent 1.
+        bytes[entry(3) + 4] = 1;
+        put_word(&mut bytes, entry(3) + 7, 0x220);
+
+        // Property tables. Only object 2 has a name.
+        bytes[0x210] = 1;
+        bytes[0x211] = 0b1001_1000;
+        bytes[0x212] = 0b1110_1000;
+
+        TestMemory::new(bytes, 0x300, 0x300)
+    }
+
+    // Two objects in a V5 table, with word links.
+    fn v5_memory() -> TestMemory {
+        let mut bytes = vec![0; 0x400];
+        bytes[0] = 5;
+        put_word(&mut bytes, OBJECT_TABLE_START, TABLE as u16);
+
+        let entries = TABLE + 126;
+        let entry = |obj: usize| entries + (obj - 1) * 14;
+
+        // Object 1: attribute 47, child 2.
+        bytes[entry(1) + 5] = 0x01;
+        put_word(&mut bytes, entry(1) + 10, 2);
+        put_word(&mut bytes, entry(1) + 12, entries as u16 + 28);
+
+        // Object 2: attribute 40, parent 1.
+        bytes[entry(2) + 5] = 0x80;
+        put_word(&mut bytes, entry(2) + 6, 1);
+        put_word(&mut bytes, entry(2) + 12, entries as u16 + 28);
+
+        TestMemory::new(bytes, 0x300, 0x300)
+    }
+
+    #[test]
+    fn test_v3_tree() {
+        let m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+        // Entries start at 0x13e and property tables at 0x200, which leaves room for 21 objects.
+        assert_eq!(21, table.object_count);
+
+        assert_eq!(0, table.parent(&m, 1).unwrap());
+        assert_eq!(0, table.sibling(&m, 1).unwrap());
+        assert_eq!(2, table.child(&m, 1).unwrap());
+
+        assert_eq!(1, table.parent(&m, 2).unwrap());
+        assert_eq!(3, table.sibling(&m, 2).unwrap());
+        assert_eq!(0, table.child(&m, 2).unwrap());
+
+        assert_eq!(1, table.parent(&m, 3).unwrap());
+        assert_eq!(0, table.sibling(&m, 3).unwrap());
+    }
+
+    #[test]
+    fn test_v3_attributes() {
+        let m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        assert!(table.attribute(&m, 1, 0).unwrap());
+        assert!(!table.attribute(&m, 1, 1).unwrap());
+        assert!(table.attribute(&m, 1, 31).unwrap());
+        assert!(table.attribute(&m, 2, 9).unwrap());
+        assert!(!table.attribute(&m, 2, 8).unwrap());
+
+        assert!(table.attribute(&m, 1, 32).is_err());
+    }
+
+    #[test]
+    fn test_set_attributes() {
+        let mut m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        table.set_attribute(&mut m, 3, 17, true).unwrap();
+        assert!(table.attribute(&m, 3, 17).unwrap());
+        assert!(!table.attribute(&m, 3, 16).unwrap());
+        assert!(!table.attribute(&m, 3, 18).unwrap());
+
+        table.set_attribute(&mut m, 1, 0, false).unwrap();
+        assert!(!table.attribute(&m, 1, 0).unwrap());
+        assert!(table.attribute(&m, 1, 31).unwrap());
+    }
+
+    // Children of obj, in order.
+    fn children(table: &ZObjectTable, m: &TestMemory, obj: u16) -> Vec<u16> {
+        let mut result = vec![];
+        let mut child = table.child(m, obj).unwrap();
+        while child != 0 {
+            assert_eq!(obj, table.parent(m, child).unwrap());
+            result.push(child);
+            child = table.sibling(m, child).unwrap();
+        }
+        result
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        // Remove the last child.
+        table.remove(&mut m, 3).unwrap();
+        assert_eq!(vec![2], children(&table, &m, 1));
+        assert_eq!(0, table.parent(&m, 3).unwrap());
+        assert_eq!(0, table.sibling(&m, 3).unwrap());
+
+        // Removing an object without a parent does nothing.
+        table.remove(&mut m, 3).unwrap();
+
+        // Remove the first child.
+        let mut m = v3_memory();
+        table.remove(&mut m, 2).unwrap();
+        assert_eq!(vec![3], children(&table, &m, 1));
+        assert_eq!(0, table.parent(&m, 2).unwrap());
+        assert_eq!(0, table.sibling(&m, 2).unwrap());
+    }
+
+    #[test]
+    fn test_insert() {
+        let mut m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        // Move 3 under 2, then back under 1.
+        table.insert(&mut m, 3, 2).unwrap();
+        assert_eq!(vec![2], children(&table, &m, 1));
+        assert_eq!(vec![3], children(&table, &m, 2));
+
+        table.insert(&mut m, 3, 1).unwrap();
+        assert_eq!(vec![3, 2], children(&table, &m, 1));
+        assert!(children(&table, &m, 2).is_empty());
+
+        // Inserting the first child into its own parent leaves it first.
+        table.insert(&mut m, 3, 1).unwrap();
+        assert_eq!(vec![3, 2], children(&table, &m, 1));
+
+        // Moving the last child to the front.
+        table.insert(&mut m, 2, 1).unwrap();
+        assert_eq!(vec![2, 3], children(&table, &m, 1));
+    }
+
+    #[test]
+    fn test_corrupt_tree() {
+        let mut m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        // Object 3 claims 1 as its parent, but is unreachable from it.
+        table.set_sibling(&mut m, 2, 0).unwrap();
+        assert!(table.remove(&mut m, 3).is_err());
+
+        // A cycle among the siblings.
+        let mut m = v3_memory();
+        table.set_sibling(&mut m, 3, 2).unwrap();
+        table.set_parent(&mut m, 4, 1).unwrap();
+        assert!(table.remove(&mut m, 4).is_err());
+    }
+
+    #[test]
+    fn test_v3_properties() {
+        let m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        assert_eq!(0x101, table.default_property(&m, 1).unwrap());
+        assert_eq!(0x11f, table.default_property(&m, 31).unwrap());
+        assert!(table.default_property(&m, 0).is_err());
+        assert!(table.default_property(&m, 32).is_err());
+
+        assert_eq!(0x210, usize::from(table.property_table(&m, 2).unwrap()));
+    }
+
+    #[test]
+    fn test_short_name() {
+        let m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        let abbrevs = ZAbbrevTable::new(&m).unwrap();
+
+        let name = table.short_name(&m, &abbrevs, 2).unwrap();
+        assert_eq!("abc", String::try_from(name).unwrap());
+        let name = table.short_name(&m, &abbrevs, 1).unwrap();
+        assert_eq!("", String::try_from(name).unwrap());
+    }
+
+    #[test]
+    fn test_v5_layout() {
+        let m = v5_memory();
+        let table = ZObjectTable::new(&m, number_to_version(5).unwrap()).unwrap();
+        assert_eq!(2, table.object_count);
+
+        assert_eq!(2, table.child(&m, 1).unwrap());
+        assert_eq!(1, table.parent(&m, 2).unwrap());
+        assert_eq!(0, table.sibling(&m, 2).unwrap());
+
+        assert!(table.attribute(&m, 1, 47).unwrap());
+        assert!(table.attribute(&m, 2, 40).unwrap());
+        assert!(!table.attribute(&m, 2, 41).unwrap());
+        assert!(table.attribute(&m, 1, 48).is_err());
+
+        assert!(table.default_property(&m, 63).is_ok());
+        assert!(table.default_property(&m, 64).is_err());
+    }
+
+    // Two V3 objects. Object 1 has properties 18 (2 bytes), 7 (1 byte) and 3 (4 bytes).
+    // Object 2 has none.
+    fn v3_properties() -> (ZMemory, ZObjectTable) {
+        let m = fake_memory_with(3, 0x400, 0x300, |bytes| {
+            put_word(bytes, OBJECT_TABLE_START, TABLE as u16);
+            for prop in 1..=31 {
+                put_word(bytes, TABLE + 2 * (prop - 1), 0x100 + prop as u16);
+            }
+            let entries = TABLE + 62;
+            put_word(bytes, entries + 7, 0x200);
+            put_word(bytes, entries + 9 + 7, 0x210);
+
+            bytes[0x200..0x20d]
+                .copy_from_slice(&[0, 0x32, 0x12, 0x34, 0x07, 0x56, 0x63, 1, 2, 3, 4, 0, 0]);
+        });
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+        (m, table)
+    }
+
+    // One V5 object with properties 40 (64 bytes), 20 (3 bytes), 10 (2 bytes) and 5 (1 byte).
+    fn v5_properties() -> (ZMemory, ZObjectTable) {
+        let m = fake_memory_with(5, 0x400, 0x300, |bytes| {
+            put_word(bytes, OBJECT_TABLE_START, TABLE as u16);
+            let entries = TABLE + 126;
+            put_word(bytes, entries + 12, entries as u16 + 14);
+
+            let props = entries + 14;
+            // Name, then the size bytes for property 40, whose length of 0 means 64.
+            bytes[props..props + 3].copy_from_slice(&[0, 0xa8, 0x80]);
+            let rest = props + 3 + 64;
+            bytes[rest..rest + 11]
+                .copy_from_slice(&[0x94, 0x83, 1, 2, 3, 0x4a, 0xab, 0xcd, 0x05, 0x77, 0]);
+        });
+        let table = ZObjectTable::new(&m, number_to_version(5).unwrap()).unwrap();
+        (m, table)
+    }
+
+    #[test]
+    fn test_v3_property_list() {
+        let (m, table) = v3_properties();
+
+        assert_eq!(
+            Some((0x202, 2)),
+            table
+                .property_data(&m, 1, 18)
+                .unwrap()
+                .map(|(addr, len)| (usize::from(addr), len))
+        );
+        assert!(table.property_data(&m, 1, 5).unwrap().is_none());
+        assert!(table.property_data(&m, 2, 18).unwrap().is_none());
+        assert!(table.property_data(&m, 1, 0).is_err());
+        assert!(table.property_data(&m, 1, 32).is_err());
+
+        assert_eq!(
+            2,
+            table
+                .property_length(&m, ZOffset::from(0x202usize))
+                .unwrap()
+        );
+        assert_eq!(
+            1,
+            table
+                .property_length(&m, ZOffset::from(0x205usize))
+                .unwrap()
+        );
+        assert_eq!(
+            4,
+            table
+                .property_length(&m, ZOffset::from(0x207usize))
+                .unwrap()
+        );
+        assert_eq!(0, table.property_length(&m, ZOffset::from(0usize)).unwrap());
+
+        assert_eq!(18, table.next_property(&m, 1, 0).unwrap());
+        assert_eq!(7, table.next_property(&m, 1, 18).unwrap());
+        assert_eq!(3, table.next_property(&m, 1, 7).unwrap());
+        assert_eq!(0, table.next_property(&m, 1, 3).unwrap());
+        assert!(table.next_property(&m, 1, 5).is_err());
+        assert_eq!(0, table.next_property(&m, 2, 0).unwrap());
+    }
+
+    #[test]
+    fn test_get_property() {
+        let (m, table) = v3_properties();
+
+        assert_eq!(0x1234, table.property(&m, 1, 18).unwrap());
+        assert_eq!(0x56, table.property(&m, 1, 7).unwrap());
+        // Missing properties come from the defaults table.
+        assert_eq!(0x105, table.property(&m, 1, 5).unwrap());
+        assert_eq!(0x112, table.property(&m, 2, 18).unwrap());
+        // Too long to read.
+        assert!(table.property(&m, 1, 3).is_err());
+        assert!(table.property(&m, 0, 3).is_err());
+    }
+
+    #[test]
+    fn test_put_property() {
+        let (mut m, table) = v3_properties();
+
+        table.set_property(&mut m, 1, 18, 0xbeef).unwrap();
+        assert_eq!(0xbeef, table.property(&m, 1, 18).unwrap());
+
+        // Only the low byte fits in a 1 byte property.
+        table.set_property(&mut m, 1, 7, 0x1234).unwrap();
+        assert_eq!(0x34, table.property(&m, 1, 7).unwrap());
+
+        // Missing and long properties can't be written.
+        assert!(table.set_property(&mut m, 1, 5, 1).is_err());
+        assert!(table.set_property(&mut m, 1, 3, 1).is_err());
+        assert_eq!(1, m.read_byte(ZOffset::from(0x207usize)).unwrap());
+    }
+
+    #[test]
+    fn test_v5_property_list() {
+        let (m, table) = v5_properties();
+
+        let (addr, len) = table.property_data(&m, 1, 40).unwrap().unwrap();
+        assert_eq!(64, len);
+        assert_eq!(64, table.property_length(&m, addr).unwrap());
+
+        let (addr, len) = table.property_data(&m, 1, 20).unwrap().unwrap();
+        assert_eq!(3, len);
+        assert_eq!(3, table.property_length(&m, addr).unwrap());
+
+        let (addr, len) = table.property_data(&m, 1, 10).unwrap().unwrap();
+        assert_eq!(2, len);
+        assert_eq!(2, table.property_length(&m, addr).unwrap());
+        assert_eq!(0xabcd, table.property(&m, 1, 10).unwrap());
+
+        let (addr, len) = table.property_data(&m, 1, 5).unwrap().unwrap();
+        assert_eq!(1, len);
+        assert_eq!(1, table.property_length(&m, addr).unwrap());
+        assert_eq!(0x77, table.property(&m, 1, 5).unwrap());
+
+        let mut props = vec![];
+        let mut prop = table.next_property(&m, 1, 0).unwrap();
+        while prop != 0 {
+            props.push(prop);
+            prop = table.next_property(&m, 1, prop).unwrap();
+        }
+        assert_eq!(vec![40, 20, 10, 5], props);
+
+        assert!(table.property(&m, 1, 40).is_err());
+        assert!(table.property_data(&m, 1, 63).unwrap().is_none());
+        assert!(table.property_data(&m, 1, 64).is_err());
+    }
+
+    #[test]
+    fn test_unterminated_properties() {
+        // Object 2's property list repeats property 1 until the end of dynamic memory.
+        let m = fake_memory_with(3, 0x400, 0x300, |bytes| {
+            put_word(bytes, OBJECT_TABLE_START, TABLE as u16);
+            let entries = TABLE + 62;
+            put_word(bytes, entries + 7, 0x200);
+            put_word(bytes, entries + 9 + 7, 0x210);
+            for byte in &mut bytes[0x211..0x300] {
+                *byte = 0x01;
+            }
+        });
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+        assert!(table.property(&m, 2, 5).is_err());
+        assert!(table.next_property(&m, 2, 0).is_err());
+        assert_eq!(0, table.next_property(&m, 1, 0).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_objects() {
+        let m = v3_memory();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+
+        assert!(table.parent(&m, 0).is_err());
+        assert!(table.child(&m, 21).is_ok());
+        assert!(table.child(&m, 22).is_err());
+        assert!(table.attribute(&m, 0, 1).is_err());
+        let abbrevs = ZAbbrevTable::new(&m).unwrap();
+        assert!(table.short_name(&m, &abbrevs, 0).is_err());
+    }
+
+    #[test]
+    fn test_object_count() {
+        // The entries end at the lowest property table, even when it isn't object 1's.
+        let mut m = v3_memory();
+        let entries = TABLE + 62;
+        m.write_word(ZOffset::from(entries + 2 * 9 + 7), (entries + 3 * 9) as u16)
+            .unwrap();
+        let table = ZObjectTable::new(&m, number_to_version(3).unwrap()).unwrap();
+        assert_eq!(3, table.object_count);
+        assert!(table.child(&m, 3).is_ok());
+        assert!(table.child(&m, 4).is_err());
+    }
+}
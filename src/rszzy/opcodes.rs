@@ -0,0 +1,402 @@
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+use std::fmt::{Display, Formatter};
+
+/// ZSpec 4.3 - The operand count of an instruction, which selects the opcode table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperandCount {
+    Op0,
+    Op1,
+    Op2,
+    Var,
+    Ext,
+}
+
+/// Every opcode in the ZSpec 14 tables.
+/// Opcodes whose meaning changes between versions are given distinct variants,
+/// so that the processor never needs to consult the version when executing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Opcode {
+    // 2OP
+    Je,
+    Jl,
+    Jg,
+    DecChk,
+    IncChk,
+    Jin,
+    Test,
+    Or,
+    And,
+    TestAttr,
+    SetAttr,
+    ClearAttr,
+    Store,
+    InsertObj,
+    Loadw,
+    Loadb,
+    GetProp,
+    GetPropAddr,
+    GetNextProp,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Call2s,
+    Call2n,
+    SetColour,
+    Throw,
+
+    // 1OP
+    Jz,
+    GetSibling,
+    GetChild,
+    GetParent,
+    GetPropLen,
+    Inc,
+    Dec,
+    PrintAddr,
+    Call1s,
+    RemoveObj,
+    PrintObj,
+    Ret,
+    Jump,
+    PrintPaddr,
+    Load,
+    Not,
+    Call1n,
+
+    // 0OP
+    Rtrue,
+    Rfalse,
+    Print,
+    PrintRet,
+    Nop,
+    Save,
+    Restore,
+    Restart,
+    RetPopped,
+    Pop,
+    Catch,
+    Quit,
+    NewLine,
+    ShowStatus,
+    Verify,
+    Piracy,
+
+    // VAR
+    CallVs,
+    Storew,
+    Storeb,
+    PutProp,
+    Sread,
+    Aread,
+    PrintChar,
+    PrintNum,
+    Random,
+    Push,
+    Pull,
+    SplitWindow,
+    SetWindow,
+    CallVs2,
+    EraseWindow,
+    EraseLine,
+    SetCursor,
+    GetCursor,
+    SetTextStyle,
+    BufferMode,
+    OutputStream,
+    InputStream,
+    SoundEffect,
+    ReadChar,
+    ScanTable,
+    CallVn,
+    CallVn2,
+    Tokenise,
+    EncodeText,
+    CopyTable,
+    PrintTable,
+    CheckArgCount,
+
+    // EXT
+    SaveExt,
+    RestoreExt,
+    LogShift,
+    ArtShift,
+    SetFont,
+    DrawPicture,
+    PictureData,
+    ErasePicture,
+    SetMargins,
+    SaveUndo,
+    RestoreUndo,
+    PrintUnicode,
+    CheckUnicode,
+    SetTrueColour,
+    MoveWindow,
+    WindowSize,
+    WindowStyle,
+    GetWindProp,
+    ScrollWindow,
+    PopStack,
+    ReadMouse,
+    MouseWindow,
+    PushStack,
+    PutWindProp,
+    PrintForm,
+    MakeMenu,
+    PictureTable,
+    BufferScreen,
+}
+
+impl Opcode {
+    /// Look up an opcode from its operand count and number, as described in ZSpec 14.
+    /// Fails if the opcode does not exist in the given version.
+    #[throws]
+    pub fn decode(count: OperandCount, number: u8, version: &Version) -> Opcode {
+        use Opcode::*;
+        use OperandCount::*;
+
+        let v = version.version_number;
+        let opcode = match (count, number) {
+            (Op2, 0x01) => Some(Je),
+            (Op2, 0x02) => Some(Jl),
+            (Op2, 0x03) => Some(Jg),
+            (Op2, 0x04) => Some(DecChk),
+            (Op2, 0x05) => Some(IncChk),
+            (Op2, 0x06) => Some(Jin),
+            (Op2, 0x07) => Some(Test),
+            (Op2, 0x08) => Some(Or),
+            (Op2, 0x09) => Some(And),
+            (Op2, 0x0a) => Some(TestAttr),
+            (Op2, 0x0b) => Some(SetAttr),
+            (Op2, 0x0c) => Some(ClearAttr),
+            (Op2, 0x0d) => Some(Store),
+            (Op2, 0x0e) => Some(InsertObj),
+            (Op2, 0x0f) => Some(Loadw),
+            (Op2, 0x10) => Some(Loadb),
+            (Op2, 0x11) => Some(GetProp),
+            (Op2, 0x12) => Some(GetPropAddr),
+            (Op2, 0x13) => Some(GetNextProp),
+            (Op2, 0x14) => Some(Add),
+            (Op2, 0x15) => Some(Sub),
+            (Op2, 0x16) => Some(Mul),
+            (Op2, 0x17) => Some(Div),
+            (Op2, 0x18) => Some(Mod),
+            (Op2, 0x19) if v >= 4 => Some(Call2s),
+            (Op2, 0x1a) if v >= 5 => Some(Call2n),
+            (Op2, 0x1b) if v >= 5 => Some(SetColour),
+            (Op2, 0x1c) if v >= 5 => Some(Throw),
+
+            (Op1, 0x00) => Some(Jz),
+            (Op1, 0x01) => Some(GetSibling),
+            (Op1, 0x02) => Some(GetChild),
+            (Op1, 0x03) => Some(GetParent),
+            (Op1, 0x04) => Some(GetPropLen),
+            (Op1, 0x05) => Some(Inc),
+            (Op1, 0x06) => Some(Dec),
+            (Op1, 0x07) => Some(PrintAddr),
+            (Op1, 0x08) if v >= 4 => Some(Call1s),
+            (Op1, 0x09) => Some(RemoveObj),
+            (Op1, 0x0a) => Some(PrintObj),
+            (Op1, 0x0b) => Some(Ret),
+            (Op1, 0x0c) => Some(Jump),
+            (Op1, 0x0d) => Some(PrintPaddr),
+            (Op1, 0x0e) => Some(Load),
+            (Op1, 0x0f) if v <= 4 => Some(Not),
+            (Op1, 0x0f) => Some(Call1n),
+
+            (Op0, 0x00) => Some(Rtrue),
+            (Op0, 0x01) => Some(Rfalse),
+            (Op0, 0x02) => Some(Print),
+            (Op0, 0x03) => Some(PrintRet),
+            (Op0, 0x04) => Some(Nop),
+            (Op0, 0x05) if v <= 4 => Some(Save),
+            (Op0, 0x06) if v <= 4 => Some(Restore),
+            (Op0, 0x07) => Some(Restart),
+            (Op0, 0x08) => Some(RetPopped),
+            (Op0, 0x09) if v <= 4 => Some(Pop),
+            (Op0, 0x09) => Some(Catch),
+            (Op0, 0x0a) => Some(Quit),
+            (Op0, 0x0b) => Some(NewLine),
+            (Op0, 0x0c) if v == 3 => Some(ShowStatus),
+            // ZSpec 14: show_status is illegal after V3, but some games contain it anyway.
+            (Op0, 0x0c) if v > 3 => Some(Nop),
+            (Op0, 0x0d) if v >= 3 => Some(Verify),
+            (Op0, 0x0f) if v >= 5 => Some(Piracy),
+
+            (Var, 0x00) => Some(CallVs),
+            (Var, 0x01) => Some(Storew),
+            (Var, 0x02) => Some(Storeb),
+            (Var, 0x03) => Some(PutProp),
+            (Var, 0x04) if v <= 4 => Some(Sread),
+            (Var, 0x04) => Some(Aread),
+            (Var, 0x05) => Some(PrintChar),
+            (Var, 0x06) => Some(PrintNum),
+            (Var, 0x07) => Some(Random),
+            (Var, 0x08) => Some(Push),
+            (Var, 0x09) => Some(Pull),
+            (Var, 0x0a) if v >= 3 => Some(SplitWindow),
+            (Var, 0x0b) if v >= 3 => Some(SetWindow),
+            (Var, 0x0c) if v >= 4 => Some(CallVs2),
+            (Var, 0x0d) if v >= 4 => Some(EraseWindow),
+            (Var, 0x0e) if v >= 4 => Some(EraseLine),
+            (Var, 0x0f) if v >= 4 => Some(SetCursor),
+            (Var, 0x10) if v >= 4 => Some(GetCursor),
+            (Var, 0x11) if v >= 4 => Some(SetTextStyle),
+            (Var, 0x12) if v >= 4 => Some(BufferMode),
+            (Var, 0x13) if v >= 3 => Some(OutputStream),
+            (Var, 0x14) if v >= 3 => Some(InputStream),
+            (Var, 0x15) if v >= 3 => Some(SoundEffect),
+            (Var, 0x16) if v >= 4 => Some(ReadChar),
+            (Var, 0x17) if v >= 4 => Some(ScanTable),
+            (Var, 0x18) if v >= 5 => Some(Not),
+            (Var, 0x19) if v >= 5 => Some(CallVn),
+            (Var, 0x1a) if v >= 5 => Some(CallVn2),
+            (Var, 0x1b) if v >= 5 => Some(Tokenise),
+            (Var, 0x1c) if v >= 5 => Some(EncodeText),
+            (Var, 0x1d) if v >= 5 => Some(CopyTable),
+            (Var, 0x1e) if v >= 5 => Some(PrintTable),
+            (Var, 0x1f) if v >= 5 => Some(CheckArgCount),
+
+            (Ext, 0x00) => Some(SaveExt),
+            (Ext, 0x01) => Some(RestoreExt),
+            (Ext, 0x02) => Some(LogShift),
+            (Ext, 0x03) => Some(ArtShift),
+            (Ext, 0x04) => Some(SetFont),
+            (Ext, 0x05) if v == 6 => Some(DrawPicture),
+            (Ext, 0x06) if v == 6 => Some(PictureData),
+            (Ext, 0x07) if v == 6 => Some(ErasePicture),
+            (Ext, 0x08) if v == 6 => Some(SetMargins),
+            (Ext, 0x09) => Some(SaveUndo),
+            (Ext, 0x0a) => Some(RestoreUndo),
+            (Ext, 0x0b) => Some(PrintUnicode),
+            (Ext, 0x0c) => Some(CheckUnicode),
+            (Ext, 0x0d) => Some(SetTrueColour),
+            (Ext, 0x10) if v == 6 => Some(MoveWindow),
+            (Ext, 0x11) if v == 6 => Some(WindowSize),
+            (Ext, 0x12) if v == 6 => Some(WindowStyle),
+            (Ext, 0x13) if v == 6 => Some(GetWindProp),
+            (Ext, 0x14) if v == 6 => Some(ScrollWindow),
+            (Ext, 0x15) if v == 6 => Some(PopStack),
+            (Ext, 0x16) if v == 6 => Some(ReadMouse),
+            (Ext, 0x17) if v == 6 => Some(MouseWindow),
+            (Ext, 0x18) if v == 6 => Some(PushStack),
+            (Ext, 0x19) if v == 6 => Some(PutWindProp),
+            (Ext, 0x1a) if v == 6 => Some(PrintForm),
+            (Ext, 0x1b) if v == 6 => Some(MakeMenu),
+            (Ext, 0x1c) if v == 6 => Some(PictureTable),
+            (Ext, 0x1d) if v == 6 => Some(BufferScreen),
+
+            _ => None,
+        };
+
+        opcode.ok_or_else(|| {
+            anyhow!(
+                "Illegal opcode: {:?}:0x{:02x} in version {}",
+                count,
+                number,
+                version
+            )
+        })?
+    }
+
+    /// ZSpec 4.6 - true if the instruction is followed by a store variable byte.
+    pub fn stores(self, version: &Version) -> bool {
+        use Opcode::*;
+
+        match self {
+            Or | And | Loadw | Loadb | GetProp | GetPropAddr | GetNextProp | Add | Sub | Mul
+            | Div | Mod | Call2s | GetSibling | GetChild | GetParent | GetPropLen | Call1s
+            | Load | Not | Catch | CallVs | Aread | Random | CallVs2 | ReadChar | ScanTable
+            | SaveExt | RestoreExt | LogShift | ArtShift | SetFont | SaveUndo | RestoreUndo
+            | CheckUnicode | GetWindProp | BufferScreen => true,
+
+            // In V4, save and restore store their result rather than branching.
+            Save | Restore => version.version_number == 4,
+
+            // pull stores in V6 only.
+            Pull => version.version_number == 6,
+
+            _ => false,
+        }
+    }
+
+    /// ZSpec 4.7 - true if the instruction is followed by branch data.
+    pub fn branches(self, version: &Version) -> bool {
+        use Opcode::*;
+
+        match self {
+            Je | Jl | Jg | DecChk | IncChk | Jin | Test | TestAttr | Jz | GetSibling | GetChild
+            | Verify | Piracy | ScanTable | CheckArgCount | PictureData | PushStack | MakeMenu => {
+                true
+            }
+
+            Save | Restore => version.version_number <= 3,
+
+            _ => false,
+        }
+    }
+
+    /// ZSpec 4.8 - true if the instruction is followed by an inline ZString.
+    pub fn has_text(self) -> bool {
+        matches!(self, Opcode::Print | Opcode::PrintRet)
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, fmt: &mut Formatter) -> std::result::Result<(), std::fmt::Error> {
+        write!(fmt, "{:?}", self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::versions::number_to_version;
+
+    #[test]
+    fn test_version_specific() {
+        let v3 = number_to_version(3).unwrap();
+        let v5 = number_to_version(5).unwrap();
+
+        assert_eq!(
+            Opcode::Not,
+            Opcode::decode(OperandCount::Op1, 0x0f, v3).unwrap()
+        );
+        assert_eq!(
+            Opcode::Call1n,
+            Opcode::decode(OperandCount::Op1, 0x0f, v5).unwrap()
+        );
+        assert_eq!(
+            Opcode::Not,
+            Opcode::decode(OperandCount::Var, 0x18, v5).unwrap()
+        );
+        assert!(Opcode::decode(OperandCount::Var, 0x18, v3).is_err());
+        assert!(Opcode::decode(OperandCount::Op2, 0x19, v3).is_err());
+        assert!(Opcode::decode(OperandCount::Op0, 0x05, v5).is_err());
+    }
+
+    #[test]
+    fn test_illegal() {
+        let v5 = number_to_version(5).unwrap();
+        assert!(Opcode::decode(OperandCount::Op2, 0x00, v5).is_err());
+        assert!(Opcode::decode(OperandCount::Op0, 0x0e, v5).is_err());
+        assert!(Opcode::decode(OperandCount::Ext, 0x1e, v5).is_err());
+    }
+
+    #[test]
+    fn test_store_and_branch() {
+        let v3 = number_to_version(3).unwrap();
+        let v5 = number_to_version(5).unwrap();
+
+        assert!(Opcode::Save.branches(v3));
+        assert!(!Opcode::Save.stores(v3));
+        assert!(Opcode::GetChild.branches(v5));
+        assert!(Opcode::GetChild.stores(v5));
+        assert!(!Opcode::Print.stores(v5));
+        assert!(Opcode::Print.has_text());
+        assert!(!Opcode::Pull.stores(v5));
+    }
+}
@@ -1,7 +1,8 @@
 use crate::rszzy::addressing::ZOffset;
+use std::fmt::Display;
 use std::ops::AddAssign;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone, Copy)]
 pub struct PC(usize);
 
 impl PC {
@@ -12,6 +13,14 @@ impl PC {
     pub fn is_zero(&self) -> bool {
         self.0 == 0
     }
+
+    pub fn offset(&self) -> ZOffset {
+        ZOffset::from(self.0)
+    }
+
+    pub fn set(&mut self, offset: impl Into<ZOffset>) {
+        self.0 = offset.into().into();
+    }
 }
 
 impl From<PC> for ZOffset {
@@ -28,3 +37,9 @@ where
         self.0 += usize::from(rhs.into());
     }
 }
+
+impl Display for PC {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "PC:0x{:x}", self.0)
+    }
+}
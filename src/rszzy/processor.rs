@@ -1,32 +1,2049 @@
+use crate::ensure;
+use crate::rszzy::abbrevs::ZAbbrevTable;
+use crate::rszzy::addressing::{PackedAddress, ZOffset};
+use crate::rszzy::constants::flags2::{FORCE_FIXED_PITCH, TRANSCRIPTING};
+use crate::rszzy::constants::header_offset::{FLAGS2, HEADER_SIZE};
+use crate::rszzy::dictionary::ZDictionary;
+use crate::rszzy::globals::ZGlobalTable;
+use crate::rszzy::header::Header;
+use crate::rszzy::input::{preloaded_text, store_text, terminating_chars, text_capacity};
+use crate::rszzy::instruction::{decode, BranchTarget, Instruction, Operand};
+use crate::rszzy::interpreter::InterpreterConfig;
+use crate::rszzy::keys::Key;
+use crate::rszzy::lexer::tokenise;
 use crate::rszzy::memory::ZMemory;
+use crate::rszzy::objects::ZObjectTable;
+use crate::rszzy::opcodes::Opcode;
 use crate::rszzy::pc::PC;
-use crate::rszzy::traits::Memory;
-use anyhow::Error;
-use fehler::throws;
+use crate::rszzy::stack::{read_routine_header, Frame, Variable, ZStack};
+use crate::rszzy::terminal::ZTerminal;
+use crate::rszzy::text::{encode_text, ZString, ZSCII};
+use crate::rszzy::traits::{GlobalTable, Memory, ObjectTable, Terminal};
+use crate::rszzy::unicode::ZUnicodeTable;
+use crate::rszzy::verify::checksum;
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Context, Error};
+use fehler::{throw, throws};
+use std::time::Duration;
 
-type Stack = ();
-
-#[derive(Default, Debug)]
-pub struct ZProcessor<M = ZMemory> {
+pub struct ZProcessor<M = ZMemory, T = ZTerminal> {
     // The ZMachine's "core" memory.
     memory: M,
+    // Dynamic memory as loaded, for @restart.
+    initial_dynamic: Vec<u8>,
+
+    version: &'static Version,
+    config: InterpreterConfig,
+
+    abbrevs: ZAbbrevTable,
+    globals: ZGlobalTable,
+    objects: ZObjectTable,
+    unicode: ZUnicodeTable,
+
+    // Screen and keyboard
+    terminal: T,
 
     // program counter
     pc: PC,
 
     // Runtime stack for procedure calls/local vars
-    stack: (),
+    stack: ZStack,
 }
 
-impl<M> ZProcessor<M>
+impl<M, T> ZProcessor<M, T>
 where
     M: Memory,
+    T: Terminal,
 {
-    pub fn new(memory: M, pc: PC, stack: Stack) -> ZProcessor<M> {
-        ZProcessor { memory, pc, stack }
+    #[throws]
+    pub fn new(
+        mut memory: M,
+        version: &'static Version,
+        config: InterpreterConfig,
+        pc: PC,
+        stack: ZStack,
+        terminal: T,
+    ) -> ZProcessor<M, T> {
+        let initial_dynamic = (0..memory.memory_size())
+            .map(ZOffset::from)
+            .take_while(|offset| memory.in_dynamic_range(*offset))
+            .map(|offset| memory.read_byte_unchecked(offset))
+            .collect::<Result<Vec<_>, _>>()?;
+        config.apply(&mut memory, version)?;
+
+        let abbrevs = ZAbbrevTable::new(&memory)?;
+        let globals = ZGlobalTable::new(&memory)?;
+        let objects = ZObjectTable::new(&memory, version)?;
+        let unicode = ZUnicodeTable::new(&memory)?;
+        ZProcessor {
+            memory,
+            initial_dynamic,
+            version,
+            config,
+            abbrevs,
+            globals,
+            objects,
+            unicode,
+            terminal,
+            pc,
+            stack,
+        }
+    }
+
+    #[throws]
+    pub fn process(&mut self) {
+        while self.step()? {}
+    }
+
+    /// Decode and execute a single instruction.
+    /// Returns false once the game has quit.
+    #[throws]
+    pub fn step(&mut self) -> bool {
+        let instr = decode(&self.memory, self.version, self.pc.offset())?;
+        self.pc.set(instr.next_offset());
+        let running = self.execute(&instr);
+        for warning in self.memory.take_warnings() {
+            self.terminal.warn(&warning)?;
+        }
+        running?
+    }
+
+    #[throws]
+    fn execute(&mut self, instr: &Instruction) -> bool {
+        use Opcode::*;
+
+        match instr.opcode {
+            // ZSpec 15 - arithmetic. All arithmetic is signed 16-bit and wraps.
+            Add => {
+                let v = self.values(instr, 2)?;
+                self.store(instr, signed(v[0]).wrapping_add(signed(v[1])) as u16)?;
+            }
+            Sub => {
+                let v = self.values(instr, 2)?;
+                self.store(instr, signed(v[0]).wrapping_sub(signed(v[1])) as u16)?;
+            }
+            Mul => {
+                let v = self.values(instr, 2)?;
+                self.store(instr, signed(v[0]).wrapping_mul(signed(v[1])) as u16)?;
+            }
+            Div | Mod => {
+                let v = self.values(instr, 2)?;
+                ensure!(
+                    v[1] != 0,
+                    anyhow!(
+                        "Division by zero in {} at {}",
+                        instr.opcode,
+                        PC::at(instr.offset)
+                    )
+                );
+                // Rust's / and % truncate toward zero, as required by ZSpec 15.
+                let result = if instr.opcode == Div {
+                    signed(v[0]).wrapping_div(signed(v[1]))
+                } else {
+                    signed(v[0]).wrapping_rem(signed(v[1]))
+                };
+                self.store(instr, result as u16)?;
+            }
+            LogShift => {
+                let v = self.values(instr, 2)?;
+                let places = signed(v[1]);
+                let result = if places >= 0 {
+                    v[0].checked_shl(places as u32).unwrap_or(0)
+                } else {
+                    v[0].checked_shr(-i32::from(places) as u32).unwrap_or(0)
+                };
+                self.store(instr, result)?;
+            }
+            ArtShift => {
+                let v = self.values(instr, 2)?;
+                let places = signed(v[1]);
+                let result = if places >= 0 {
+                    v[0].checked_shl(places as u32).unwrap_or(0)
+                } else {
+                    // Shifting an i16 right by 15 or more fills it with the sign bit.
+                    (signed(v[0]) >> std::cmp::min(15, -i32::from(places))) as u16
+                };
+                self.store(instr, result)?;
+            }
+
+            // ZSpec 15 - bitwise operations.
+            And => {
+                let v = self.values(instr, 2)?;
+                self.store(instr, v[0] & v[1])?;
+            }
+            Or => {
+                let v = self.values(instr, 2)?;
+                self.store(instr, v[0] | v[1])?;
+            }
+            Not => {
+                let v = self.values(instr, 1)?;
+                self.store(instr, !v[0])?;
+            }
+
+            // ZSpec 15 - comparisons and tests.
+            Je => {
+                let v = self.values(instr, 2)?;
+                self.branch(instr, v[1..].contains(&v[0]))?;
+            }
+            Jl => {
+                let v = self.values(instr, 2)?;
+                self.branch(instr, signed(v[0]) < signed(v[1]))?;
+            }
+            Jg => {
+                let v = self.values(instr, 2)?;
+                self.branch(instr, signed(v[0]) > signed(v[1]))?;
+            }
+            Jz => {
+                let v = self.values(instr, 1)?;
+                self.branch(instr, v[0] == 0)?;
+            }
+            Test => {
+                let v = self.values(instr, 2)?;
+                self.branch(instr, v[0] & v[1] == v[1])?;
+            }
+
+            // ZSpec 15 - increment and decrement take a variable number as their first operand.
+            Inc | Dec => {
+                let v = self.values(instr, 1)?;
+                let var = Variable::from(v[0] as u8);
+                let delta = if instr.opcode == Inc { 1 } else { -1 };
+                let val = signed(self.read_variable_indirect(var)?).wrapping_add(delta);
+                self.write_variable_indirect(var, val as u16)?;
+            }
+            IncChk | DecChk => {
+                let v = self.values(instr, 2)?;
+                let var = Variable::from(v[0] as u8);
+                let val = signed(self.read_variable_indirect(var)?);
+                let (val, cond) = if instr.opcode == IncChk {
+                    let val = val.wrapping_add(1);
+                    (val, val > signed(v[1]))
+                } else {
+                    let val = val.wrapping_sub(1);
+                    (val, val < signed(v[1]))
+                };
+                self.write_variable_indirect(var, val as u16)?;
+                self.branch(instr, cond)?;
+            }
+
+            // ZSpec 6.4 - routine calls. The "n" variants throw away the result.
+            CallVs | Call1s | Call2s | CallVs2 => {
+                let v = self.values(instr, 1)?;
+                let store = self.store_variable(instr)?;
+                self.call(instr, v[0], &v[1..], Some(store))?;
+            }
+            Call1n | Call2n | CallVn | CallVn2 => {
+                let v = self.values(instr, 1)?;
+                self.call(instr, v[0], &v[1..], None)?;
+            }
+            Ret => {
+                let v = self.values(instr, 1)?;
+                self.return_value(v[0])?;
+            }
+            Rtrue => self.return_value(1)?,
+            Rfalse => self.return_value(0)?,
+            RetPopped => {
+                let val = self.stack.pop()?;
+                self.return_value(val)?;
+            }
+            // ZSpec 6.6 - catch and throw.
+            Catch => {
+                let id = self.stack.frame_id();
+                self.store(instr, id)?;
+            }
+            Throw => {
+                let v = self.values(instr, 2)?;
+                self.stack
+                    .unwind_to(v[1])
+                    .with_context(|| format!("at {}", PC::at(instr.offset)))?;
+                self.return_value(v[0])?;
+            }
+            CheckArgCount => {
+                let v = self.values(instr, 1)?;
+                let arg_count = self.stack.arg_count();
+                self.branch(instr, v[0] <= u16::from(arg_count))?;
+            }
+
+            // ZSpec 12, 15 - the object tree.
+            InsertObj => {
+                let v = self.values(instr, 2)?;
+                self.check_object(instr, v[0])?;
+                self.check_object(instr, v[1])?;
+                self.objects
+                    .insert(&mut self.memory, v[0], v[1])
+                    .with_context(|| object_context(instr, v[0]))?;
+            }
+            RemoveObj => {
+                let v = self.values(instr, 1)?;
+                self.objects
+                    .remove(&mut self.memory, v[0])
+                    .with_context(|| object_context(instr, v[0]))?;
+            }
+            GetParent => {
+                let v = self.values(instr, 1)?;
+                let parent = self
+                    .objects
+                    .parent(&self.memory, v[0])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.store(instr, parent)?;
+            }
+            GetSibling => {
+                let v = self.values(instr, 1)?;
+                let sibling = self
+                    .objects
+                    .sibling(&self.memory, v[0])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.store(instr, sibling)?;
+                self.branch(instr, sibling != 0)?;
+            }
+            GetChild => {
+                let v = self.values(instr, 1)?;
+                let child = self
+                    .objects
+                    .child(&self.memory, v[0])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.store(instr, child)?;
+                self.branch(instr, child != 0)?;
+            }
+            Jin => {
+                let v = self.values(instr, 2)?;
+                let parent = self
+                    .objects
+                    .parent(&self.memory, v[0])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.check_object(instr, v[1])?;
+                self.branch(instr, parent == v[1])?;
+            }
+            TestAttr => {
+                let v = self.values(instr, 2)?;
+                let set = self
+                    .objects
+                    .attribute(&self.memory, v[0], v[1])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.branch(instr, set)?;
+            }
+            SetAttr | ClearAttr => {
+                let v = self.values(instr, 2)?;
+                self.objects
+                    .set_attribute(&mut self.memory, v[0], v[1], instr.opcode == SetAttr)
+                    .with_context(|| object_context(instr, v[0]))?;
+            }
+            GetProp => {
+                let v = self.values(instr, 2)?;
+                let val = self
+                    .objects
+                    .property(&self.memory, v[0], v[1])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.store(instr, val)?;
+            }
+            PutProp => {
+                let v = self.values(instr, 3)?;
+                self.objects
+                    .set_property(&mut self.memory, v[0], v[1], v[2])
+                    .with_context(|| object_context(instr, v[0]))?;
+            }
+            GetPropAddr => {
+                let v = self.values(instr, 2)?;
+                let data = self
+                    .objects
+                    .property_data(&self.memory, v[0], v[1])
+                    .with_context(|| object_context(instr, v[0]))?;
+                // Property data is in dynamic memory, so its address fits in a word.
+                let addr = data.map_or(0, |(addr, _)| usize::from(addr) as u16);
+                self.store(instr, addr)?;
+            }
+            GetPropLen => {
+                let v = self.values(instr, 1)?;
+                let len = self
+                    .objects
+                    .property_length(&self.memory, ZOffset::from(v[0]))
+                    .with_context(|| format!("{} at {}", instr.opcode, PC::at(instr.offset)))?;
+                self.store(instr, len)?;
+            }
+            GetNextProp => {
+                let v = self.values(instr, 2)?;
+                let next = self
+                    .objects
+                    .next_property(&self.memory, v[0], v[1])
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.store(instr, next)?;
+            }
+            PrintObj => {
+                let v = self.values(instr, 1)?;
+                let name = self
+                    .objects
+                    .short_name(&self.memory, &self.abbrevs, v[0])
+                    .and_then(|name| self.unicode.decode(name))
+                    .with_context(|| object_context(instr, v[0]))?;
+                self.terminal.print(&name)?;
+            }
+
+            // ZSpec 3, 15 - text.
+            Print | PrintRet => {
+                // Decoding always sets text for these opcodes.
+                let text = instr.text.unwrap_or_default();
+                self.print_string(instr, text)?;
+                if instr.opcode == PrintRet {
+                    self.terminal.print("\n")?;
+                    self.return_value(1)?;
+                }
+            }
+            PrintAddr => {
+                let v = self.values(instr, 1)?;
+                self.print_string(instr, ZOffset::from(v[0]))?;
+            }
+            PrintPaddr => {
+                let v = self.values(instr, 1)?;
+                let offset = PackedAddress::from(v[0]).string_offset(&self.memory, self.version)?;
+                self.print_string(instr, offset)?;
+            }
+            NewLine => self.terminal.print("\n")?,
+            PrintUnicode => {
+                let v = self.values(instr, 1)?;
+                // ZSpec 3.8.5.4.1 - print a question mark for characters that can't be printed.
+                let ch = printable_char(v[0]).unwrap_or('?');
+                self.terminal.print(&ch.to_string())?;
+            }
+            CheckUnicode => {
+                let v = self.values(instr, 1)?;
+                // ZSpec 15 (check_unicode) - bit 0 if the character can be printed,
+                // bit 1 if it can be typed. Only characters with a ZSCII code can be typed.
+                let result = match printable_char(v[0]) {
+                    None => 0,
+                    Some(ch) if self.unicode.to_zscii(ch).is_some() => 3,
+                    Some(_) => 1,
+                };
+                self.store(instr, result)?;
+            }
+            EncodeText => {
+                let v = self.values(instr, 4)?;
+                let start = ZOffset::from(v[0]) + usize::from(v[2]);
+                let dest = ZOffset::from(v[3]);
+                let context = || format!("encode_text at {}", PC::at(instr.offset));
+
+                let mut text = Vec::with_capacity(usize::from(v[1]));
+                for idx in 0..usize::from(v[1]) {
+                    let byte = self.memory.read_byte(start + idx).with_context(context)?;
+                    text.push(ZSCII::from(u16::from(byte)));
+                }
+                let encoded =
+                    encode_text(&self.memory, self.version, &text).with_context(context)?;
+                for (idx, byte) in encoded.into_iter().enumerate() {
+                    self.memory
+                        .write_byte(dest + idx, byte)
+                        .with_context(context)?;
+                }
+            }
+            Sread | Aread => return self.read(instr)?,
+            ReadChar => return self.read_char(instr)?,
+            Tokenise => {
+                let v = self.values(instr, 2)?;
+                let context = || format!("tokenise at {}", PC::at(instr.offset));
+                // ZSpec 15 (tokenise) - a dictionary of 0 means the game's own.
+                let dictionary = match v.get(2) {
+                    Some(dict) if *dict != 0 => {
+                        ZDictionary::at(&self.memory, self.version, ZOffset::from(*dict))
+                    }
+                    _ => ZDictionary::new(&self.memory, self.version),
+                }
+                .with_context(context)?;
+                let keep_unknown = v.get(3).is_some_and(|flag| *flag != 0);
+                tokenise(
+                    &mut self.memory,
+                    self.version,
+                    &dictionary,
+                    ZOffset::from(v[0]),
+                    ZOffset::from(v[1]),
+                    keep_unknown,
+                )
+                .with_context(context)?;
+            }
+
+            Nop => {}
+            Verify => {
+                let ok = self.verify()?;
+                self.branch(instr, ok)?;
+            }
+            Restart => self.restart()?,
+            Quit => return false,
+            _ => throw!(anyhow!(
+                "Unimplemented opcode, {}, at {}",
+                instr.opcode,
+                instr.offset
+            )),
+        }
+        true
+    }
+
+    /// ZSpec 15 (read) - read a line of input into the text buffer and, if there is
+    /// a parse buffer, tokenise it. Returns false if the game quit during a timed
+    /// interrupt.
+    #[throws]
+    fn read(&mut self, instr: &Instruction) -> bool {
+        let v = self.values(instr, 1)?;
+        let context = || format!("{} at {}", instr.opcode, PC::at(instr.offset));
+        let text_buffer = ZOffset::from(v[0]);
+        let parse_buffer = v.get(1).copied().unwrap_or(0);
+        let timer = timer(v.get(2), v.get(3));
+
+        let max_len =
+            text_capacity(&self.memory, self.version, text_buffer).with_context(context)?;
+        let preloaded =
+            preloaded_text(&self.memory, self.version, text_buffer).with_context(context)?;
+        let terminators = terminating_chars(&self.memory, self.version).with_context(context)?;
+        let mut line = preloaded
+            .into_iter()
+            .filter_map(|zscii| self.unicode.to_char(zscii))
+            .collect::<String>();
+
+        let terminator = loop {
+            let timeout = timer.map(|(timeout, _)| timeout);
+            if let Some(key) = self
+                .terminal
+                .read_line(&mut line, max_len, &terminators, timeout)?
+            {
+                break key;
+            }
+
+            let (_, routine) = timer.ok_or_else(|| anyhow!("Input timed out with no timer"))?;
+            match self.interrupt(instr, routine)? {
+                None => return false,
+                Some(0) => {}
+                // The routine may stop input, which then ends with ZSCII 0.
+                Some(_) => break ZSCII::from(0),
+            }
+        };
+
+        // Input is stored in lower case, and characters without a ZSCII code are dropped.
+        let text = line
+            .chars()
+            .flat_map(char::to_lowercase)
+            .filter_map(|ch| self.unicode.to_zscii(ch))
+            .take(max_len)
+            .collect::<Vec<_>>();
+        store_text(&mut self.memory, self.version, text_buffer, &text).with_context(context)?;
+
+        if parse_buffer != 0 {
+            let dictionary = ZDictionary::new(&self.memory, self.version).with_context(context)?;
+            tokenise(
+                &mut self.memory,
+                self.version,
+                &dictionary,
+                text_buffer,
+                ZOffset::from(parse_buffer),
+                false,
+            )
+            .with_context(context)?;
+        }
+
+        // V5+ stores the key which ended input.
+        if instr.opcode == Opcode::Aread {
+            self.store(instr, terminator.code())?;
+        }
+        true
+    }
+
+    /// ZSpec 15 (read_char) - wait for a key and store its ZSCII code. Returns false
+    /// if the game quit during a timed interrupt.
+    #[throws]
+    fn read_char(&mut self, instr: &Instruction) -> bool {
+        // The first operand is always 1, for the keyboard.
+        let v = self.values(instr, 1)?;
+        let timer = timer(v.get(1), v.get(2));
+
+        let zscii = loop {
+            let timeout = timer.map(|(timeout, _)| timeout);
+            match self.terminal.read_key(timeout)? {
+                // ZSpec 3.8 - characters without a ZSCII code are undefined, so
+                // they come through as question marks.
+                Some(Key::Char(ch)) => {
+                    break self
+                        .unicode
+                        .to_zscii(ch)
+                        .unwrap_or_else(|| ZSCII::from(u16::from(b'?')))
+                }
+                Some(Key::Special(zscii)) => break zscii,
+                None => {}
+            }
+
+            let (_, routine) = timer.ok_or_else(|| anyhow!("Input timed out with no timer"))?;
+            match self.interrupt(instr, routine)? {
+                None => return false,
+                Some(0) => {}
+                // The routine may stop input, which then gives ZSCII 0.
+                Some(_) => break ZSCII::from(0),
+            }
+        };
+        self.store(instr, zscii.code())?;
+        true
+    }
+
+    /// ZSpec 15 (read) - run the interrupt routine at packed address `routine` to
+    /// completion, in the middle of `instr`, and return its result. None if the game
+    /// quit before it returned.
+    #[throws]
+    fn interrupt(&mut self, instr: &Instruction, routine: u16) -> Option<u16> {
+        // The result goes on the evaluation stack of the routine doing the reading,
+        // and is taken straight back off.
+        let depth = self.stack.depth();
+        self.call(instr, routine, &[], Some(Variable::Stack))?;
+        while self.stack.depth() > depth {
+            if !self.step()? {
+                return None;
+            }
+        }
+        Some(self.stack.pop()?)
+    }
+
+    /// Fail, naming `obj`, unless it is in the object table. For operands which are
+    /// only compared, or which would otherwise be blamed on another object.
+    #[throws]
+    fn check_object(&self, instr: &Instruction, obj: u16) {
+        self.objects
+            .parent(&self.memory, obj)
+            .with_context(|| object_context(instr, obj))?;
+    }
+
+    /// ZSpec 11.1.6 - check the story's checksum against the header. The story is
+    /// checked as loaded, ignoring any changes the game has made to dynamic memory.
+    #[throws]
+    fn verify(&self) -> bool {
+        let header = Header::new(&self.memory);
+        let size = self.memory.memory_size();
+        let len = header.file_length()?.unwrap_or(size);
+        if len > size {
+            return false;
+        }
+
+        let mut story = Vec::with_capacity(len);
+        for idx in HEADER_SIZE..len {
+            let byte = match self.initial_dynamic.get(idx) {
+                Some(byte) => *byte,
+                None => self.memory.read_code_byte(ZOffset::from(idx))?,
+            };
+            story.push(byte);
+        }
+        checksum(&story) == header.checksum()?
+    }
+
+    /// ZSpec 6.1.3 - reload dynamic memory and start the game again. Only the
+    /// transcripting and fixed-pitch bits of Flags 2 survive.
+    #[throws]
+    fn restart(&mut self) {
+        let kept = TRANSCRIPTING | FORCE_FIXED_PITCH;
+        let flags2 = Header::new(&self.memory).flags2()? & kept;
+
+        for (idx, byte) in self.initial_dynamic.iter().enumerate() {
+            self.memory
+                .write_byte_unchecked(ZOffset::from(idx), *byte)?;
+        }
+        let restored = Header::new(&self.memory).flags2()? & !kept;
+        self.memory
+            .write_word_unchecked(FLAGS2, restored | flags2)?;
+        self.config.apply(&mut self.memory, self.version)?;
+        self.start()?;
+    }
+
+    /// ZSpec 5.5 - begin the game with an empty stack, at the address in the header.
+    /// In V6, that is the packed address of the main routine, which is called with no
+    /// arguments.
+    #[throws]
+    pub fn start(&mut self) {
+        let header = Header::new(&self.memory);
+        if self.version.main_routine {
+            let offset = header
+                .main_routine()?
+                .routine_offset(&self.memory, self.version)
+                .context("Starting the main routine")?;
+            let (locals, start) = read_routine_header(&self.memory, self.version, offset)
+                .with_context(|| format!("Starting the main routine at {}", offset))?;
+            self.stack.reset_with_locals(locals)?;
+            self.pc.set(start);
+        } else {
+            self.stack.reset();
+            self.pc = PC::at(header.start_pc()?);
+        }
+    }
+
+    /// Decode the ZString at `offset` and send it to the terminal.
+    #[throws]
+    fn print_string(&mut self, instr: &Instruction, offset: ZOffset) {
+        let text = ZString::at(&self.memory, self.version, &self.abbrevs, offset)
+            .and_then(|zs| self.unicode.decode(zs))
+            .with_context(|| {
+                format!(
+                    "{} of string at {}, at {}",
+                    instr.opcode,
+                    offset,
+                    PC::at(instr.offset)
+                )
+            })?;
+        self.terminal.print(&text)?;
+    }
+
+    /// Evaluate the operands of `instr`, in order. Fails if there are fewer than `required`.
+    #[throws]
+    fn values(&mut self, instr: &Instruction, required: usize) -> Vec<u16> {
+        ensure!(
+            instr.operands.len() >= required,
+            anyhow!(
+                "{} requires {} operands, but only {} supplied at {}",
+                instr.opcode,
+                required,
+                instr.operands.len(),
+                PC::at(instr.offset)
+            )
+        );
+
+        let mut values = Vec::with_capacity(instr.operands.len());
+        for operand in &instr.operands {
+            let value = match *operand {
+                Operand::LargeConstant(val) => val,
+                Operand::SmallConstant(val) => u16::from(val),
+                Operand::Variable(num) => self.read_variable(Variable::from(num))?,
+            };
+            values.push(value);
+        }
+        values
+    }
+
+    #[throws]
+    fn store_variable(&self, instr: &Instruction) -> Variable {
+        let var = instr.store.ok_or_else(|| {
+            anyhow!(
+                "{} at {} has no store variable",
+                instr.opcode,
+                PC::at(instr.offset)
+            )
+        })?;
+        Variable::from(var)
+    }
+
+    /// ZSpec 4.6 - store the result of an instruction.
+    #[throws]
+    fn store(&mut self, instr: &Instruction, val: u16) {
+        let var = self.store_variable(instr)?;
+        self.write_variable(var, val)?;
+    }
+
+    /// ZSpec 6.4 - call the routine at packed address `routine` with `args`.
+    /// The return value goes to `store`, or is thrown away if there is none.
+    #[throws]
+    fn call(&mut self, instr: &Instruction, routine: u16, args: &[u16], store: Option<Variable>) {
+        let packed = PackedAddress::from(routine);
+
+        // ZSpec 6.4.3 - calling address 0 does nothing and returns false.
+        if packed.is_zero() {
+            if let Some(var) = store {
+                self.write_variable(var, 0)?;
+            }
+            return;
+        }
+
+        let offset = packed
+            .routine_offset(&self.memory, self.version)
+            .with_context(|| format!("Calling routine from {}", PC::at(instr.offset)))?;
+        let (mut locals, start) = read_routine_header(&self.memory, self.version, offset)
+            .with_context(|| {
+                format!(
+                    "Calling routine at {} from {}",
+                    offset,
+                    PC::at(instr.offset)
+                )
+            })?;
+
+        // ZSpec 6.4.4 - arguments are copied into the first locals. Extra arguments are discarded.
+        for (local, arg) in locals.iter_mut().zip(args) {
+            *local = *arg;
+        }
+
+        let frame = Frame::new(self.pc.offset(), store, args.len() as u8, locals)?;
+        self.stack.push_frame(frame).with_context(|| {
+            format!(
+                "Calling routine at {} from {}",
+                offset,
+                PC::at(instr.offset)
+            )
+        })?;
+        self.pc.set(start);
+    }
+
+    /// ZSpec 4.7 - branch if `cond` matches the sense of the branch data.
+    #[throws]
+    fn branch(&mut self, instr: &Instruction, cond: bool) {
+        let branch = instr.branch.ok_or_else(|| {
+            anyhow!(
+                "{} at {} has no branch data",
+                instr.opcode,
+                PC::at(instr.offset)
+            )
+        })?;
+
+        if cond == branch.on_true {
+            match branch.target {
+                BranchTarget::ReturnFalse => self.return_value(0)?,
+                BranchTarget::ReturnTrue => self.return_value(1)?,
+                BranchTarget::Offset(offset) => self.jump(instr, offset)?,
+            }
+        }
+    }
+
+    /// ZSpec 4.7.2 - the destination is the address after the instruction, plus offset, minus 2.
+    #[throws]
+    fn jump(&mut self, instr: &Instruction, offset: i16) {
+        let dest = usize::from(instr.next_offset()) as isize + isize::from(offset) - 2;
+        ensure!(
+            dest >= 0,
+            anyhow!(
+                "Jump to negative offset, {}, at {}",
+                dest,
+                PC::at(instr.offset)
+            )
+        );
+        self.pc.set(dest as usize);
+    }
+
+    /// ZSpec 4.2.2, 6.3 - read the value of a variable.
+    /// Reading the stack variable pops the evaluation stack.
+    #[throws]
+    pub fn read_variable(&mut self, var: Variable) -> u16 {
+        match var {
+            Variable::Stack => self.stack.pop()?,
+            Variable::Local(num) => self.stack.read_local(num)?,
+            Variable::Global(num) => self.globals.read_global(&self.memory, num)?,
+        }
     }
 
+    /// ZSpec 4.2.2, 6.3 - write the value of a variable.
+    /// Writing the stack variable pushes onto the evaluation stack.
     #[throws]
-    pub fn process(&self)  {
+    pub fn write_variable(&mut self, var: Variable, val: u16) {
+        match var {
+            Variable::Stack => self.stack.push(val),
+            Variable::Local(num) => self.stack.write_local(num, val)?,
+            Variable::Global(num) => self.globals.write_global(&mut self.memory, num, val)?,
+        }
+    }
+
+    /// ZSpec 6.3.4 - opcodes that take a variable number as an operand
+    /// (inc, dec, load, store, pull...) access the stack in place rather
+    /// than pushing or popping.
+    #[throws]
+    pub fn read_variable_indirect(&mut self, var: Variable) -> u16 {
+        match var {
+            Variable::Stack => self.stack.peek()?,
+            _ => self.read_variable(var)?,
+        }
+    }
+
+    #[throws]
+    pub fn write_variable_indirect(&mut self, var: Variable, val: u16) {
+        match var {
+            Variable::Stack => self.stack.poke(val)?,
+            _ => self.write_variable(var, val)?,
+        }
+    }
+
+    /// ZSpec 6.4.4 - return from the current routine, storing `val` as directed by the caller.
+    #[throws]
+    pub fn return_value(&mut self, val: u16) {
+        let frame = self.stack.pop_frame()?;
+        self.pc.set(frame.return_pc);
+        if let Some(var) = frame.store {
+            self.write_variable(var, val)?;
+        }
+    }
+}
+
+/// ZSpec 15 (read, read_char) - how long to wait for input before calling the
+/// interrupt routine, given in tenths of a second, and the routine. None unless
+/// both are given and nonzero.
+fn timer(time: Option<&u16>, routine: Option<&u16>) -> Option<(Duration, u16)> {
+    match (time, routine) {
+        (Some(&time), Some(&routine)) if time != 0 && routine != 0 => {
+            Some((Duration::from_millis(u64::from(time) * 100), routine))
+        }
+        _ => None,
+    }
+}
+
+/// The character for Unicode code point `code`, if it is one the terminal can print.
+fn printable_char(code: u16) -> Option<char> {
+    std::char::from_u32(u32::from(code)).filter(|ch| !ch.is_control())
+}
+
+fn object_context(instr: &Instruction, obj: u16) -> String {
+    format!(
+        "{} on object {} at {}",
+        instr.opcode,
+        obj,
+        PC::at(instr.offset)
+    )
+}
+
+/// ZSpec 2.2 - numbers are interpreted as signed 16-bit for arithmetic.
+fn signed(val: u16) -> i16 {
+    val as i16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::addressing::ZOffset;
+    use crate::rszzy::constants::flags1::STATUS_LINE_UNAVAILABLE;
+    use crate::rszzy::constants::header_offset::{
+        CHECKSUM, DICTIONARY_START, FLAGS1, GLOBAL_TABLE_START, OBJECT_TABLE_START,
+        ROUTINES_OFFSET, STANDARD_REVISION, START_PC, TERMINATING_CHARS_TABLE,
+    };
+    use crate::rszzy::dictionary::test::dictionary_bytes;
+    use crate::rszzy::memory::test::put_word;
+    use crate::rszzy::traits::test::{TestInput, TestMemory, TestTerminal};
+    use crate::rszzy::versions::number_to_version;
+
+    const GLOBALS: usize = 0x40;
+    const STATIC_START: usize = 0x300;
+    const CODE: usize = 0x400;
+
+    // Store target for all of the tests: global 0.
+    const G00: u8 = 0x10;
+
+    // One-byte branch data: branch on true, offset 5.
+    const BRANCH: u8 = 0xc5;
+
+    fn processor(version: u8, code: &[u8]) -> ZProcessor<TestMemory, TestTerminal> {
+        processor_with(version, code, |_| {})
+    }
+
+    // As `processor`, but `init` may change the story's bytes before it is loaded.
+    fn processor_with<F>(version: u8, code: &[u8], init: F) -> ZProcessor<TestMemory, TestTerminal>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let mut bytes = vec![0; 0x800];
+        bytes[0] = version;
+        bytes[GLOBAL_TABLE_START] = (GLOBALS >> 8) as u8;
+        bytes[GLOBAL_TABLE_START + 1] = (GLOBALS & 0xff) as u8;
+        bytes[START_PC] = (CODE >> 8) as u8;
+        bytes[START_PC + 1] = (CODE & 0xff) as u8;
+        bytes[CODE..CODE + code.len()].copy_from_slice(code);
+        init(&mut bytes);
+
+        let memory = TestMemory::new(bytes, STATIC_START, CODE);
+        ZProcessor::new(
+            memory,
+            number_to_version(version).unwrap(),
+            InterpreterConfig::default(),
+            PC::at(CODE),
+            ZStack::default(),
+            TestTerminal::default(),
+        )
+        .unwrap()
+    }
+
+    fn neg(val: i16) -> u16 {
+        (-val) as u16
+    }
+
+    // A 2OP in variable form with two large constants.
+    fn op2(number: u8, a: u16, b: u16) -> Vec<u8> {
+        vec![
+            0xc0 | number,
+            0b00_00_11_11,
+            (a >> 8) as u8,
+            (a & 0xff) as u8,
+            (b >> 8) as u8,
+            (b & 0xff) as u8,
+        ]
+    }
+
+    // An EXT with two large constants.
+    fn ext(number: u8, a: u16, b: u16) -> Vec<u8> {
+        vec![
+            0xbe,
+            number,
+            0b00_00_11_11,
+            (a >> 8) as u8,
+            (a & 0xff) as u8,
+            (b >> 8) as u8,
+            (b & 0xff) as u8,
+        ]
+    }
+
+    fn run_store(version: u8, mut code: Vec<u8>) -> u16 {
+        code.push(G00);
+        let mut p = processor(version, &code);
+        p.step().unwrap();
+        p.read_variable(Variable::Global(0)).unwrap()
+    }
+
+    fn run_branch(version: u8, mut code: Vec<u8>) -> bool {
+        code.push(BRANCH);
+        let len = code.len();
+        let mut p = processor(version, &code);
+        p.step().unwrap();
+        let pc = usize::from(p.pc.offset());
+        if pc == CODE + len + 3 {
+            true
+        } else {
+            assert_eq!(CODE + len, pc);
+            false
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        const ADD: u8 = 0x14;
+        const SUB: u8 = 0x15;
+        const MUL: u8 = 0x16;
+        const DIV: u8 = 0x17;
+        const MOD: u8 = 0x18;
+
+        let cases = [
+            (ADD, 1, 2, 3),
+            (ADD, 0x7fff, 1, 0x8000),
+            (ADD, 0xffff, 1, 0),
+            (ADD, neg(5), 3, neg(2)),
+            (SUB, 3, 2, 1),
+            (SUB, 0, 1, 0xffff),
+            (SUB, 0x8000, 1, 0x7fff),
+            (MUL, 6, 7, 42),
+            (MUL, 0x100, 0x100, 0),
+            (MUL, neg(3), 5, neg(15)),
+            (MUL, neg(3), neg(5), 15),
+            (DIV, 7, 2, 3),
+            (DIV, neg(7), 2, neg(3)),
+            (DIV, 7, neg(2), neg(3)),
+            (DIV, neg(7), neg(2), 3),
+            (DIV, 0x8000, neg(1), 0x8000),
+            (MOD, 7, 2, 1),
+            (MOD, neg(7), 2, neg(1)),
+            (MOD, 7, neg(2), 1),
+            (MOD, neg(7), neg(2), neg(1)),
+            (MOD, 0x8000, neg(1), 0),
+        ];
+
+        for (number, a, b, expected) in cases.iter() {
+            for version in [3, 5].iter() {
+                assert_eq!(
+                    *expected,
+                    run_store(*version, op2(*number, *a, *b)),
+                    "opcode 0x{:02x} with {:04x}, {:04x} in V{}",
+                    number,
+                    a,
+                    b,
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        for number in [0x17, 0x18].iter() {
+            let mut code = op2(*number, 5, 0);
+            code.push(G00);
+            let mut p = processor(3, &code);
+            let err = p.step().unwrap_err();
+            assert!(format!("{}", err).contains("PC:0x400"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_logic() {
+        const OR: u8 = 0x08;
+        const AND: u8 = 0x09;
+
+        let cases = [
+            (OR, 0x0f0f, 0x00ff, 0x0fff),
+            (OR, 0, 0, 0),
+            (AND, 0x0f0f, 0x00ff, 0x000f),
+            (AND, 0xffff, 0x1234, 0x1234),
+        ];
+        for (number, a, b, expected) in cases.iter() {
+            assert_eq!(*expected, run_store(3, op2(*number, *a, *b)));
+        }
+
+        // not is 1OP in V1-4...
+        assert_eq!(0xedcb, run_store(3, vec![0x8f, 0x12, 0x34]));
+        // ...and VAR in V5+.
+        assert_eq!(0xedcb, run_store(5, vec![0xf8, 0b00_11_11_11, 0x12, 0x34]));
+    }
+
+    #[test]
+    fn test_shifts() {
+        const LOG_SHIFT: u8 = 0x02;
+        const ART_SHIFT: u8 = 0x03;
+
+        let cases = [
+            (LOG_SHIFT, 1, 3, 8),
+            (LOG_SHIFT, 0x8001, 1, 0x0002),
+            (LOG_SHIFT, 0x8000, neg(1), 0x4000),
+            (LOG_SHIFT, 0xffff, neg(15), 0x0001),
+            (LOG_SHIFT, 0xffff, 16, 0),
+            (LOG_SHIFT, 0xffff, neg(16), 0),
+            (ART_SHIFT, 1, 3, 8),
+            (ART_SHIFT, 0x8000, neg(1), 0xc000),
+            (ART_SHIFT, 0x4000, neg(2), 0x1000),
+            (ART_SHIFT, 0x8000, neg(20), 0xffff),
+            (ART_SHIFT, 0x4000, neg(20), 0),
+        ];
+        for (number, a, b, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                run_store(5, ext(*number, *a, *b)),
+                "opcode 0x{:02x} with {:04x}, {:04x}",
+                number,
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn test_comparisons() {
+        const JE: u8 = 0x01;
+        const JL: u8 = 0x02;
+        const JG: u8 = 0x03;
+        const TEST: u8 = 0x07;
+
+        let cases = [
+            (JE, 5, 5, true),
+            (JE, 5, 6, false),
+            (JL, 5, 6, true),
+            (JL, 6, 5, false),
+            (JL, 5, 5, false),
+            (JL, neg(1), 0, true),
+            (JG, 6, 5, true),
+            (JG, 5, 5, false),
+            (JG, 0, neg(1), true),
+            (JG, 0x8000, 0x7fff, false),
+            (TEST, 0b1110, 0b0110, true),
+            (TEST, 0b1010, 0b0110, false),
+            (TEST, 0, 0, true),
+        ];
+        for (number, a, b, expected) in cases.iter() {
+            assert_eq!(
+                *expected,
+                run_branch(3, op2(*number, *a, *b)),
+                "opcode 0x{:02x} with {:04x}, {:04x}",
+                number,
+                a,
+                b
+            );
+        }
+
+        // jz #0000, jz #0001
+        assert!(run_branch(3, vec![0x80, 0x00, 0x00]));
+        assert!(!run_branch(3, vec![0x80, 0x00, 0x01]));
+    }
+
+    #[test]
+    fn test_je_many_operands() {
+        // je #01 #02 #03 #04
+        let je = |last: u8| vec![0xc1, 0b01_01_01_01, 0x01, 0x02, 0x03, last];
+        assert!(run_branch(3, je(0x01)));
+        assert!(!run_branch(3, je(0x05)));
+
+        // je #01 #02 #01 (three operands)
+        assert!(run_branch(3, vec![0xc1, 0b01_01_01_11, 0x01, 0x02, 0x01]));
+    }
+
+    #[test]
+    fn test_branch_sense_and_return() {
+        // je #01 #02 ?~+5 - branch on false.
+        let mut p = processor(3, &[0x01, 0x01, 0x02, 0x45]);
+        p.step().unwrap();
+        assert_eq!(CODE + 4 + 3, usize::from(p.pc.offset()));
+
+        // je #01 #01 ?rtrue - returns from the current routine.
+        let mut p = processor(3, &[0x01, 0x01, 0x01, 0xc1]);
+        p.stack
+            .push_frame(
+                Frame::new(
+                    ZOffset::from(0x500usize),
+                    Some(Variable::Global(0)),
+                    0,
+                    vec![],
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        p.step().unwrap();
+        assert_eq!(0x500, usize::from(p.pc.offset()));
+        assert_eq!(1, p.read_variable(Variable::Global(0)).unwrap());
+    }
+
+    #[test]
+    fn test_inc_dec() {
+        let cases = [
+            // inc G00
+            (vec![0x95, G00], 5, 6),
+            (vec![0x95, G00], 0xffff, 0),
+            (vec![0x95, G00], 0x7fff, 0x8000),
+            // dec G00
+            (vec![0x96, G00], 5, 4),
+            (vec![0x96, G00], 0, 0xffff),
+        ];
+        for (code, start, expected) in cases.iter() {
+            let mut p = processor(3, code);
+            p.write_variable(Variable::Global(0), *start).unwrap();
+            p.step().unwrap();
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_inc_dec_stack_in_place() {
+        // inc sp
+        let mut p = processor(3, &[0x95, 0x00]);
+        p.stack.push(5);
+        p.step().unwrap();
+        assert_eq!(6, p.stack.pop().unwrap());
+        assert!(p.stack.pop().is_err());
+    }
+
+    #[test]
+    fn test_inc_chk_dec_chk() {
+        const DEC_CHK: u8 = 0x04;
+        const INC_CHK: u8 = 0x05;
+
+        let cases = [
+            (INC_CHK, 5, 5, 6, true),
+            (INC_CHK, 4, 5, 5, false),
+            (INC_CHK, neg(2), neg(2), neg(1), true),
+            (DEC_CHK, 5, 5, 4, true),
+            (DEC_CHK, 6, 5, 5, false),
+            (DEC_CHK, 0, neg(1), neg(1), false),
+        ];
+        for (number, start, check, expected, taken) in cases.iter() {
+            let mut code = op2(*number, u16::from(G00), *check);
+            code.push(BRANCH);
+            let len = code.len();
+            let mut p = processor(3, &code);
+            p.write_variable(Variable::Global(0), *start).unwrap();
+            p.step().unwrap();
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+            let dest = if *taken { CODE + len + 3 } else { CODE + len };
+            assert_eq!(dest, usize::from(p.pc.offset()));
+        }
+    }
+
+    const ROUTINE: usize = 0x500;
+
+    fn place(p: &mut ZProcessor<TestMemory, TestTerminal>, at: usize, bytes: &[u8]) {
+        for (idx, byte) in bytes.iter().enumerate() {
+            p.memory
+                .write_byte_unchecked(ZOffset::from(at + idx), *byte)
+                .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_call_v3() {
+        // call $0280 #11 #22 -> G00
+        let mut p = processor(3, &[0xe0, 0b00_01_01_11, 0x02, 0x80, 0x11, 0x22, G00]);
+        // Three locals with initial values 1, 2, 3.
+        // add L01 L03 -> sp
+        // ret_popped
+        place(
+            &mut p,
+            ROUTINE,
+            &[3, 0, 1, 0, 2, 0, 3, 0x74, 0x01, 0x03, 0x00, 0xb8],
+        );
+
+        p.step().unwrap();
+        assert_eq!(ROUTINE + 7, usize::from(p.pc.offset()));
+        assert_eq!(0x11, p.stack.read_local(1).unwrap());
+        assert_eq!(0x22, p.stack.read_local(2).unwrap());
+        assert_eq!(3, p.stack.read_local(3).unwrap());
+        assert_eq!(2, p.stack.arg_count());
+
+        p.step().unwrap();
+        p.step().unwrap();
+        assert_eq!(CODE + 7, usize::from(p.pc.offset()));
+        assert_eq!(0x14, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(1, p.stack.depth());
+    }
+
+    #[test]
+    fn test_call_vn_v5() {
+        // call_vn $0140 #11
+        let mut p = processor(5, &[0xf9, 0b00_01_11_11, 0x01, 0x40, 0x11]);
+        // Two locals, zeroed. Then rtrue.
+        place(&mut p, ROUTINE, &[2, 0xb0]);
+
+        p.step().unwrap();
+        assert_eq!(ROUTINE + 1, usize::from(p.pc.offset()));
+        assert_eq!(0x11, p.stack.read_local(1).unwrap());
+        assert_eq!(0, p.stack.read_local(2).unwrap());
+
+        p.step().unwrap();
+        assert_eq!(CODE + 5, usize::from(p.pc.offset()));
+        // The result is thrown away.
+        assert!(p.stack.pop().is_err());
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+    }
+
+    #[test]
+    fn test_call_extra_args() {
+        // call_vs2 $0140 #1 #2 #3 #4 #5 #6 #7 -> G00, into a routine with 2 locals.
+        let mut p = processor(
+            5,
+            &[
+                0xec,
+                0b00_01_01_01,
+                0b01_01_01_01,
+                0x01,
+                0x40,
+                1,
+                2,
+                3,
+                4,
+                5,
+                6,
+                7,
+                G00,
+            ],
+        );
+        place(&mut p, ROUTINE, &[2, 0xb8]);
+
+        p.step().unwrap();
+        assert_eq!(1, p.stack.read_local(1).unwrap());
+        assert_eq!(2, p.stack.read_local(2).unwrap());
+        assert!(p.stack.read_local(3).is_err());
+        assert_eq!(7, p.stack.arg_count());
+    }
+
+    #[test]
+    fn test_call_zero() {
+        // call_1s #0000 -> G00
+        let mut p = processor(5, &[0x88, 0x00, 0x00, G00]);
+        p.write_variable(Variable::Global(0), 99).unwrap();
+        p.step().unwrap();
+        assert_eq!(CODE + 4, usize::from(p.pc.offset()));
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(1, p.stack.depth());
+    }
+
+    #[test]
+    fn test_returns() {
+        let cases = [
+            // rtrue
+            (vec![0xb0], 1),
+            // rfalse
+            (vec![0xb1], 0),
+            // ret #1234
+            (vec![0x8b, 0x12, 0x34], 0x1234),
+            // add L01 #00 -> sp; ret_popped
+            (vec![0x54, 0x01, 0x00, 0x00, 0xb8], 0x05),
+        ];
+
+        for (body, expected) in cases.iter() {
+            // call_2s $0140 #05 -> G00
+            let mut p = processor(5, &[0xd9, 0b00_01_11_11, 0x01, 0x40, 0x05, G00]);
+            p.write_variable(Variable::Global(0), 0xffff).unwrap();
+            let mut routine = vec![1];
+            routine.extend(body);
+            place(&mut p, ROUTINE, &routine);
+
+            while p.stack.depth() > 1 || usize::from(p.pc.offset()) == CODE {
+                p.step().unwrap();
+            }
+            assert_eq!(CODE + 6, usize::from(p.pc.offset()));
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_check_arg_count() {
+        for (count, expected) in [(1, 1), (2, 1), (3, 0)].iter() {
+            // call_vs $0140 #01 #02 -> G00
+            let mut p = processor(5, &[0xe0, 0b00_01_01_11, 0x01, 0x40, 0x01, 0x02, G00]);
+            // check_arg_count #count ?rtrue; rfalse
+            place(
+                &mut p,
+                ROUTINE,
+                &[3, 0xff, 0b01_11_11_11, *count, 0xc1, 0xb1],
+            );
+
+            p.step().unwrap();
+            p.step().unwrap();
+            if p.stack.depth() > 1 {
+                p.step().unwrap();
+            }
+            assert_eq!(CODE + 7, usize::from(p.pc.offset()));
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_stack_limit() {
+        // call_vn $0140, into a routine that calls itself forever.
+        let mut p = processor(5, &[0xf9, 0b00_11_11_11, 0x01, 0x40]);
+        place(&mut p, ROUTINE, &[0, 0xf9, 0b00_11_11_11, 0x01, 0x40]);
+        p.stack = ZStack::with_limit(10);
+
+        let err = loop {
+            if let Err(err) = p.step() {
+                break err;
+            }
+        };
+        assert_eq!(10, p.stack.depth());
+        assert!(format!("{:#}", err).contains("Stack overflow"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_return_from_main() {
+        let mut p = processor(5, &[0xb0]);
+        assert!(p.step().is_err());
+    }
+
+    #[test]
+    fn test_catch_throw() {
+        // call_vs $0140 -> G00
+        let mut p = processor(5, &[0xe0, 0b00_11_11_11, 0x01, 0x40, G00]);
+        // 0x500: catch -> L01
+        //        call_vn $0148 L01
+        //        rfalse
+        place(
+            &mut p,
+            ROUTINE,
+            &[1, 0xb9, 0x01, 0xf9, 0b00_10_11_11, 0x01, 0x48, 0x01, 0xb1],
+        );
+        // 0x520: call_vn $014c L01
+        place(
+            &mut p,
+            ROUTINE + 0x20,
+            &[1, 0xf9, 0b00_10_11_11, 0x01, 0x4c, 0x01],
+        );
+        // 0x530: add #40 #02 -> sp; throw #99 L01
+        place(
+            &mut p,
+            ROUTINE + 0x30,
+            &[1, 0x14, 40, 2, 0x00, 0x3c, 99, 0x01],
+        );
+
+        // Run until the throw returns to main.
+        for _ in 0..6 {
+            p.step().unwrap();
+        }
+        assert_eq!(CODE + 5, usize::from(p.pc.offset()));
+        assert_eq!(1, p.stack.depth());
+        assert_eq!(99, p.read_variable(Variable::Global(0)).unwrap());
+    }
+
+    #[test]
+    fn test_throw_to_dead_frame() {
+        // call_vs $0140 -> G00; throw #1 G00
+        let mut p = processor(5, &[0xe0, 0b00_11_11_11, 0x01, 0x40, G00, 0x3c, 0x01, G00]);
+        // catch -> sp; ret_popped
+        place(&mut p, ROUTINE, &[0, 0xb9, 0x00, 0xb8]);
+
+        for _ in 0..3 {
+            p.step().unwrap();
+        }
+        let err = p.step().unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("no longer exists"),
+            "{:#}",
+            err
+        );
+        assert!(format!("{:#}", err).contains("PC:0x405"), "{:#}", err);
+    }
+
+    const OBJECTS: usize = 0x230;
+
+    // V3 object table with three objects: 1 is the parent of 2 and 3.
+    // Object 1 has attribute 0. Object 2 is named "abc", and has properties
+    // 5 (0x1234) and 3 (0x56). The default for property 7 is 0x777.
+    fn object_processor(code: &[u8]) -> ZProcessor<TestMemory, TestTerminal> {
+        let mut p = processor(3, code);
+        place(
+            &mut p,
+            OBJECT_TABLE_START,
+            &[(OBJECTS >> 8) as u8, (OBJECTS & 0xff) as u8],
+        );
+        let entries = OBJECTS + 62;
+        let props = entries + 3 * 9;
+        let (props_hi, props_lo) = ((props >> 8) as u8, (props & 0xff) as u8);
+        let entry = |obj: usize| entries + (obj - 1) * 9;
+        place(
+            &mut p,
+            entry(1),
+            &[0x80, 0, 0, 0, 0, 0, 2, props_hi, props_lo],
+        );
+        place(
+            &mut p,
+            entry(2),
+            &[0, 0, 0, 0, 1, 3, 0, props_hi, props_lo + 3],
+        );
+        place(&mut p, entry(3), &[0, 0, 0, 0, 1, 0, 0, props_hi, props_lo]);
+        place(
+            &mut p,
+            props,
+            &[
+                0,
+                0,
+                0,
+                1,
+                0b1001_1000,
+                0b1110_1000,
+                0x25,
+                0x12,
+                0x34,
+                0x03,
+                0x56,
+                0,
+            ],
+        );
+        place(&mut p, OBJECTS + 2 * 6, &[0x07, 0x77]);
+        p.objects = ZObjectTable::new(&p.memory, p.version).unwrap();
+        p
+    }
+
+    #[test]
+    fn test_object_links() {
+        // get_parent #2 -> G00
+        let mut p = object_processor(&[0x93, 2, G00]);
+        p.step().unwrap();
+        assert_eq!(1, p.read_variable(Variable::Global(0)).unwrap());
+
+        // get_child #1 -> G00 ?+5
+        let mut p = object_processor(&[0x92, 1, G00, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(2, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + 4 + 3, usize::from(p.pc.offset()));
+
+        // get_child #3 -> G00 ?+5
+        let mut p = object_processor(&[0x92, 3, G00, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + 4, usize::from(p.pc.offset()));
+
+        // get_sibling #2 -> G00 ?+5
+        let mut p = object_processor(&[0x91, 2, G00, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(3, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + 4 + 3, usize::from(p.pc.offset()));
+
+        // jin #2 #1 ?+5
+        let mut p = object_processor(&[0x06, 2, 1, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(CODE + 4 + 3, usize::from(p.pc.offset()));
+
+        // jin #1 #2 ?+5
+        let mut p = object_processor(&[0x06, 1, 2, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(CODE + 4, usize::from(p.pc.offset()));
+    }
+
+    #[test]
+    fn test_object_tree_ops() {
+        // insert_obj #3 #2; remove_obj #2
+        let mut p = object_processor(&[0x0e, 3, 2, 0x99, 2]);
+        p.step().unwrap();
+        assert_eq!(2, p.objects.parent(&p.memory, 3).unwrap());
+        assert_eq!(3, p.objects.child(&p.memory, 2).unwrap());
+        assert_eq!(2, p.objects.child(&p.memory, 1).unwrap());
+        assert_eq!(0, p.objects.sibling(&p.memory, 2).unwrap());
+
+        p.step().unwrap();
+        assert_eq!(0, p.objects.parent(&p.memory, 2).unwrap());
+        assert_eq!(0, p.objects.child(&p.memory, 1).unwrap());
+        // Children move with their parent.
+        assert_eq!(3, p.objects.child(&p.memory, 2).unwrap());
+    }
+
+    #[test]
+    fn test_attribute_ops() {
+        // test_attr #1 #0 ?+5
+        let mut p = object_processor(&[0x0a, 1, 0, BRANCH]);
+        p.step().unwrap();
+        assert_eq!(CODE + 4 + 3, usize::from(p.pc.offset()));
+
+        // set_attr #3 #5; clear_attr #1 #0
+        let mut p = object_processor(&[0x0b, 3, 5, 0x0c, 1, 0]);
+        p.step().unwrap();
+        p.step().unwrap();
+        assert!(p.objects.attribute(&p.memory, 3, 5).unwrap());
+        assert!(!p.objects.attribute(&p.memory, 1, 0).unwrap());
+    }
+
+    #[test]
+    fn test_property_ops() {
+        // get_prop #2 #5 -> G00
+        let mut p = object_processor(&[0x11, 2, 5, G00]);
+        p.step().unwrap();
+        assert_eq!(0x1234, p.read_variable(Variable::Global(0)).unwrap());
+
+        // get_prop #2 #7 -> G00
+        let mut p = object_processor(&[0x11, 2, 7, G00]);
+        p.step().unwrap();
+        assert_eq!(0x777, p.read_variable(Variable::Global(0)).unwrap());
+
+        // put_prop #2 #3 #0x99
+        let mut p = object_processor(&[0xe3, 0x57, 2, 3, 0x99]);
+        p.step().unwrap();
+        assert_eq!(0x99, p.objects.property(&p.memory, 2, 3).unwrap());
+
+        // get_prop_addr #2 #3 -> G00; get_prop_len G00 -> G00
+        let mut p = object_processor(&[0x12, 2, 3, G00, 0xa4, G00, G00]);
+        p.step().unwrap();
+        let props = OBJECTS + 62 + 3 * 9;
+        assert_eq!(
+            props + 10,
+            usize::from(p.read_variable(Variable::Global(0)).unwrap())
+        );
+        p.step().unwrap();
+        assert_eq!(1, p.read_variable(Variable::Global(0)).unwrap());
+
+        // get_prop_addr #2 #7 -> G00; get_prop_len G00 -> G00
+        let mut p = object_processor(&[0x12, 2, 7, G00, 0xa4, G00, G00]);
+        p.step().unwrap();
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        p.step().unwrap();
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+    }
+
+    #[test]
+    fn test_get_next_prop() {
+        let cases = [(0, 5), (5, 3), (3, 0)];
+        for (prop, next) in cases.iter() {
+            // get_next_prop #2 #prop -> G00
+            let mut p = object_processor(&[0x13, 2, *prop, G00]);
+            p.step().unwrap();
+            assert_eq!(*next, p.read_variable(Variable::Global(0)).unwrap());
+        }
+
+        // Object 1 has no properties.
+        let mut p = object_processor(&[0x13, 1, 0, G00]);
+        p.step().unwrap();
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+
+        // get_next_prop #2 #7 -> G00
+        let mut p = object_processor(&[0x13, 2, 7, G00]);
+        assert!(p.step().is_err());
+    }
+
+    #[test]
+    fn test_print() {
+        // print "abc"; new_line; print_paddr $0280; print_addr $0200
+        let mut p = processor(
+            3,
+            &[0xb2, 0x98, 0xe8, 0xbb, 0x8d, 0x02, 0x80, 0x87, 0x02, 0x00],
+        );
+        place(&mut p, ROUTINE, &[0b1001_0011, 0b1110_1000]);
+        place(&mut p, 0x200, &[0b1001_1000, 0b1000_0110]);
+        for _ in 0..4 {
+            p.step().unwrap();
+        }
+        assert_eq!("abc\nZcaA", p.terminal.output);
+        assert_eq!(CODE + 10, usize::from(p.pc.offset()));
+    }
+
+    #[test]
+    fn test_encode_text() {
+        // encode_text $0200 #3 #1 $0210
+        let mut p = processor(5, &[0xfc, 0x14, 0x02, 0x00, 3, 1, 0x02, 0x10]);
+        place(&mut p, 0x200, b"xabc");
+        p.step().unwrap();
+        // [6, 7, 8], [5, 5, 5], [5, 5, 5]
+        for (idx, byte) in [0x18, 0xe8, 0x14, 0xa5, 0x94, 0xa5].iter().enumerate() {
+            assert_eq!(
+                *byte,
+                p.memory.read_byte(ZOffset::from(0x210 + idx)).unwrap()
+            );
+        }
+    }
+
+    // A processor whose dictionary, at $0180, has the one word "go", with a text
+    // buffer of `text` at $0100 and a parse buffer with room for 4 words at $0140.
+    fn read_processor(
+        version: u8,
+        code: &[u8],
+        text: &[u8],
+    ) -> ZProcessor<TestMemory, TestTerminal> {
+        let mut p = processor(version, code);
+        p.memory
+            .write_word_unchecked(DICTIONARY_START, 0x180)
+            .unwrap();
+        place(
+            &mut p,
+            0x180,
+            &dictionary_bytes(version, "", &["go"], false),
+        );
+        place(&mut p, 0x100, text);
+        place(&mut p, 0x140, &[4]);
+        p
+    }
+
+    fn bytes_at(p: &ZProcessor<TestMemory, TestTerminal>, at: usize, len: usize) -> Vec<u8> {
+        (at..at + len)
+            .map(|offset| p.memory.read_byte(ZOffset::from(offset)).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenise() {
+        fn setup(code: &[u8]) -> ZProcessor<TestMemory, TestTerminal> {
+            let mut p = read_processor(5, code, b"\x14\x05go up");
+            place(&mut p, 0x146, &[0xaa; 4]);
+            p
+        }
+        fn block(p: &ZProcessor<TestMemory, TestTerminal>, idx: usize) -> Vec<u8> {
+            bytes_at(p, 0x142 + idx * 4, 4)
+        }
+
+        // tokenise $0100 $0140 $0180 #1 - the unknown word's block is left alone. The
+        // header has no dictionary, so only the one given can be used.
+        let mut p = setup(&[0xfb, 0b00_00_00_01, 1, 0, 1, 0x40, 1, 0x80, 1]);
+        p.memory.write_word_unchecked(DICTIONARY_START, 0).unwrap();
+        p.step().unwrap();
+        assert_eq!(vec![2], bytes_at(&p, 0x141, 1));
+        assert_eq!(vec![0x01, 0x84, 2, 2], block(&p, 0));
+        assert_eq!(vec![0xaa; 4], block(&p, 1));
+
+        // tokenise $0100 $0140 - with the game's dictionary, from the header.
+        let mut p = setup(&[0xfb, 0b00_00_11_11, 1, 0, 1, 0x40]);
+        p.step().unwrap();
+        assert_eq!(vec![0x01, 0x84, 2, 2], block(&p, 0));
+        assert_eq!(vec![0, 0, 2, 5], block(&p, 1));
+    }
+
+    #[test]
+    fn test_sread() {
+        // sread $0100 $0140
+        let code = [0xe4, 0b00_00_11_11, 1, 0, 1, 0x40];
+        let mut p = read_processor(3, &code, &[21]);
+        p.terminal.input.push_back(TestInput::Line("Go  UP", 13));
+        p.step().unwrap();
+        assert_eq!(b"go  up\0".to_vec(), bytes_at(&p, 0x101, 7));
+        assert_eq!(
+            vec![2, 0x01, 0x84, 2, 1, 0, 0, 2, 5],
+            bytes_at(&p, 0x141, 9)
+        );
+        assert_eq!(CODE + code.len(), usize::from(p.pc.offset()));
+
+        // Room for only 3 characters, and the zero.
+        let mut p = read_processor(3, &code, &[4]);
+        p.terminal.input.push_back(TestInput::Line("Go UP", 13));
+        p.step().unwrap();
+        assert_eq!(b"go \0".to_vec(), bytes_at(&p, 0x101, 4));
+    }
+
+    #[test]
+    fn test_aread() {
+        // aread $0100 #0 -> G00
+        let code = [0xe4, 0b00_01_11_11, 1, 0, 0, G00];
+
+        // Preloaded input, which the player continues.
+        let mut p = read_processor(5, &code, b"\x14\x03go ");
+        p.terminal.input.push_back(TestInput::Line("UP", 13));
+        p.step().unwrap();
+        assert_eq!(b"\x05go up".to_vec(), bytes_at(&p, 0x101, 6));
+        assert_eq!(13, p.read_variable(Variable::Global(0)).unwrap());
+        // No parse buffer, so it is untouched.
+        assert_eq!(vec![4, 0], bytes_at(&p, 0x140, 2));
+
+        // Any function key ends input when the table holds 255.
+        let mut p = read_processor(5, &code, &[20, 0]);
+        p.memory
+            .write_word_unchecked(TERMINATING_CHARS_TABLE, 0x1c0)
+            .unwrap();
+        place(&mut p, 0x1c0, &[255, 0]);
+        p.terminal.input.push_back(TestInput::Line("look", 133));
+        p.step().unwrap();
+        assert_eq!(b"\x04look".to_vec(), bytes_at(&p, 0x101, 5));
+        assert_eq!(133, p.read_variable(Variable::Global(0)).unwrap());
+
+        // Function keys not in the table don't end input.
+        let mut p = read_processor(5, &code, &[20, 0]);
+        p.terminal.input.push_back(TestInput::Line("look", 133));
+        assert!(p.step().is_err());
+    }
+
+    #[test]
+    fn test_read_interrupts() {
+        // aread $0100 #0 #5 $0140 -> G00, calling the routine every half second.
+        let code = [0xe4, 0b00_01_01_00, 1, 0, 0, 5, 0x01, 0x40, G00];
+
+        // inc G01; rfalse - input carries on.
+        let mut p = read_processor(5, &code, &[20, 0]);
+        place(&mut p, ROUTINE, &[0, 0x95, 0x11, 0xb1]);
+        p.terminal.input.push_back(TestInput::Timeout("go"));
+        p.terminal.input.push_back(TestInput::Timeout(" n"));
+        p.terminal.input.push_back(TestInput::Line("orth", 13));
+        assert!(p.step().unwrap());
+        assert_eq!(2, p.read_variable(Variable::Global(1)).unwrap());
+        assert_eq!(b"\x08go north".to_vec(), bytes_at(&p, 0x101, 9));
+        assert_eq!(13, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + code.len(), usize::from(p.pc.offset()));
+        assert_eq!(1, p.stack.depth());
+        assert!(p.stack.pop().is_err());
+
+        // inc G01; rtrue - input stops, keeping what was typed.
+        let mut p = read_processor(5, &code, &[20, 0]);
+        place(&mut p, ROUTINE, &[0, 0x95, 0x11, 0xb0]);
+        p.terminal.input.push_back(TestInput::Timeout("go"));
+        assert!(p.step().unwrap());
+        assert_eq!(1, p.read_variable(Variable::Global(1)).unwrap());
+        assert_eq!(b"\x02go".to_vec(), bytes_at(&p, 0x101, 3));
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + code.len(), usize::from(p.pc.offset()));
+
+        // quit
+        let mut p = read_processor(5, &code, &[20, 0]);
+        place(&mut p, ROUTINE, &[0, 0xba]);
+        p.terminal.input.push_back(TestInput::Timeout("go"));
+        assert!(!p.step().unwrap());
+    }
+
+    #[test]
+    fn test_read_char() {
+        // read_char #1 -> G00
+        let code = [0xf6, 0b01_11_11_11, 1, G00];
+        let cases = [
+            (Key::Char('a'), u16::from(b'a')),
+            (Key::Char('ä'), 155),
+            // No ZSCII code.
+            (Key::Char('€'), u16::from(b'?')),
+            (Key::Special(ZSCII::from(129)), 129),
+            (Key::Special(ZSCII::from(27)), 27),
+        ];
+        for (key, expected) in cases.iter() {
+            let mut p = processor(5, &code);
+            p.terminal.keys.push_back(Some(*key));
+            assert!(p.step().unwrap());
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+            assert_eq!(CODE + code.len(), usize::from(p.pc.offset()));
+        }
+    }
+
+    #[test]
+    fn test_read_char_interrupts() {
+        // read_char #1 #3 $0140 -> G00
+        let code = [0xf6, 0b01_01_00_11, 1, 3, 0x01, 0x40, G00];
+
+        // inc G01; rfalse - keep waiting.
+        let mut p = processor(5, &code);
+        place(&mut p, ROUTINE, &[0, 0x95, 0x11, 0xb1]);
+        p.terminal
+            .keys
+            .extend(vec![None, None, Some(Key::Char('y'))]);
+        assert!(p.step().unwrap());
+        assert_eq!(2, p.read_variable(Variable::Global(1)).unwrap());
+        assert_eq!(
+            u16::from(b'y'),
+            p.read_variable(Variable::Global(0)).unwrap()
+        );
+        assert_eq!(CODE + code.len(), usize::from(p.pc.offset()));
+
+        // inc G01; rtrue - stop waiting.
+        let mut p = processor(5, &code);
+        p.write_variable(Variable::Global(0), 0xffff).unwrap();
+        place(&mut p, ROUTINE, &[0, 0x95, 0x11, 0xb0]);
+        p.terminal.keys.extend(vec![None, Some(Key::Char('y'))]);
+        assert!(p.step().unwrap());
+        assert_eq!(1, p.read_variable(Variable::Global(1)).unwrap());
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(1, p.terminal.keys.len());
+
+        // Without a timer, a timeout is an error.
+        let mut p = processor(5, &[0xf6, 0b01_11_11_11, 1, G00]);
+        p.terminal.keys.push_back(None);
+        assert!(p.step().is_err());
+    }
+
+    #[test]
+    fn test_unicode_ops() {
+        // print_unicode $20ac; print_unicode $0007
+        let mut p = processor(5, &[0xbe, 0x0b, 0x3f, 0x20, 0xac, 0xbe, 0x0b, 0x3f, 0, 7]);
+        p.step().unwrap();
+        p.step().unwrap();
+        assert_eq!("€?", p.terminal.output);
+
+        // check_unicode $xxxx -> G00
+        let cases = [
+            (0xe4, 3),
+            (u16::from(b'a'), 3),
+            (0x20ac, 1),
+            (0xd800, 0),
+            (7, 0),
+        ];
+        for (code, expected) in cases.iter() {
+            let mut p = processor(
+                5,
+                &[
+                    0xbe,
+                    0x0c,
+                    0x3f,
+                    (code >> 8) as u8,
+                    (code & 0xff) as u8,
+                    G00,
+                ],
+            );
+            p.step().unwrap();
+            assert_eq!(*expected, p.read_variable(Variable::Global(0)).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_print_ret() {
+        // call $0280 -> G00
+        let mut p = processor(3, &[0xe0, 0b00_11_11_11, 0x02, 0x80, G00]);
+        // print_ret "abc"
+        place(&mut p, ROUTINE, &[0, 0xb3, 0x98, 0xe8]);
+        p.step().unwrap();
+        p.step().unwrap();
+        assert_eq!("abc\n", p.terminal.output);
+        assert_eq!(1, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(CODE + 5, usize::from(p.pc.offset()));
+    }
+
+    #[test]
+    fn test_verify() {
+        let verify = |checksum: u16, scribble: bool| {
+            // verify ?(+5)
+            let mut p = processor(3, &[0xbd, BRANCH]);
+            p.memory.write_word_unchecked(CHECKSUM, checksum).unwrap();
+            if scribble {
+                // Changes to dynamic memory don't count.
+                p.write_variable(Variable::Global(0), 0x1234).unwrap();
+            }
+            p.step().unwrap();
+            usize::from(p.pc.offset()) == CODE + 5
+        };
+
+        // The code bytes are the only non-zero bytes after the header.
+        let sum = 0xbd + u16::from(BRANCH);
+        assert!(verify(sum, false));
+        assert!(verify(sum, true));
+        assert!(!verify(sum + 1, false));
+    }
+
+    #[test]
+    fn test_header_warning() {
+        // The game may not change the version number. Whatever the next instruction
+        // does, the warning is passed on to the terminal once it has run.
+        let mut code = op2(0x14, 1, 2);
+        code.push(G00);
+        let mut p = processor(3, &code);
+        p.memory.write_byte(0usize.into(), 5).unwrap();
+        p.step().unwrap();
+        assert_eq!(3, p.memory.read_byte(0usize.into()).unwrap());
+        assert_eq!(
+            vec!["Ignoring write to read-only header bits 0x06 at ZO:0x0".to_string()],
+            p.terminal.warnings
+        );
+        assert!(p.memory.take_warnings().is_empty());
+        assert!(p.terminal.output.is_empty());
+    }
+
+    #[test]
+    fn test_restart() {
+        // The interpreter fills in the header when the processor is created.
+        let mut p = processor(3, &[0xb7]);
+        assert_ne!(
+            0,
+            Header::new(&p.memory).flags1().unwrap() & STATUS_LINE_UNAVAILABLE
+        );
+        assert_eq!(1, p.memory.read_byte(STANDARD_REVISION.into()).unwrap());
+
+        p.write_variable(Variable::Global(0), 5).unwrap();
+        p.memory
+            .write_word(FLAGS2, TRANSCRIPTING | FORCE_FIXED_PITCH)
+            .unwrap();
+        p.memory.write_byte_unchecked(FLAGS1.into(), 0).unwrap();
+        p.stack.push(7);
+        p.pc.set(CODE);
+
+        // restart
+        p.step().unwrap();
+        assert_eq!(0, p.read_variable(Variable::Global(0)).unwrap());
+        assert_eq!(
+            TRANSCRIPTING | FORCE_FIXED_PITCH,
+            Header::new(&p.memory).flags2().unwrap()
+        );
+        assert_ne!(
+            0,
+            Header::new(&p.memory).flags1().unwrap() & STATUS_LINE_UNAVAILABLE
+        );
+        assert!(p.stack.pop().is_err());
+        assert_eq!(CODE, usize::from(p.pc.offset()));
+    }
+
+    #[test]
+    fn test_start_v6() {
+        // The main routine is at $0500: 2 locals, then restart.
+        let mut p = processor_with(6, &[], |bytes| {
+            bytes[0x500..0x502].copy_from_slice(&[2, 0xb7]);
+            put_word(bytes, ROUTINES_OFFSET, 0x10);
+            // ZSpec 1.2.3 - 4 * $0120 + 8 * $10 = $0500.
+            put_word(bytes, START_PC, 0x120);
+        });
+
+        p.start().unwrap();
+        assert_eq!(0x501, usize::from(p.pc.offset()));
+        assert_eq!(1, p.stack.depth());
+        assert_eq!(0, p.read_variable(Variable::Local(2)).unwrap());
+        assert!(p.read_variable(Variable::Local(3)).is_err());
+
+        // restart - back to the start of the main routine, with fresh locals.
+        p.write_variable(Variable::Local(1), 5).unwrap();
+        p.stack.push(7);
+        p.step().unwrap();
+        assert_eq!(0x501, usize::from(p.pc.offset()));
+        assert_eq!(0, p.read_variable(Variable::Local(1)).unwrap());
+        assert!(p.stack.pop().is_err());
+
+        // A main routine outside the story.
+        p.memory.write_word_unchecked(START_PC, 0xffff).unwrap();
+        assert!(p.start().is_err());
+    }
+
+    #[test]
+    fn test_print_obj() {
+        // print_obj #2; print_obj #1
+        let mut p = object_processor(&[0x9a, 2, 0x9a, 1]);
+        p.step().unwrap();
+        p.step().unwrap();
+        assert_eq!("abc", p.terminal.output);
+    }
+
+    #[test]
+    fn test_bad_objects() {
+        let cases = [
+            // get_parent #0 -> G00
+            (vec![0x93, 0, G00], 0),
+            // get_child #4 -> G00 ?+5
+            (vec![0x92, 4, G00, BRANCH], 4),
+            // insert_obj #0 #1
+            (vec![0x0e, 0, 1], 0),
+            // insert_obj #1 #0
+            (vec![0x0e, 1, 0], 0),
+            // insert_obj #1 #9
+            (vec![0x0e, 1, 9], 9),
+            // remove_obj #0
+            (vec![0x99, 0], 0),
+            // jin #0 #1 ?+5
+            (vec![0x06, 0, 1, BRANCH], 0),
+            // jin #1 #9 ?+5
+            (vec![0x06, 1, 9, BRANCH], 9),
+            // test_attr #0 #1 ?+5
+            (vec![0x0a, 0, 1, BRANCH], 0),
+            // set_attr #9 #1
+            (vec![0x0b, 9, 1], 9),
+            // print_obj #0
+            (vec![0x9a, 0], 0),
+            // get_prop #0 #5 -> G00
+            (vec![0x11, 0, 5, G00], 0),
+            // put_prop #2 #7 #1
+            (vec![0xe3, 0x57, 2, 7, 1], 2),
+        ];
+        for (code, obj) in cases.iter() {
+            let mut p = object_processor(code);
+            let err = format!("{:#}", p.step().unwrap_err());
+            assert!(err.contains(&format!("object {}", obj)), "{}", err);
+            assert!(err.contains("PC:0x400"), "{}", err);
+        }
     }
 }
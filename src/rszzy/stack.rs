@@ -0,0 +1,431 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::traits::Memory;
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+
+/// ZSpec 5.2 - a routine may have at most 15 local variables.
+pub const MAX_LOCALS: usize = 15;
+
+/// ZSpec 4.2.2 - a variable number, resolved to the kind of storage it refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    /// 0x00 - the top of the evaluation stack of the current routine.
+    Stack,
+    /// 0x01-0x0f - local variables 1-15 of the current routine.
+    Local(u8),
+    /// 0x10-0xff - global variables 0-239.
+    Global(u8),
+}
+
+impl From<u8> for Variable {
+    fn from(num: u8) -> Variable {
+        match num {
+            0x00 => Variable::Stack,
+            0x01..=0x0f => Variable::Local(num),
+            _ => Variable::Global(num - 0x10),
+        }
+    }
+}
+
+/// ZSpec 6.3, 6.4 - the state of a single routine call.
+#[derive(Debug, Default)]
+pub struct Frame {
+    /// Where execution resumes when this routine returns.
+    pub return_pc: ZOffset,
+
+    /// Where the return value goes. None if the result is thrown away.
+    pub store: Option<Variable>,
+
+    /// The number of arguments supplied by the caller. (Needed for check_arg_count.)
+    pub arg_count: u8,
+
+    /// Identifies the frame for catch/throw. Assigned by ZStack::push_frame.
+    id: u16,
+
+    locals: Vec<u16>,
+    eval: Vec<u16>,
+}
+
+impl Frame {
+    #[throws]
+    pub fn new(
+        return_pc: ZOffset,
+        store: Option<Variable>,
+        arg_count: u8,
+        locals: Vec<u16>,
+    ) -> Frame {
+        ensure!(
+            locals.len() <= MAX_LOCALS,
+            anyhow!(
+                "Routine has {} locals, but the maximum is {}",
+                locals.len(),
+                MAX_LOCALS
+            )
+        );
+        Frame {
+            return_pc,
+            store,
+            arg_count,
+            id: 0,
+            locals,
+            eval: Vec::new(),
+        }
+    }
+}
+
+/// Default maximum number of routine frames. Deeply recursive games fail with
+/// an error when they exceed this, rather than exhausting the host's memory.
+pub const DEFAULT_FRAME_LIMIT: usize = 1024;
+
+/// ZSpec 6.3 - the stack of routine frames, each with its own evaluation stack.
+///
+/// The bottom frame represents the "main routine" and cannot be popped.
+pub struct ZStack {
+    frames: Vec<Frame>,
+    frame_limit: usize,
+
+    // The id to give to the next frame pushed.
+    next_id: u16,
+}
+
+impl Default for ZStack {
+    fn default() -> ZStack {
+        ZStack::with_limit(DEFAULT_FRAME_LIMIT)
+    }
+}
+
+impl ZStack {
+    /// `frame_limit` is the maximum number of routine frames, including the main routine.
+    pub fn with_limit(frame_limit: usize) -> ZStack {
+        let main = Frame {
+            id: 1,
+            ..Frame::default()
+        };
+        ZStack {
+            frames: vec![main],
+            frame_limit,
+            next_id: 2,
+        }
+    }
+
+    fn frame(&self) -> &Frame {
+        // There is always at least one frame.
+        self.frames.last().unwrap()
+    }
+
+    fn frame_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Discard every frame but a fresh main routine, as for @restart.
+    pub fn reset(&mut self) {
+        *self = ZStack::with_limit(self.frame_limit);
+    }
+
+    /// ZSpec 5.5.1 - as `reset`, but the main routine is a real routine with `locals`,
+    /// as in V6.
+    #[throws]
+    pub fn reset_with_locals(&mut self, locals: Vec<u16>) {
+        let main = Frame::new(ZOffset::default(), None, 0, locals)?;
+        self.reset();
+        self.frames[0] = Frame { id: 1, ..main };
+    }
+
+    /// The number of routine frames, including the main routine.
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn arg_count(&self) -> u8 {
+        self.frame().arg_count
+    }
+
+    #[throws]
+    pub fn push_frame(&mut self, frame: Frame) {
+        ensure!(
+            self.frames.len() < self.frame_limit,
+            anyhow!(
+                "Stack overflow: routine calls nested more than {} deep",
+                self.frame_limit
+            )
+        );
+        let id = self.next_id;
+        // Ids wrap around, skipping 0, so that a stale catch value will only
+        // match a live frame after 65535 intervening calls.
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+        self.frames.push(Frame { id, ..frame });
+    }
+
+    /// ZSpec 6.6 - the identifier of the current frame, as returned by @catch.
+    pub fn frame_id(&self) -> u16 {
+        self.frame().id
+    }
+
+    /// ZSpec 6.6 - discard frames until the frame with `id` is the current frame.
+    /// Fails, leaving the stack unchanged, if there is no such frame.
+    #[throws]
+    pub fn unwind_to(&mut self, id: u16) {
+        let idx = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.id == id)
+            .ok_or_else(|| {
+                anyhow!(
+                    "@throw to frame {}, which no longer exists (current frame is {})",
+                    id,
+                    self.frame_id()
+                )
+            })?;
+        self.frames.truncate(idx + 1);
+    }
+
+    #[throws]
+    pub fn pop_frame(&mut self) -> Frame {
+        ensure!(
+            self.frames.len() > 1,
+            anyhow!("Attempt to return from the main routine")
+        );
+        // Just checked that there is a frame to pop.
+        self.frames.pop().unwrap()
+    }
+
+    pub fn push(&mut self, val: u16) {
+        self.frame_mut().eval.push(val);
+    }
+
+    #[throws]
+    pub fn pop(&mut self) -> u16 {
+        self.frame_mut()
+            .eval
+            .pop()
+            .ok_or_else(|| anyhow!("Stack underflow"))?
+    }
+
+    /// ZSpec 6.3.4 - indirect references to the stack read the top value in place.
+    #[throws]
+    pub fn peek(&self) -> u16 {
+        *self
+            .frame()
+            .eval
+            .last()
+            .ok_or_else(|| anyhow!("Stack underflow"))?
+    }
+
+    /// ZSpec 6.3.4 - indirect references to the stack write the top value in place.
+    #[throws]
+    pub fn poke(&mut self, val: u16) {
+        let top = self
+            .frame_mut()
+            .eval
+            .last_mut()
+            .ok_or_else(|| anyhow!("Stack underflow"))?;
+        *top = val;
+    }
+
+    /// `num` is the variable number, 1-15.
+    #[throws]
+    pub fn read_local(&self, num: u8) -> u16 {
+        let idx = self.local_index(num)?;
+        self.frame().locals[idx]
+    }
+
+    /// `num` is the variable number, 1-15.
+    #[throws]
+    pub fn write_local(&mut self, num: u8, val: u16) {
+        let idx = self.local_index(num)?;
+        self.frame_mut().locals[idx] = val;
+    }
+
+    #[throws]
+    fn local_index(&self, num: u8) -> usize {
+        let count = self.frame().locals.len();
+        ensure!(
+            num >= 1 && usize::from(num) <= count,
+            anyhow!(
+                "Local variable {} does not exist in a routine with {} locals",
+                num,
+                count
+            )
+        );
+        usize::from(num - 1)
+    }
+}
+
+/// ZSpec 5.1, 5.2 - reads the routine header at `offset`.
+/// Returns the initial values of the locals and the offset of the first instruction.
+/// Locals are initialized from the header in V1-4, and to zero in V5+.
+#[throws]
+pub fn read_routine_header<M>(memory: &M, version: &Version, offset: ZOffset) -> (Vec<u16>, ZOffset)
+where
+    M: Memory,
+{
+    let count = usize::from(memory.read_code_byte(offset)?);
+    ensure!(
+        count <= MAX_LOCALS,
+        anyhow!(
+            "Routine at {} declares {} locals, but the maximum is {}",
+            offset,
+            count,
+            MAX_LOCALS
+        )
+    );
+
+    if version.initial_locals {
+        let mut locals = Vec::with_capacity(count);
+        for idx in 0..count {
+            locals.push(memory.read_code_word(offset + 1 + 2 * idx)?);
+        }
+        (locals, offset + 1 + 2 * count)
+    } else {
+        (vec![0; count], offset + 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::traits::test::TestMemory;
+    use crate::rszzy::versions::number_to_version;
+
+    #[test]
+    fn test_variable() {
+        assert_eq!(Variable::Stack, Variable::from(0x00));
+        assert_eq!(Variable::Local(1), Variable::from(0x01));
+        assert_eq!(Variable::Local(15), Variable::from(0x0f));
+        assert_eq!(Variable::Global(0), Variable::from(0x10));
+        assert_eq!(Variable::Global(239), Variable::from(0xff));
+    }
+
+    #[test]
+    fn test_eval_stack() {
+        let mut stack = ZStack::default();
+        assert!(stack.pop().is_err());
+        assert!(stack.peek().is_err());
+
+        stack.push(3);
+        stack.push(4);
+        assert_eq!(4, stack.peek().unwrap());
+        stack.poke(5).unwrap();
+        assert_eq!(5, stack.pop().unwrap());
+        assert_eq!(3, stack.pop().unwrap());
+        assert!(stack.pop().is_err());
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut stack = ZStack::default();
+        assert!(stack.pop_frame().is_err());
+        assert!(stack.read_local(1).is_err());
+
+        stack.push(99);
+        stack
+            .push_frame(
+                Frame::new(
+                    ZOffset::from(0x1234usize),
+                    Some(Variable::Stack),
+                    1,
+                    vec![7, 8],
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        assert_eq!(2, stack.depth());
+        assert_eq!(1, stack.arg_count());
+
+        // Each frame has its own evaluation stack.
+        assert!(stack.pop().is_err());
+
+        assert_eq!(7, stack.read_local(1).unwrap());
+        assert_eq!(8, stack.read_local(2).unwrap());
+        assert!(stack.read_local(3).is_err());
+        assert!(stack.read_local(0).is_err());
+        stack.write_local(2, 88).unwrap();
+        assert_eq!(88, stack.read_local(2).unwrap());
+
+        let frame = stack.pop_frame().unwrap();
+        assert_eq!(0x1234, usize::from(frame.return_pc));
+        assert_eq!(Some(Variable::Stack), frame.store);
+        assert_eq!(99, stack.pop().unwrap());
+    }
+
+    #[test]
+    fn test_frame_ids() {
+        let mut stack = ZStack::default();
+        let main_id = stack.frame_id();
+
+        stack.push_frame(Frame::default()).unwrap();
+        let first_id = stack.frame_id();
+        stack.push(1);
+        stack.push_frame(Frame::default()).unwrap();
+        stack.push_frame(Frame::default()).unwrap();
+        assert_eq!(4, stack.depth());
+
+        assert_ne!(main_id, first_id);
+        assert_ne!(first_id, stack.frame_id());
+
+        stack.unwind_to(first_id).unwrap();
+        assert_eq!(2, stack.depth());
+        assert_eq!(first_id, stack.frame_id());
+        assert_eq!(1, stack.pop().unwrap());
+
+        // Once a frame is popped, its id no longer refers to anything.
+        stack.pop_frame().unwrap();
+        let err = stack.unwind_to(first_id).unwrap_err();
+        assert!(format!("{}", err).contains("no longer exists"));
+        assert_eq!(1, stack.depth());
+
+        // A new frame at the same depth gets a new id.
+        stack.push_frame(Frame::default()).unwrap();
+        assert_ne!(first_id, stack.frame_id());
+    }
+
+    #[test]
+    fn test_frame_limit() {
+        let mut stack = ZStack::with_limit(3);
+        assert!(stack.push_frame(Frame::default()).is_ok());
+        assert!(stack.push_frame(Frame::default()).is_ok());
+        assert!(stack.push_frame(Frame::default()).is_err());
+        assert_eq!(3, stack.depth());
+
+        stack.pop_frame().unwrap();
+        assert!(stack.push_frame(Frame::default()).is_ok());
+    }
+
+    #[test]
+    fn test_too_many_locals() {
+        assert!(Frame::new(ZOffset::from(0usize), None, 0, vec![0; 16]).is_err());
+    }
+
+    #[test]
+    fn test_routine_header() {
+        let memory = TestMemory::new(vec![0, 2, 0x12, 0x34, 0x56, 0x78, 0xb0], 0, 0);
+
+        let (locals, start) = read_routine_header(
+            &memory,
+            number_to_version(3).unwrap(),
+            ZOffset::from(1usize),
+        )
+        .unwrap();
+        assert_eq!(vec![0x1234, 0x5678], locals);
+        assert_eq!(6, usize::from(start));
+
+        let (locals, start) = read_routine_header(
+            &memory,
+            number_to_version(5).unwrap(),
+            ZOffset::from(1usize),
+        )
+        .unwrap();
+        assert_eq!(vec![0, 0], locals);
+        assert_eq!(2, usize::from(start));
+
+        let memory = TestMemory::new(vec![16], 0, 0);
+        assert!(read_routine_header(
+            &memory,
+            number_to_version(5).unwrap(),
+            ZOffset::from(0usize)
+        )
+        .is_err());
+    }
+}
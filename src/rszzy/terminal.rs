@@ -0,0 +1,385 @@
+use crate::rszzy::keys::zscii_key::{DELETE, ESCAPE, NEWLINE};
+use crate::rszzy::keys::{decode_key, Decoded, Key, ScriptKey, ESC};
+use crate::rszzy::text::ZSCII;
+use crate::rszzy::traits::Terminal;
+use anyhow::{anyhow, Error};
+use fehler::{throw, throws};
+use std::collections::VecDeque;
+use std::io::{stderr, stdin, stdout, IsTerminal, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for the rest of an escape sequence before deciding that the
+/// player pressed escape.
+const ESCAPE_WAIT: Duration = Duration::from_millis(50);
+
+/// Concrete Terminal that prints to stdout, or another output. Input comes from the
+/// keyboard, or from a script when playing headless.
+pub struct ZTerminal {
+    input: Input,
+    output: Box<dyn Write>,
+}
+
+enum Input {
+    Keyboard(Keyboard),
+    Script(VecDeque<ScriptKey>),
+}
+
+impl Default for ZTerminal {
+    fn default() -> ZTerminal {
+        ZTerminal {
+            input: Input::Keyboard(Keyboard::default()),
+            output: Box::new(stdout()),
+        }
+    }
+}
+
+impl ZTerminal {
+    /// A terminal which plays the keys in `script` rather than reading the keyboard.
+    pub fn scripted(script: VecDeque<ScriptKey>) -> ZTerminal {
+        ZTerminal {
+            input: Input::Script(script),
+            output: Box::new(stdout()),
+        }
+    }
+
+    /// Print to `output` rather than stdout.
+    pub fn with_output(self, output: Box<dyn Write>) -> ZTerminal {
+        ZTerminal { output, ..self }
+    }
+}
+
+impl Terminal for ZTerminal {
+    #[throws]
+    fn print(&mut self, text: &str) {
+        self.output.write_all(text.as_bytes())?;
+        self.output.flush()?;
+    }
+
+    /// Warnings go to stderr, so that they stay out of a transcript of the game.
+    #[throws]
+    fn warn(&mut self, warning: &str) {
+        writeln!(stderr(), "Warning: {}", warning)?;
+    }
+
+    /// From the keyboard, stdin is line buffered, so input can only end with newline,
+    /// and text typed before a timeout is kept by the terminal rather than in `line`.
+    /// Any text already in `line` is taken to be on the screen, with the player typing
+    /// after it.
+    #[throws]
+    fn read_line(
+        &mut self,
+        line: &mut String,
+        max_len: usize,
+        terminators: &[ZSCII],
+        timeout: Option<Duration>,
+    ) -> Option<ZSCII> {
+        match &mut self.input {
+            Input::Keyboard(keyboard) => {
+                let typed = match keyboard.read_line(timeout)? {
+                    Some(typed) => typed,
+                    None => return None,
+                };
+                let room = max_len.saturating_sub(line.chars().count());
+                line.extend(typed.chars().take(room));
+                Some(ZSCII::from(NEWLINE))
+            }
+            Input::Script(script) => {
+                let key = script_line(script, line, max_len, terminators, timeout)?;
+                // Show the input, as the keyboard would.
+                if let Some(key) = key {
+                    self.print(line)?;
+                    if key.code() == NEWLINE {
+                        self.print("\n")?;
+                    }
+                }
+                key
+            }
+        }
+    }
+
+    #[throws]
+    fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key> {
+        match &mut self.input {
+            Input::Keyboard(keyboard) => keyboard.read_key(timeout)?,
+            Input::Script(script) => loop {
+                match script.pop_front() {
+                    Some(ScriptKey::Key(key)) => break Some(key),
+                    Some(ScriptKey::Wait) if timeout.is_some() => break None,
+                    // Nothing to wait for.
+                    Some(ScriptKey::Wait) => {}
+                    None => throw!(anyhow!("End of script")),
+                }
+            },
+        }
+    }
+}
+
+/// Play keys from `script` into `line` until one ends input, as the player would
+/// type them.
+#[throws]
+fn script_line(
+    script: &mut VecDeque<ScriptKey>,
+    line: &mut String,
+    max_len: usize,
+    terminators: &[ZSCII],
+    timeout: Option<Duration>,
+) -> Option<ZSCII> {
+    loop {
+        match script.pop_front() {
+            Some(ScriptKey::Key(Key::Char(ch))) => {
+                if line.chars().count() < max_len {
+                    line.push(ch);
+                }
+            }
+            Some(ScriptKey::Key(Key::Special(zscii))) => match zscii.code() {
+                NEWLINE => break Some(zscii),
+                DELETE => {
+                    line.pop();
+                }
+                _ if terminators.contains(&zscii) => break Some(zscii),
+                // Other keys do nothing while typing a line.
+                _ => {}
+            },
+            Some(ScriptKey::Wait) if timeout.is_some() => break None,
+            Some(ScriptKey::Wait) => {}
+            None => throw!(anyhow!("End of script")),
+        }
+    }
+}
+
+/// stdin, read on another thread so that reads can time out. Started by the first
+/// read.
+#[derive(Default)]
+struct Keyboard {
+    chunks: Option<Receiver<std::io::Result<Vec<u8>>>>,
+    // Bytes received but not yet used.
+    pending: Vec<u8>,
+}
+
+impl Keyboard {
+    /// Wait for more bytes. Returns false if `timeout` passes first.
+    #[throws]
+    fn receive(&mut self, timeout: Option<Duration>) -> bool {
+        let chunks = self.chunks.get_or_insert_with(|| {
+            let (sender, receiver) = channel();
+            thread::spawn(move || {
+                let mut buffer = [0; 256];
+                loop {
+                    let chunk = match stdin().read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(len) => Ok(buffer[..len].to_vec()),
+                        Err(err) => Err(err),
+                    };
+                    if sender.send(chunk).is_err() {
+                        break;
+                    }
+                }
+            });
+            receiver
+        });
+
+        let chunk = match timeout {
+            Some(timeout) => match chunks.recv_timeout(timeout) {
+                Ok(chunk) => chunk,
+                Err(RecvTimeoutError::Timeout) => return false,
+                Err(RecvTimeoutError::Disconnected) => throw!(anyhow!("End of input")),
+            },
+            None => chunks.recv().map_err(|_| anyhow!("End of input"))?,
+        }?;
+        self.pending.extend(chunk);
+        true
+    }
+
+    #[throws]
+    fn read_line(&mut self, timeout: Option<Duration>) -> Option<String> {
+        loop {
+            if let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+                let line = String::from_utf8_lossy(&self.pending[..end])
+                    .trim_end_matches('\r')
+                    .to_string();
+                self.pending.drain(..=end);
+                break Some(line);
+            }
+            if !self.receive(timeout)? {
+                break None;
+            }
+        }
+    }
+
+    #[throws]
+    fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key> {
+        let _raw = RawMode::enter()?;
+        loop {
+            match decode_key(&self.pending) {
+                Decoded::Key(key, len) => {
+                    self.pending.drain(..len);
+                    break Some(key);
+                }
+                Decoded::Ignored(len) => {
+                    self.pending.drain(..len);
+                }
+                Decoded::Incomplete if self.pending.is_empty() => {
+                    if !self.receive(timeout)? {
+                        break None;
+                    }
+                }
+                // The start of an escape sequence, or of a character, whose rest
+                // may not have arrived yet.
+                Decoded::Incomplete => {
+                    if !self.receive(Some(ESCAPE_WAIT))? {
+                        let first = self.pending.remove(0);
+                        if first == ESC {
+                            break Some(Key::Special(ZSCII::from(ESCAPE)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// While this lives, the terminal sends each key as it is pressed, without echoing
+/// it, and the keypad sends its own escape sequences. Does nothing if stdin is not
+/// a terminal.
+struct RawMode {
+    saved: Option<String>,
+}
+
+impl RawMode {
+    #[throws]
+    fn enter() -> RawMode {
+        if !stdin().is_terminal() {
+            return RawMode { saved: None };
+        }
+        let saved = stty(&["-g"])?;
+        stty(&["-icanon", "-echo", "min", "1"])?;
+        // DECKPAM - application keypad.
+        print!("\x1b=");
+        stdout().flush()?;
+        RawMode {
+            saved: Some(saved.trim().to_string()),
+        }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Some(saved) = &self.saved {
+            // DECKPNM - normal keypad.
+            print!("\x1b>");
+            let _ = stdout().flush();
+            let _ = stty(&[saved]);
+        }
+    }
+}
+
+#[throws]
+fn stty(args: &[&str]) -> String {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        throw!(anyhow!(
+            "stty failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::keys::parse_script;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // Output which the test can read back after the terminal has printed it.
+    #[derive(Clone, Default)]
+    struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.borrow().clone()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn scripted(script: &str) -> (ZTerminal, Output) {
+        let output = Output::default();
+        let terminal = ZTerminal::scripted(parse_script(script).unwrap())
+            .with_output(Box::new(output.clone()));
+        (terminal, output)
+    }
+
+    #[test]
+    fn test_script_lines() {
+        let (mut terminal, output) = scripted("go nx{del}orth\nlook{f1}{wait}abc\n");
+        let mut line = String::new();
+        assert_eq!(
+            Some(ZSCII::from(NEWLINE)),
+            terminal.read_line(&mut line, 20, &[], None).unwrap()
+        );
+        assert_eq!("go north", line);
+
+        // F1 ends input only when it is a terminator.
+        let f1 = ZSCII::from(133);
+        let mut line = String::new();
+        assert_eq!(
+            Some(f1),
+            terminal.read_line(&mut line, 20, &[f1], None).unwrap()
+        );
+        assert_eq!("look", line);
+
+        // Waiting times out, and typing carries on afterwards, up to the limit.
+        let mut line = String::new();
+        let timeout = Some(Duration::from_secs(1));
+        assert_eq!(
+            None,
+            terminal.read_line(&mut line, 2, &[], timeout).unwrap()
+        );
+        assert_eq!(
+            Some(ZSCII::from(NEWLINE)),
+            terminal.read_line(&mut line, 2, &[], timeout).unwrap()
+        );
+        assert_eq!("ab", line);
+
+        // Each line is shown as it was typed, with a newline only when that ended it.
+        assert_eq!("go north\nlookab\n", output.text());
+
+        assert!(terminal.read_line(&mut line, 2, &[], None).is_err());
+    }
+
+    #[test]
+    fn test_script_keys() {
+        let (mut terminal, output) = scripted("y{wait}{up}");
+        assert_eq!(Some(Key::Char('y')), terminal.read_key(None).unwrap());
+        let timeout = Some(Duration::from_secs(1));
+        assert_eq!(None, terminal.read_key(timeout).unwrap());
+        assert_eq!(
+            Some(Key::Special(ZSCII::from(129))),
+            terminal.read_key(None).unwrap()
+        );
+        assert!(terminal.read_key(None).is_err());
+        // Keys aren't shown.
+        assert_eq!("", output.text());
+
+        // Without a timer, there is nothing to wait for.
+        let (mut terminal, _) = scripted("{wait}n");
+        assert_eq!(Some(Key::Char('n')), terminal.read_key(None).unwrap());
+    }
+}
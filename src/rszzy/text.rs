@@ -1,67 +1,390 @@
-use std::char::{decode_utf16, REPLACEMENT_CHARACTER};
-
-/// ZSpec 3.5.3 - Basic alphabet table for V2+.
-const V2_ALPHA_TABLE: &[u8] =
-    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ \n0123456789.,!?_#'\"/\\-:()";
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::constants::header_offset::ALPHABET_TABLE_START;
+use crate::rszzy::traits::{AbbrevTable, Memory};
+use crate::rszzy::unicode::ZUnicodeTable;
+use crate::rszzy::versions::Version;
+use anyhow::{anyhow, Error, Result};
+use fehler::{throw, throws};
+use std::convert::TryFrom;
+
+/// ZSpec 3.5.3 - the default alphabets A0, A1 and A2 for V2+.
+/// Z-char 6 in A2 is the 10-bit escape, so the table has a placeholder for it.
+/// Z-char 7 in A2 is a newline, which is ZSCII 13 ('\r').
+pub const DEFAULT_ALPHABETS: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ \r0123456789.,!?_#'\"/\\-:()";
+
+/// ZSpec 3.5.4 - V1 has no newline in A2, but has '<'.
+pub const V1_ALPHABETS: &[u8] =
+    b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ 0123456789.,!?_#'\"/\\<-:()";
+
+/// ZSpec 3.8.5.3 - the default Unicode translations of the extra characters, ZSCII 155-223.
+pub const DEFAULT_EXTRA_CHARS: &[char] = &[
+    'ä', 'ö', 'ü', 'Ä', 'Ö', 'Ü', 'ß', '»', '«', 'ë', 'ï', 'ÿ', 'Ë', 'Ï', 'á', 'é', 'í', 'ó', 'ú',
+    'ý', 'Á', 'É', 'Í', 'Ó', 'Ú', 'Ý', 'à', 'è', 'ì', 'ò', 'ù', 'À', 'È', 'Ì', 'Ò', 'Ù', 'â', 'ê',
+    'î', 'ô', 'û', 'Â', 'Ê', 'Î', 'Ô', 'Û', 'å', 'Å', 'ø', 'Ø', 'ã', 'ñ', 'õ', 'Ã', 'Ñ', 'Õ', 'æ',
+    'Æ', 'ç', 'Ç', 'þ', 'ð', 'Þ', 'Ð', '£', 'œ', 'Œ', '¡', '¿',
+];
 
 /// ZSpec 3.8 - A ZSCII character.
 /// ZSCII defines 10-bit characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ZSCII(u16);
 
+impl From<u16> for ZSCII {
+    fn from(code: u16) -> ZSCII {
+        ZSCII(code)
+    }
+}
+
+impl ZSCII {
+    pub fn code(self) -> u16 {
+        self.0
+    }
+
+    /// ZSpec 3.8, Table 2 - true if the game may print this character.
+    pub fn is_valid_output(self) -> bool {
+        matches!(self.0, 0 | 9 | 11 | 13 | 32..=126 | 155..=251)
+    }
+
+    /// ZSpec 3.8, Table 2 - true if the keyboard may produce this character.
+    /// (Delete, escape, cursor and function keys, and mouse clicks are input only.)
+    pub fn is_valid_input(self) -> bool {
+        matches!(self.0, 8 | 13 | 27 | 32..=126 | 129..=154 | 155..=251 | 252..=254)
+    }
+
+    /// ZSpec 3.8 - the character to print for this ZSCII code, if it has one.
+    /// ZSCII 0 is a valid output character, but prints nothing.
+    pub fn to_char(self) -> Option<char> {
+        match self.0 {
+            // ZSpec 3.8.2.2 - tab. ZSpec 3.8.2.3 - sentence space.
+            9 => Some('\t'),
+            11 => Some(' '),
+            13 => Some('\n'),
+            32..=126 => Some(char::from(self.0 as u8)),
+            155..=223 => Some(DEFAULT_EXTRA_CHARS[usize::from(self.0 - 155)]),
+            // 224-251 are undefined unless the game provides a Unicode translation table.
+            _ => None,
+        }
+    }
+}
+
+/// ZSpec 3.5 - the three alphabets, A0-A2, which vary by version and may be
+/// supplied by the game.
+struct Alphabet {
+    default: &'static [u8],
+    // ZSpec 3.5.5 - V5+ games may supply their own alphabet table.
+    custom: Option<ZOffset>,
+}
+
+impl Alphabet {
+    #[throws]
+    fn new(memory: &impl Memory, version: &Version) -> Alphabet {
+        let custom = if version.custom_alphabet {
+            match memory.read_word(ZOffset::from(ALPHABET_TABLE_START))? {
+                0 => None,
+                addr => Some(ZOffset::from(addr)),
+            }
+        } else {
+            None
+        };
+        Alphabet {
+            default: version.alphabets,
+            custom,
+        }
+    }
+
+    /// The character for z-char `zc` (6-31) in alphabet `charset`.
+    #[throws]
+    fn zscii(&self, memory: &impl Memory, charset: u8, zc: u8) -> ZSCII {
+        let idx = 26 * usize::from(charset) + usize::from(zc - 6);
+        match self.custom {
+            // ZSpec 3.5.5.1 - A2 z-char 7 is always a newline, even in a custom table.
+            Some(_) if charset == 2 && zc == 7 => ZSCII(13),
+            Some(table) => ZSCII(u16::from(memory.read_code_byte(table + idx)?)),
+            None => ZSCII(u16::from(self.default[idx])),
+        }
+    }
+
+    /// The alphabet and z-char for `zscii`, or None if no alphabet has it.
+    #[throws]
+    fn find(&self, memory: &impl Memory, zscii: ZSCII) -> Option<(u8, u8)> {
+        for charset in 0..3 {
+            for zc in 6..32 {
+                // ZSpec 3.4 - A2 z-char 6 is the 10-bit escape, not a character.
+                if charset == 2 && zc == 6 {
+                    continue;
+                }
+                if self.zscii(memory, charset, zc)? == zscii {
+                    return Some((charset, zc));
+                }
+            }
+        }
+        None
+    }
+}
+
 /// A ZString is a sequence of ZSCII characters.
 /// To minimize copying, ZString is implemented as an Iterator. If a String is desired,
-/// use String::from().
-pub struct ZString<'a> {
+/// use String::try_from(), or ZUnicodeTable::decode() for games with their own
+/// Unicode translation table.
+///
+/// Decoding can fail (e.g., on a malformed abbreviation), so the Iterator yields Results.
+/// After the first error, the Iterator is done.
+pub struct ZString<'a, M, A> {
+    memory: &'a M,
+    version: &'a Version,
+    abbrevs: &'a A,
+
+    alphabet: Alphabet,
+
     zchars: ZCharIter<'a>,
+    // ZSpec 3.3 - the expansion of the abbreviation being read, if any, along with
+    // the shift-lock state to restore when it ends.
+    abbrev: Option<(ZCharIter<'a>, u8)>,
+
+    // ZSpec 3.2.2 - V1-2 have shift-lock, so the alphabet in use after a shift may not be A0.
+    locked_charset: u8,
     active_charset: u8,
+    failed: bool,
 }
 
-impl<'a> ZString<'a> {
+impl<'a, M, A> ZString<'a, M, A>
+where
+    M: Memory,
+    A: AbbrevTable,
+{
     /// Creates a new ZString from the bytes in the slice.
-    pub fn new(buf: &[u8]) -> ZString {
+    /// Abbreviations are looked up in `abbrevs` and read from `memory`.
+    #[throws]
+    pub fn new(
+        memory: &'a M,
+        version: &'a Version,
+        abbrevs: &'a A,
+        buf: &'a [u8],
+    ) -> ZString<'a, M, A> {
         ZString {
+            memory,
+            version,
+            abbrevs,
+            alphabet: Alphabet::new(memory, version)?,
             zchars: ZCharIter::new(buf),
+            abbrev: None,
 
+            locked_charset: 0,
             active_charset: 0,
+            failed: false,
+        }
+    }
+
+    /// Creates a new ZString from the bytes at `offset`. The string ends at the
+    /// first word with its stop bit set.
+    #[throws]
+    pub fn at(
+        memory: &'a M,
+        version: &'a Version,
+        abbrevs: &'a A,
+        offset: ZOffset,
+    ) -> ZString<'a, M, A> {
+        ZString::new(memory, version, abbrevs, slice_at(memory, offset)?)?
+    }
+
+    fn next_zchar(&mut self) -> Option<u8> {
+        if let Some((abbrev, locked_charset)) = &mut self.abbrev {
+            if let Some(zc) = abbrev.next() {
+                return Some(zc);
+            }
+            // ZSpec 3.7 - a shift left at the end is padding, and does not carry
+            // over into the text that follows the abbreviation.
+            self.locked_charset = *locked_charset;
+            self.active_charset = self.locked_charset;
+            self.abbrev = None;
+        }
+        self.zchars.next()
+    }
+
+    /// The next z-char of a multi-z-char construction, which must come from the same
+    /// string as the start of the construction.
+    #[throws]
+    fn construction_zchar(&mut self) -> u8 {
+        match &mut self.abbrev {
+            // ZSpec 3.3.1 - an abbreviation may not end with an incomplete construction.
+            Some((abbrev, _)) => abbrev.next().ok_or_else(|| {
+                anyhow!("Abbreviation ends in the middle of a 10-bit ZSCII escape")
+            })?,
+            None => self
+                .zchars
+                .next()
+                .ok_or_else(|| anyhow!("String ends in the middle of a 10-bit ZSCII escape"))?,
         }
     }
+
+    /// ZSpec 3.3 - z-chars 1-3 are abbreviations in V3+, but only z-char 1 in V2.
+    fn is_abbreviation(&self, zc: u8) -> bool {
+        zc <= self.version.abbrev_zchars
+    }
+
+    #[throws]
+    fn start_abbreviation(&mut self, table: u8) {
+        // ZSpec 3.3.1 - abbreviations may not themselves contain abbreviations,
+        // and a string may not end partway through an abbreviation reference.
+        ensure!(
+            self.abbrev.is_none(),
+            anyhow!("Abbreviation contains another abbreviation")
+        );
+        let idx = self
+            .zchars
+            .next()
+            .ok_or_else(|| anyhow!("String ends in the middle of an abbreviation"))?;
+        let addr = self.abbrevs.abbrev_location(self.memory, table, idx)?;
+        let zchars = ZCharIter::new(slice_at(self.memory, addr.into())?);
+
+        // The abbreviation starts in A0. Shifts apply to the next character, not to
+        // an abbreviation, and the shift-lock state comes back when it ends.
+        self.abbrev = Some((zchars, self.locked_charset));
+        self.locked_charset = 0;
+        self.active_charset = 0;
+    }
+
+    /// ZSpec 3.2.2, 3.2.3 - z-chars 2-5 in V1-2, and 4-5 in V3+, change alphabet.
+    fn shift(&mut self, zc: u8) {
+        if self.version.shift_lock {
+            // Shifts and shift-locks move "up" one or two alphabets from the locked one.
+            match zc {
+                2 | 3 => self.active_charset = (self.locked_charset + zc - 1) % 3,
+                _ => {
+                    self.locked_charset = (self.locked_charset + zc - 3) % 3;
+                    self.active_charset = self.locked_charset;
+                }
+            }
+        } else {
+            self.active_charset = zc - 3;
+        }
+    }
+
+    #[throws]
+    fn next_zscii(&mut self) -> Option<ZSCII> {
+        while let Some(zc) = self.next_zchar() {
+            match zc {
+                0 => return Some(ZSCII(b' ' as u16)),
+                // ZSpec 3.5.2 - in V1, which has no abbreviations, z-char 1 is a newline.
+                1 if self.version.abbrev_zchars == 0 => return Some(ZSCII(13)),
+                1..=3 if self.is_abbreviation(zc) => self.start_abbreviation(zc)?,
+                // Any other z-char in this range is a shift.
+                2..=5 => self.shift(zc),
+                6 if self.active_charset == 2 => {
+                    // ZSpec 3.4 - the next two z-chars are the top and bottom
+                    // five bits of a 10-bit ZSCII code.
+                    self.active_charset = self.locked_charset;
+                    let high = u16::from(self.construction_zchar()?);
+                    let low = u16::from(self.construction_zchar()?);
+                    return Some(ZSCII((high << 5) | low));
+                }
+                6..=31 => {
+                    let zscii = self.alphabet.zscii(self.memory, self.active_charset, zc)?;
+                    self.active_charset = self.locked_charset;
+                    return Some(zscii);
+                }
+                _ => throw!(anyhow!("Z-char out of range: {}", zc)),
+            }
+        }
+        None
+    }
+}
+
+/// ZSpec 3.7 - encode `text` as a dictionary word: exactly 6 z-chars in V1-3, or 9 in V4+,
+/// truncated or padded with 5s, packed 3 to a word with the stop bit on the last word.
+#[throws]
+pub fn encode_text(memory: &impl Memory, version: &Version, text: &[ZSCII]) -> Vec<u8> {
+    let mut zchars = encode_zchars(memory, version, text)?;
+    zchars.resize(version.dictionary_zchars, 5);
+    pack_zchars(&zchars)
+}
+
+/// The z-chars for `text`, without truncation or padding. Characters outside the
+/// alphabets use the 10-bit escape. Never uses abbreviations or shift-lock.
+#[throws]
+fn encode_zchars(memory: &impl Memory, version: &Version, text: &[ZSCII]) -> Vec<u8> {
+    let alphabet = Alphabet::new(memory, version)?;
+    // ZSpec 3.2.2, 3.2.3 - the single shifts to A1 and A2.
+    let (a1, a2) = if version.shift_lock { (2, 3) } else { (4, 5) };
+
+    let mut zchars = Vec::with_capacity(text.len());
+    for &zscii in text {
+        match zscii.0 {
+            32 => zchars.push(0),
+            13 if version.abbrev_zchars == 0 => zchars.push(1),
+            code => match alphabet.find(memory, zscii)? {
+                Some((0, zc)) => zchars.push(zc),
+                Some((1, zc)) => zchars.extend_from_slice(&[a1, zc]),
+                Some((_, zc)) => zchars.extend_from_slice(&[a2, zc]),
+                None => zchars.extend_from_slice(&[
+                    a2,
+                    6,
+                    ((code >> 5) & 0x1f) as u8,
+                    (code & 0x1f) as u8,
+                ]),
+            },
+        }
+    }
+    zchars
 }
 
-impl From<ZString<'_>> for String {
-    fn from(zs: ZString) -> String {
-        decode_utf16(zs.map(|zc| zc.0))
-            .map(|r| r.unwrap_or(REPLACEMENT_CHARACTER))
-            .collect::<String>()
+/// ZSpec 3.2 - pack z-chars 3 to a word. `zchars.len()` must be a multiple of 3.
+fn pack_zchars(zchars: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(zchars.len() / 3 * 2);
+    for (idx, chunk) in zchars.chunks(3).enumerate() {
+        let mut word = chunk
+            .iter()
+            .fold(0u16, |word, zc| (word << 5) | u16::from(*zc & 0x1f));
+        if (idx + 1) * 3 >= zchars.len() {
+            word |= 0x8000;
+        }
+        bytes.push((word >> 8) as u8);
+        bytes.push((word & 0xff) as u8);
     }
+    bytes
 }
 
-impl Iterator for ZString<'_> {
-    type Item = ZSCII;
+// Encoded text may be in high memory, so this doesn't use Memory::read_byte.
+#[throws]
+fn slice_at(memory: &impl Memory, offset: ZOffset) -> &[u8] {
+    ensure!(
+        usize::from(offset) < memory.memory_size(),
+        anyhow!("String at {} starts past end of memory", offset)
+    );
+    memory.slice_at(offset)?
+}
+
+impl<M, A> TryFrom<ZString<'_, M, A>> for String
+where
+    M: Memory,
+    A: AbbrevTable,
+{
+    type Error = Error;
+
+    #[throws]
+    fn try_from(zs: ZString<M, A>) -> String {
+        ZUnicodeTable::default().decode(zs)?
+    }
+}
+
+impl<M, A> Iterator for ZString<'_, M, A>
+where
+    M: Memory,
+    A: AbbrevTable,
+{
+    type Item = Result<ZSCII>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            let n = self.zchars.next();
-            if let Some(zc) = n {
-                match zc {
-                    0 => return Some(ZSCII(b' ' as u16)),
-                    1..=3 => unimplemented!("abbrev table unimplemented"),
-                    4 => self.active_charset = 1,
-                    5 => self.active_charset = 2,
-                    6..=31 => {
-                        if zc == 6 && self.active_charset == 2 {
-                            unimplemented!("10-bit ZSCII unimplemented");
-                        }
-
-                        let idx: usize = (26 * self.active_charset + zc - 6) as usize;
-                        self.active_charset = 0;
-                        return Some(ZSCII(V2_ALPHA_TABLE[idx] as u16));
-                    }
-                    _ => {
-                        panic!("Some zchar out of range: {}", zc);
-                    }
-                }
-            } else {
-                return None;
+        if self.failed {
+            return None;
+        }
+        match self.next_zscii() {
+            Ok(zscii) => zscii.map(Ok),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
             }
         }
     }
@@ -122,8 +445,54 @@ impl<'a> Iterator for ZCharIter<'a> {
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
+    use crate::rszzy::abbrevs::ZAbbrevTable;
+    use crate::rszzy::traits::test::TestMemory;
+    use crate::rszzy::versions::number_to_version;
+    use proptest::prelude::*;
+
+    // Abbreviation table at 0x40, with the strings it points to from 0x60.
+    //   0: "the"
+    //   1: nested abbreviation
+    //   2: "aa", then a shift
+    //   3: an incomplete 10-bit escape
+    //  32: "x"
+    fn abbrev_memory() -> TestMemory {
+        let mut bytes = vec![0; 0x100];
+        bytes[0x18] = 0x00;
+        bytes[0x19] = 0x40;
+        let words: &[(usize, u16)] = &[
+            (0x40, 0x30),
+            (0x42, 0x32),
+            (0x44, 0x34),
+            (0x46, 0x38),
+            (0x40 + 2 * 32, 0x36),
+            (0x60, 0xe5aa),
+            (0x64, 0x8405),
+            (0x68, 0x98c4),
+            (0x6c, 0xf4a5),
+            (0x70, 0x94c2),
+        ];
+        for (at, val) in words {
+            bytes[*at] = (val >> 8) as u8;
+            bytes[at + 1] = (val & 0xff) as u8;
+        }
+        TestMemory::new(bytes, 0x80, 0x100)
+    }
+
+    fn decode_in(memory: &TestMemory, version: &Version, buf: &[u8]) -> anyhow::Result<String> {
+        let abbrevs = ZAbbrevTable::new(memory)?;
+        String::try_from(ZString::new(memory, version, &abbrevs, buf)?)
+    }
+
+    fn decode_with(memory: &TestMemory, buf: &[u8]) -> anyhow::Result<String> {
+        decode_in(memory, number_to_version(3).unwrap(), buf)
+    }
+
+    fn decode(buf: &[u8]) -> String {
+        decode_with(&abbrev_memory(), buf).unwrap()
+    }
 
     #[test]
     fn test_empty() {
@@ -187,47 +556,47 @@ mod test {
 
     #[test]
     fn test_empty_string() {
-        let zs = ZString::new(&[0b1001_0100, 0b1010_0101]); // [4, 4, 4]
-        assert_eq!("", String::from(zs));
+        let zs = decode(&[0b1001_0100, 0b1010_0101]); // [4, 4, 4]
+        assert_eq!("", zs);
     }
 
     #[test]
     fn test_aaa_string() {
-        let zs = ZString::new(&[0b1001_1000, 0b1100_0110]);
-        assert_eq!("aaa", String::from(zs));
+        let zs = decode(&[0b1001_1000, 0b1100_0110]);
+        assert_eq!("aaa", zs);
     }
 
     #[test]
     fn test_abc_string() {
-        let zs = ZString::new(&[0b1001_1000, 0b1110_1000]);
-        assert_eq!("abc", String::from(zs));
+        let zs = decode(&[0b1001_1000, 0b1110_1000]);
+        assert_eq!("abc", zs);
     }
 
     #[allow(non_snake_case)]
     #[test]
     fn test_aA_string() {
-        let zs = ZString::new(&[0b1001_1000, 0b1000_0110]);
-        assert_eq!("aA", String::from(zs));
+        let zs = decode(&[0b1001_1000, 0b1000_0110]);
+        assert_eq!("aA", zs);
     }
 
     #[allow(non_snake_case)]
     #[test]
     fn test_Zc_string() {
-        let zs = ZString::new(&[0b1001_0011, 0b1110_1000]);
-        assert_eq!("Zc", String::from(zs));
+        let zs = decode(&[0b1001_0011, 0b1110_1000]);
+        assert_eq!("Zc", zs);
     }
 
     #[allow(non_snake_case)]
     #[test]
     fn test_cSPACEEPOINT_string() {
-        let zs = ZString::new(&[0b0010_0000, 0b0000_0101, 0b1101_0000, 0b1000_0100]);
-        assert_eq!("c !", String::from(zs));
+        let zs = decode(&[0b0010_0000, 0b0000_0101, 0b1101_0000, 0b1000_0100]);
+        assert_eq!("c !", zs);
     }
 
     #[allow(non_snake_case)]
     #[test]
     fn test_Charlie_Brown_string() {
-        let zs = ZString::new(&[
+        let zs = decode(&[
             0b0001_0001,
             0b0000_1101, // Ch
             0b0001_1010,
@@ -241,6 +610,300 @@ mod test {
             0b1001_0110,
             0b1010_0100, // ?
         ]);
-        assert_eq!("Charlie Brown?", String::from(zs));
+        assert_eq!("Charlie Brown?", zs);
+    }
+
+    #[test]
+    fn test_abbrevs() {
+        // [1, 0, 0], [2, 0, 5]
+        assert_eq!("the x", decode(&[0x04, 0x00, 0x88, 0x05]));
+
+        // [1, 2, 6] - the shift padding the end of abbreviation 2 doesn't apply to the last "a".
+        assert_eq!("aaa", decode(&[0x84, 0x46]));
+
+        // [5, 1, 0] - a shift before an abbreviation doesn't carry into it.
+        assert_eq!("the", decode(&[0x94, 0x20]));
+
+        let memory = abbrev_memory();
+        // [1, 1, 5]
+        assert!(decode_with(&memory, &[0x84, 0x25]).is_err());
+        // [6, 6, 1] - ends before the abbreviation index.
+        assert!(decode_with(&memory, &[0x98, 0xc1]).is_err());
+    }
+
+    #[test]
+    fn test_errors_end_iteration() {
+        let memory = abbrev_memory();
+        let abbrevs = ZAbbrevTable::new(&memory).unwrap();
+        // [1, 1, 6]
+        let v3 = number_to_version(3).unwrap();
+        let mut zs = ZString::new(&memory, v3, &abbrevs, &[0x84, 0x26]).unwrap();
+        assert!(zs.next().unwrap().is_err());
+        assert!(zs.next().is_none());
+    }
+
+    #[test]
+    fn test_string_at() {
+        let memory = abbrev_memory();
+        let abbrevs = ZAbbrevTable::new(&memory).unwrap();
+        let v3 = number_to_version(3).unwrap();
+        let zs = ZString::at(&memory, v3, &abbrevs, ZOffset::from(0x60usize)).unwrap();
+        assert_eq!("the", String::try_from(zs).unwrap());
+        assert!(ZString::at(&memory, v3, &abbrevs, ZOffset::from(0x100usize)).is_err());
+    }
+
+    #[test]
+    fn test_ten_bit_escape() {
+        // [5, 6, 4], [27, 5, 5] - ZSCII 155
+        assert_eq!("ä", decode(&[0x14, 0xc4, 0xec, 0xa5]));
+        // [5, 6, 2], [0, 5, 5] - ZSCII 64
+        assert_eq!("@", decode(&[0x14, 0xc2, 0x80, 0xa5]));
+        // [5, 7, 5] - newline
+        assert_eq!("\n", decode(&[0x94, 0xe5]));
+
+        let memory = abbrev_memory();
+        // [5, 6, 2] - ends before the bottom five bits.
+        assert!(decode_with(&memory, &[0x94, 0xc2]).is_err());
+        // [1, 3, 6] - abbreviation 3 ends in the middle of an escape.
+        assert!(decode_with(&memory, &[0x84, 0x66]).is_err());
+    }
+
+    #[test]
+    fn test_zscii_chars() {
+        assert_eq!(Some('a'), ZSCII::from(97).to_char());
+        assert_eq!(Some('\n'), ZSCII::from(13).to_char());
+        assert_eq!(Some('ä'), ZSCII::from(155).to_char());
+        assert_eq!(Some('¿'), ZSCII::from(223).to_char());
+        assert_eq!(None, ZSCII::from(224).to_char());
+        assert_eq!(None, ZSCII::from(0).to_char());
+        assert_eq!(None, ZSCII::from(129).to_char());
+    }
+
+    #[test]
+    fn test_zscii_validity() {
+        let both = [13, 32, 126, 155, 251];
+        let input_only = [8, 27, 129, 154, 252, 254];
+        let output_only = [0, 9, 11];
+        let neither = [1, 10, 12, 127, 128, 255, 256, 1023];
+
+        for code in both.iter() {
+            assert!(ZSCII::from(*code).is_valid_input(), "{}", code);
+            assert!(ZSCII::from(*code).is_valid_output(), "{}", code);
+        }
+        for code in input_only.iter() {
+            assert!(ZSCII::from(*code).is_valid_input(), "{}", code);
+            assert!(!ZSCII::from(*code).is_valid_output(), "{}", code);
+        }
+        for code in output_only.iter() {
+            assert!(!ZSCII::from(*code).is_valid_input(), "{}", code);
+            assert!(ZSCII::from(*code).is_valid_output(), "{}", code);
+        }
+        for code in neither.iter() {
+            assert!(!ZSCII::from(*code).is_valid_input(), "{}", code);
+            assert!(!ZSCII::from(*code).is_valid_output(), "{}", code);
+        }
+    }
+
+    #[test]
+    fn test_v1_alphabets() {
+        let memory = abbrev_memory();
+        // [3, 7, 1] - shift to A2, where 7 is '0' rather than newline. Then a newline.
+        assert_eq!(
+            "0\n",
+            decode_in(&memory, number_to_version(1).unwrap(), &[0x8c, 0xe1]).unwrap()
+        );
+        // [4, 6, 7], [5, 6, 6] - shift-lock to A1, then shift-lock back around to A0.
+        assert_eq!(
+            "ABaa",
+            decode_in(
+                &memory,
+                number_to_version(1).unwrap(),
+                &[0x10, 0xc7, 0x94, 0xc6]
+            )
+            .unwrap()
+        );
+        // [4, 3, 6], [6, 5, 5] - a single shift from A1 goes to A0 for one character.
+        assert_eq!(
+            "aA",
+            decode_in(
+                &memory,
+                number_to_version(1).unwrap(),
+                &[0x10, 0x66, 0x98, 0xa5]
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_v2_alphabets() {
+        let memory = abbrev_memory();
+        // [2, 6, 6] - a single shift.
+        assert_eq!(
+            "Aa",
+            decode_in(&memory, number_to_version(2).unwrap(), &[0x88, 0xc6]).unwrap()
+        );
+        // [3, 7, 5] - newline in A2.
+        assert_eq!(
+            "\n",
+            decode_in(&memory, number_to_version(2).unwrap(), &[0x8c, 0xe5]).unwrap()
+        );
+        // [1, 0, 6] - only z-char 1 is an abbreviation.
+        assert_eq!(
+            "thea",
+            decode_in(&memory, number_to_version(2).unwrap(), &[0x84, 0x06]).unwrap()
+        );
+        // [4, 1, 0], [6, 5, 5] - shift-lock doesn't apply inside the abbreviation,
+        // but resumes after it.
+        assert_eq!(
+            "theA",
+            decode_in(
+                &memory,
+                number_to_version(2).unwrap(),
+                &[0x10, 0x20, 0x98, 0xa5]
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_custom_alphabet() {
+        let mut memory = abbrev_memory();
+        let v5 = number_to_version(5).unwrap();
+        // [6, 7, 8], [4, 6, 5], [8, 5, 7]
+        let buf = [0x18, 0xe8, 0x10, 0xc5, 0xa0, 0xa7];
+        assert_eq!("abcA0\n", decode_in(&memory, v5, &buf).unwrap());
+
+        // A0 is reversed, A1 is digits, A2 is punctuation.
+        memory
+            .write_byte_unchecked(ZOffset::from(0x35usize), 0x90)
+            .unwrap();
+        let table: Vec<u8> = (b'a'..=b'z')
+            .rev()
+            .chain(b"0123456789012345678901234)".iter().copied())
+            .chain(b"!@#$%^&*()-=+[]{};:<>,./?~".iter().copied())
+            .collect();
+        for (idx, byte) in table.into_iter().enumerate() {
+            memory
+                .write_byte_unchecked(ZOffset::from(0x90 + idx), byte)
+                .unwrap();
+        }
+        assert_eq!("zyx0#\n", decode_in(&memory, v5, &buf).unwrap());
+
+        // The custom table is ignored before V5.
+        assert_eq!("abcA0\n", decode_with(&memory, &buf).unwrap());
+    }
+
+    /// The ZSCII for ASCII `text`, with '\n' as newline.
+    pub fn zscii(text: &str) -> Vec<ZSCII> {
+        text.bytes()
+            .map(|b| ZSCII::from(if b == b'\n' { 13 } else { u16::from(b) }))
+            .collect()
+    }
+
+    #[test]
+    fn test_encode_text() {
+        let memory = abbrev_memory();
+        let v3 = number_to_version(3).unwrap();
+        let v5 = number_to_version(5).unwrap();
+
+        // [6, 7, 8], [5, 5, 5]
+        assert_eq!(
+            vec![0x18, 0xe8, 0x94, 0xa5],
+            encode_text(&memory, v3, &zscii("abc")).unwrap()
+        );
+        // [6, 7, 8], [5, 5, 5], [5, 5, 5]
+        assert_eq!(
+            vec![0x18, 0xe8, 0x14, 0xa5, 0x94, 0xa5],
+            encode_text(&memory, v5, &zscii("abc")).unwrap()
+        );
+        // Truncated: [13, 0, 4], [6, 5, 8]
+        assert_eq!(
+            vec![0x34, 0x04, 0x98, 0xa8],
+            encode_text(&memory, v3, &zscii("h A0xyz")).unwrap()
+        );
+        // V1-2 use z-chars 2 and 3 for single shifts: [2, 6, 3], [7, 1, 5]
+        assert_eq!(
+            vec![0x08, 0xc3, 0x9c, 0x25],
+            encode_text(&memory, number_to_version(1).unwrap(), &zscii("A0\n")).unwrap()
+        );
+        // The 10-bit escape: [5, 6, 4], [27, 5, 5]
+        assert_eq!(
+            vec![0x14, 0xc4, 0xec, 0xa5],
+            encode_text(&memory, v3, &[ZSCII::from(155)]).unwrap()
+        );
+        // Empty: [5, 5, 5], [5, 5, 5]
+        assert_eq!(
+            vec![0x14, 0xa5, 0x94, 0xa5],
+            encode_text(&memory, v3, &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_encode_custom_alphabet() {
+        let mut memory = abbrev_memory();
+        memory
+            .write_byte_unchecked(ZOffset::from(0x35usize), 0x90)
+            .unwrap();
+        let table: Vec<u8> = (b'a'..=b'z')
+            .rev()
+            .chain(b"ABCDEFGHIJKLMNOPQRSTUVWXYZ".iter().copied())
+            .chain(b" ^0123456789.,!?_#'\"/\\-:()".iter().copied())
+            .collect();
+        for (idx, byte) in table.into_iter().enumerate() {
+            memory
+                .write_byte_unchecked(ZOffset::from(0x90 + idx), byte)
+                .unwrap();
+        }
+
+        // [31, 30, 29], [5, 5, 5], [5, 5, 5]
+        let v5 = number_to_version(5).unwrap();
+        assert_eq!(
+            vec![0x7f, 0xdd, 0x14, 0xa5, 0x94, 0xa5],
+            encode_text(&memory, v5, &zscii("abc")).unwrap()
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_zchars_any_buffer(buf in prop::collection::vec(any::<u8>(), 0..16)) {
+            // Three z-chars per word, plus one for an odd byte at the end.
+            let max = buf.len() / 2 * 3 + buf.len() % 2;
+            prop_assert!(ZCharIter::new(&buf).count() <= max);
+        }
+
+        #[test]
+        fn prop_encode_round_trip(
+            version in prop::sample::select(vec![1u8, 2, 3, 5]),
+            codes in prop::collection::vec(0u16..1024, 0..8),
+        ) {
+            let memory = abbrev_memory();
+            let version = number_to_version(version).unwrap();
+            let len = version.dictionary_zchars;
+            let text = codes.into_iter().map(ZSCII::from).collect::<Vec<_>>();
+
+            let zchars = encode_zchars(&memory, version, &text).unwrap();
+            let encoded = encode_text(&memory, version, &text).unwrap();
+            prop_assert_eq!(len / 3 * 2, encoded.len());
+
+            // Only the last word has the stop bit.
+            for (idx, word) in encoded.chunks(2).enumerate() {
+                prop_assert_eq!(idx == len / 3 - 1, word[0] & 0x80 != 0);
+            }
+
+            // The z-chars are the encoding, truncated or padded.
+            let mut expected = zchars.clone();
+            expected.resize(len, 5);
+            prop_assert_eq!(expected, ZCharIter::new(&encoded).collect::<Vec<_>>());
+
+            // Text which fits decodes to itself.
+            if zchars.len() <= len {
+                let abbrevs = ZAbbrevTable::new(&memory).unwrap();
+                let decoded = ZString::new(&memory, version, &abbrevs, &encoded)
+                    .unwrap()
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap();
+                prop_assert_eq!(text, decoded);
+            }
+        }
     }
 }
@@ -1,7 +1,14 @@
 use crate::ensure;
 use crate::rszzy::addressing::{WordAddress, ZOffset};
+use crate::rszzy::constants::flags2::GAME_WRITABLE;
+use crate::rszzy::constants::header_offset::{FLAGS2, HEADER_SIZE};
+use crate::rszzy::keys::Key;
+use crate::rszzy::memory::Strictness;
+use crate::rszzy::text::{encode_text, ZString, ZSCII};
+use crate::rszzy::versions::Version;
 use anyhow::{anyhow, Error};
-use fehler::throws;
+use fehler::{throw, throws};
+use std::time::Duration;
 
 /// Abstract model of ZMachine memory as defined in ZSpec 1.
 /// Implementors of the trait provide access to the backing store,
@@ -21,6 +28,27 @@ pub trait Memory {
     #[throws]
     fn slice_at(&self, offset: ZOffset) -> &[u8];
 
+    /// How to treat a game's attempt to change read-only parts of the header.
+    fn strictness(&self) -> Strictness {
+        Strictness::default()
+    }
+
+    /// Records that a lenient write to the read-only header byte at `offset` was
+    /// ignored, and returns whether it is the first for that byte, so that a game
+    /// which keeps doing it is only warned about once.
+    fn first_header_warning(&mut self, _offset: ZOffset) -> bool {
+        true
+    }
+
+    /// Keeps a warning for the processor to show through the terminal, since memory
+    /// has no output of its own.
+    fn warn(&mut self, _warning: String) {}
+
+    /// The warnings kept since the last call.
+    fn take_warnings(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
     #[throws]
     fn read_byte(&self, offset: ZOffset) -> u8 {
         // ZSpec 1.1.1, 1.1.2, 1.1.3
@@ -36,11 +64,41 @@ pub trait Memory {
     fn write_byte(&mut self, offset: ZOffset, val: u8) {
         // ZSpec 1.1.1, 1.1.2, 1.1.3
         // - only dynamic memory may be written.
-        // TODO ZSpec 1.1.1.1
         ensure!(
             self.in_dynamic_range(offset),
             anyhow!("Writing to illegal index: {}", offset)
         );
+
+        // ZSpec 1.1.1.1 - the game may only change a few bits of the header.
+        let val = if usize::from(offset) < HEADER_SIZE {
+            let writable = if usize::from(offset) == FLAGS2 + 1 {
+                (GAME_WRITABLE & 0xff) as u8
+            } else {
+                0
+            };
+            let old = self.read_byte_unchecked(offset)?;
+            let forbidden = (old ^ val) & !writable;
+            if forbidden != 0 {
+                match self.strictness() {
+                    Strictness::Strict => throw!(anyhow!(
+                        "Writing to read-only header bits 0x{:02x} at {}",
+                        forbidden,
+                        offset
+                    )),
+                    Strictness::Lenient => {
+                        if self.first_header_warning(offset) {
+                            self.warn(format!(
+                                "Ignoring write to read-only header bits 0x{:02x} at {}",
+                                forbidden, offset
+                            ));
+                        }
+                    }
+                }
+            }
+            (old & !writable) | (val & writable)
+        } else {
+            val
+        };
         self.write_byte_unchecked(offset, val)?;
     }
 
@@ -67,6 +125,39 @@ pub trait Memory {
         self.write_byte(offset, high_byte)?;
         self.write_byte(offset + 1, low_byte)?;
     }
+
+    // For the interpreter's own writes, such as filling in the header.
+    #[throws]
+    fn write_word_unchecked<T>(&mut self, at: T, val: u16)
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let offset = at.into();
+        self.write_byte_unchecked(offset, ((val >> 8) & 0xff) as u8)?;
+        self.write_byte_unchecked(offset + 1, (val & 0xff) as u8)?;
+    }
+
+    // Unlike read_byte, this may read from high memory. It is meant for the
+    // interpreter itself (routine headers, code, strings), not for the game.
+    #[throws]
+    fn read_code_byte(&self, offset: ZOffset) -> u8 {
+        ensure!(
+            usize::from(offset) < self.memory_size(),
+            anyhow!("Reading past end of memory: {}", offset)
+        );
+        self.read_byte_unchecked(offset)?
+    }
+
+    #[throws]
+    fn read_code_word<T>(&self, at: T) -> u16
+    where
+        T: Into<ZOffset> + Copy,
+    {
+        let offset = at.into();
+        let high_byte = u16::from(self.read_code_byte(offset)?);
+        let low_byte = u16::from(self.read_code_byte(offset + 1)?);
+        (high_byte << 8) + low_byte
+    }
 }
 
 pub trait AbbrevTable {
@@ -74,44 +165,350 @@ pub trait AbbrevTable {
     fn abbrev_location(&self, memory: &impl Memory, table: u8, idx: u8) -> WordAddress;
 }
 
+/// ZSpec 13 - the dictionary of words the game understands.
+pub trait Dictionary {
+    /// ZSpec 13.1 - the characters which separate words, and are words themselves.
+    fn separators(&self) -> &[ZSCII];
+
+    /// The address of the entry whose text is `encoded`, or None if there is none.
+    #[throws]
+    fn lookup(&self, memory: &impl Memory, encoded: &[u8]) -> Option<ZOffset>;
+
+    /// ZSpec 13.6 - encode `word`, truncating it as needed, and look it up.
+    #[throws]
+    fn find_word(
+        &self,
+        memory: &impl Memory,
+        version: &Version,
+        word: &[ZSCII],
+    ) -> Option<ZOffset> {
+        self.lookup(memory, &encode_text(memory, version, word)?)?
+    }
+}
+
+/// ZSpec 12 - the object tree.
+/// Objects are numbered from 1. Object 0 means "nothing" and is never a valid argument.
+pub trait ObjectTable {
+    #[throws]
+    fn parent(&self, memory: &impl Memory, obj: u16) -> u16;
+    #[throws]
+    fn sibling(&self, memory: &impl Memory, obj: u16) -> u16;
+    #[throws]
+    fn child(&self, memory: &impl Memory, obj: u16) -> u16;
+
+    fn set_parent(&self, memory: &mut impl Memory, obj: u16, val: u16) -> Result<(), Error>;
+    fn set_sibling(&self, memory: &mut impl Memory, obj: u16, val: u16) -> Result<(), Error>;
+    fn set_child(&self, memory: &mut impl Memory, obj: u16, val: u16) -> Result<(), Error>;
+
+    #[throws]
+    fn attribute(&self, memory: &impl Memory, obj: u16, attr: u16) -> bool;
+    fn set_attribute(
+        &self,
+        memory: &mut impl Memory,
+        obj: u16,
+        attr: u16,
+        val: bool,
+    ) -> Result<(), Error>;
+
+    /// ZSpec 12.2 - the default value for properties missing from an object.
+    #[throws]
+    fn default_property(&self, memory: &impl Memory, prop: u16) -> u16;
+
+    /// ZSpec 12.4 - the location of the object's property table.
+    #[throws]
+    fn property_table(&self, memory: &impl Memory, obj: u16) -> ZOffset;
+
+    /// ZSpec 12.4 - the object's short name, from the start of its property table.
+    #[throws]
+    fn short_name<'a, M, A>(&self, memory: &'a M, abbrevs: &'a A, obj: u16) -> ZString<'a, M, A>
+    where
+        M: Memory,
+        A: AbbrevTable;
+
+    /// ZSpec 12.4 - the address and length of the data for property `prop` of `obj`,
+    /// or None if the object doesn't have the property.
+    #[throws]
+    fn property_data(&self, memory: &impl Memory, obj: u16, prop: u16) -> Option<(ZOffset, u16)>;
+
+    /// ZSpec 15 (get_prop_len) - the length of the property data at `addr`.
+    /// By convention, the length at address 0 is 0.
+    #[throws]
+    fn property_length(&self, memory: &impl Memory, addr: ZOffset) -> u16;
+
+    /// ZSpec 15 (get_next_prop) - the number of the property after `prop` in the list of `obj`,
+    /// or the first property if `prop` is 0. Returns 0 at the end of the list.
+    #[throws]
+    fn next_property(&self, memory: &impl Memory, obj: u16, prop: u16) -> u16;
+
+    /// ZSpec 15 (get_prop) - the value of a 1 or 2 byte property, or the default if
+    /// the object doesn't have it.
+    #[throws]
+    fn property(&self, memory: &impl Memory, obj: u16, prop: u16) -> u16 {
+        match self.property_data(memory, obj, prop)? {
+            None => self.default_property(memory, prop)?,
+            Some((addr, 1)) => u16::from(memory.read_byte(addr)?),
+            Some((addr, 2)) => memory.read_word(addr)?,
+            Some((_, len)) => throw!(anyhow!(
+                "Property {} of object {} has length {}, so get_prop cannot read it",
+                prop,
+                obj,
+                len
+            )),
+        }
+    }
+
+    /// ZSpec 15 (put_prop) - set a 1 or 2 byte property, which must exist.
+    /// A 1 byte property gets the low byte of `val`.
+    #[throws]
+    fn set_property(&self, memory: &mut impl Memory, obj: u16, prop: u16, val: u16) {
+        match self.property_data(memory, obj, prop)? {
+            None => throw!(anyhow!("Object {} does not have property {}", obj, prop)),
+            Some((addr, 1)) => memory.write_byte(addr, (val & 0xff) as u8)?,
+            Some((addr, 2)) => memory.write_word(addr, val)?,
+            Some((_, len)) => throw!(anyhow!(
+                "Property {} of object {} has length {}, so put_prop cannot write it",
+                prop,
+                obj,
+                len
+            )),
+        }
+    }
+
+    /// ZSpec 15 (remove_obj) - detach `obj` from its parent. Its children go with it.
+    #[throws]
+    fn remove(&self, memory: &mut impl Memory, obj: u16) {
+        let parent = self.parent(memory, obj)?;
+        if parent == 0 {
+            return;
+        }
+
+        let next = self.sibling(memory, obj)?;
+        let first = self.child(memory, parent)?;
+        if first == obj {
+            self.set_child(memory, parent, next)?;
+        } else {
+            // Find the sibling just before obj. A well-formed tree can't have more
+            // siblings than objects, so bail out on a corrupt (cyclic) tree.
+            let mut prev = first;
+            let mut count = 0u32;
+            loop {
+                ensure!(
+                    prev != 0 && count <= u32::from(u16::MAX),
+                    anyhow!(
+                        "Object tree is corrupt: object {} is not among the children of its parent, {}",
+                        obj,
+                        parent
+                    )
+                );
+                let sibling = self.sibling(memory, prev)?;
+                if sibling == obj {
+                    break;
+                }
+                prev = sibling;
+                count += 1;
+            }
+            self.set_sibling(memory, prev, next)?;
+        }
+
+        self.set_parent(memory, obj, 0)?;
+        self.set_sibling(memory, obj, 0)?;
+    }
+
+    /// ZSpec 15 (insert_obj) - make `obj` the first child of `dest`.
+    #[throws]
+    fn insert(&self, memory: &mut impl Memory, obj: u16, dest: u16) {
+        // Check dest before changing anything.
+        let first = self.child(memory, dest)?;
+        self.remove(memory, obj)?;
+
+        // remove may have changed dest's first child.
+        let first = if first == obj {
+            self.child(memory, dest)?
+        } else {
+            first
+        };
+        self.set_sibling(memory, obj, first)?;
+        self.set_parent(memory, obj, dest)?;
+        self.set_child(memory, dest, obj)?;
+    }
+}
+
+/// ZSpec 7 - the screen and keyboard, as seen by the processor.
+pub trait Terminal {
+    fn print(&mut self, text: &str) -> Result<(), Error>;
+
+    /// Tell the player about a rule the game broke that the interpreter let pass.
+    /// Kept apart from the game's own output.
+    fn warn(&mut self, warning: &str) -> Result<(), Error>;
+
+    /// ZSpec 15 (read) - read a line of input into `line`, which holds anything
+    /// already typed, up to `max_len` characters. Input ends with newline, or with
+    /// one of the function keys in `terminators`, and that key's ZSCII code is
+    /// returned. If `timeout` passes first, returns None, leaving what has been
+    /// typed so far in `line`.
+    #[throws]
+    fn read_line(
+        &mut self,
+        line: &mut String,
+        max_len: usize,
+        terminators: &[ZSCII],
+        timeout: Option<Duration>,
+    ) -> Option<ZSCII>;
+
+    /// ZSpec 15 (read_char) - wait for a single key, without showing it. Returns None
+    /// if `timeout` passes first.
+    #[throws]
+    fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key>;
+}
+
+/// ZSpec 6.2 - the 240 global variables, stored as words in dynamic memory.
+/// `idx` is the global number, 0-239 (i.e., variable number - 0x10).
+pub trait GlobalTable {
+    #[throws]
+    fn read_global(&self, memory: &impl Memory, idx: u8) -> u16;
+    fn write_global(&self, memory: &mut impl Memory, idx: u8, val: u16) -> Result<(), Error>;
+}
+
 #[cfg(test)]
-mod test {
+pub mod test {
     use super::*;
+    use crate::rszzy::keys::zscii_key::NEWLINE;
+    use std::collections::VecDeque;
+    use std::ops::Range;
+
+    /// Simple Memory for tests. Dynamic and static memory are at the start,
+    /// and everything after static memory is high memory.
+    pub struct TestMemory {
+        bytes: Vec<u8>,
+        dynamic_range: Range<usize>,
+        static_range: Range<usize>,
+        warnings: Vec<String>,
+    }
+
+    impl TestMemory {
+        pub fn new(bytes: Vec<u8>, static_start: usize, high_start: usize) -> TestMemory {
+            TestMemory {
+                bytes,
+                dynamic_range: 0..static_start,
+                static_range: static_start..high_start,
+                warnings: Vec::new(),
+            }
+        }
+    }
 
-    struct TestMemory(Vec<u8>);
+    /// What the player does when a TestTerminal is asked for a line.
+    pub enum TestInput {
+        /// Types the text, then presses the key with the given ZSCII code.
+        Line(&'static str, u16),
+        /// Types the text, then waits until the timer runs out.
+        Timeout(&'static str),
+    }
+
+    /// Terminal for tests. Collects everything that is printed or warned, and plays back
+    /// `input` when asked for a line, and `keys` when asked for a key. A key of
+    /// None waits until the timer runs out.
+    #[derive(Default)]
+    pub struct TestTerminal {
+        pub output: String,
+        pub warnings: Vec<String>,
+        pub input: VecDeque<TestInput>,
+        pub keys: VecDeque<Option<Key>>,
+    }
+
+    impl Terminal for TestTerminal {
+        #[throws]
+        fn print(&mut self, text: &str) {
+            self.output.push_str(text);
+        }
+
+        #[throws]
+        fn warn(&mut self, warning: &str) {
+            self.warnings.push(warning.to_string());
+        }
+
+        #[throws]
+        fn read_line(
+            &mut self,
+            line: &mut String,
+            max_len: usize,
+            terminators: &[ZSCII],
+            timeout: Option<Duration>,
+        ) -> Option<ZSCII> {
+            let (text, key) = match self.input.pop_front() {
+                Some(TestInput::Line(text, key)) => (text, Some(ZSCII::from(key))),
+                Some(TestInput::Timeout(text)) => {
+                    ensure!(timeout.is_some(), anyhow!("Timed out without a timer"));
+                    (text, None)
+                }
+                None => throw!(anyhow!("No more test input")),
+            };
+            line.extend(
+                text.chars()
+                    .take(max_len.saturating_sub(line.chars().count())),
+            );
+            if let Some(key) = key {
+                ensure!(
+                    key.code() == NEWLINE || terminators.contains(&key),
+                    anyhow!("{} doesn't end input", key.code())
+                );
+            }
+            key
+        }
+
+        #[throws]
+        fn read_key(&mut self, timeout: Option<Duration>) -> Option<Key> {
+            let key = self
+                .keys
+                .pop_front()
+                .ok_or_else(|| anyhow!("No more test keys"))?;
+            ensure!(
+                key.is_some() || timeout.is_some(),
+                anyhow!("Timed out without a timer")
+            );
+            key
+        }
+    }
 
     impl Default for TestMemory {
         fn default() -> TestMemory {
-            TestMemory((0..100).into_iter().map(|v| v * 2).collect::<Vec<_>>())
+            TestMemory::new((0..100).map(|v| v * 2).collect::<Vec<_>>(), 10, 20)
         }
     }
 
     impl Memory for TestMemory {
         fn memory_size(&self) -> usize {
-            100
+            self.bytes.len()
         }
 
         fn in_dynamic_range(&self, idx: ZOffset) -> bool {
-            (0..10).contains(&usize::from(idx))
+            self.dynamic_range.contains(&usize::from(idx))
         }
 
         fn in_static_range(&self, idx: ZOffset) -> bool {
-            (10..20).contains(&usize::from(idx))
+            self.static_range.contains(&usize::from(idx))
         }
 
         #[throws]
         fn read_byte_unchecked(&self, offset: ZOffset) -> u8 {
-            self.0[usize::from(offset)]
+            self.bytes[usize::from(offset)]
         }
 
         #[throws]
         fn write_byte_unchecked(&mut self, offset: ZOffset, val: u8) {
-            self.0[usize::from(offset)] = val;
+            self.bytes[usize::from(offset)] = val;
         }
 
         #[throws]
         fn slice_at(&self, offset: ZOffset) -> &[u8] {
-            &self.0[usize::from(offset)..]
+            &self.bytes[usize::from(offset)..]
+        }
+
+        fn warn(&mut self, warning: String) {
+            self.warnings.push(warning);
+        }
+
+        fn take_warnings(&mut self) -> Vec<String> {
+            std::mem::take(&mut self.warnings)
         }
     }
 
@@ -123,40 +520,69 @@ mod test {
     #[test]
     fn test_unchecked() {
         let mut m = TestMemory::default();
-        assert_eq!(10, m.read_byte_unchecked(5.into()).unwrap());
-        assert_eq!(30, m.read_byte_unchecked(15.into()).unwrap());
-        assert_eq!(50, m.read_byte_unchecked(25.into()).unwrap());
+        assert_eq!(10, m.read_byte_unchecked(5usize.into()).unwrap());
+        assert_eq!(30, m.read_byte_unchecked(15usize.into()).unwrap());
+        assert_eq!(50, m.read_byte_unchecked(25usize.into()).unwrap());
 
         assert!(true);
-        assert!(m.write_byte_unchecked(5.into(), 33).is_ok());
-        assert!(m.write_byte_unchecked(15.into(), 34).is_ok());
-        assert!(m.write_byte_unchecked(25.into(), 35).is_ok());
+        assert!(m.write_byte_unchecked(5usize.into(), 33).is_ok());
+        assert!(m.write_byte_unchecked(15usize.into(), 34).is_ok());
+        assert!(m.write_byte_unchecked(25usize.into(), 35).is_ok());
 
-        assert_eq!(33, m.read_byte_unchecked(5.into()).unwrap());
-        assert_eq!(34, m.read_byte_unchecked(15.into()).unwrap());
-        assert_eq!(35, m.read_byte_unchecked(25.into()).unwrap());
+        assert_eq!(33, m.read_byte_unchecked(5usize.into()).unwrap());
+        assert_eq!(34, m.read_byte_unchecked(15usize.into()).unwrap());
+        assert_eq!(35, m.read_byte_unchecked(25usize.into()).unwrap());
     }
 
     #[test]
     fn test_checked() {
         let mut m = TestMemory::default();
-        assert_eq!(10, m.read_byte(5.into()).unwrap());
-        assert_eq!(30, m.read_byte(15.into()).unwrap());
+        assert_eq!(10, m.read_byte(5usize.into()).unwrap());
+        assert_eq!(30, m.read_byte(15usize.into()).unwrap());
 
         // cannot read from high memory
-        assert!(m.read_byte(25.into()).is_err());
+        assert!(m.read_byte(25usize.into()).is_err());
 
-        assert!(true);
-        assert!(m.write_byte(5.into(), 33).is_ok());
+        // ...unless it is the interpreter reading code
+        assert_eq!(50, m.read_code_byte(25usize.into()).unwrap());
+        assert_eq!(0x3032, m.read_code_word(24usize).unwrap());
+        assert!(m.read_code_byte(100usize.into()).is_err());
+        assert!(m.read_code_word(99usize).is_err());
 
-        // cannot write to static or high memory
-        assert!(m.write_byte(15.into(), 34).is_err());
-        assert!(m.write_byte(25.into(), 35).is_err());
+        // The header is in dynamic memory, but the game may not change it.
+        assert!(m.write_byte(5usize.into(), 33).is_ok());
+        assert_eq!(10, m.read_byte(5usize.into()).unwrap());
 
-        assert_eq!(33, m.read_byte(5.into()).unwrap());
+        // cannot write to static or high memory
+        assert!(m.write_byte(15usize.into(), 34).is_err());
+        assert!(m.write_byte(25usize.into(), 35).is_err());
 
         // these values should be unchanged
-        assert_eq!(30, m.read_byte_unchecked(15.into()).unwrap());
-        assert_eq!(50, m.read_byte_unchecked(25.into()).unwrap());
+        assert_eq!(30, m.read_byte_unchecked(15usize.into()).unwrap());
+        assert_eq!(50, m.read_byte_unchecked(25usize.into()).unwrap());
+    }
+
+    #[test]
+    fn test_header_writes() {
+        let mut m = TestMemory::new(vec![0; 0x100], 0x80, 0x100);
+
+        // ZSpec 1.1.1.1 - the game may set and clear the low bits of Flags 2...
+        m.write_word(FLAGS2, 0x0007).unwrap();
+        assert_eq!(0x0007, m.read_word(FLAGS2).unwrap());
+        m.write_byte((FLAGS2 + 1).into(), 0x02).unwrap();
+        assert_eq!(0x0002, m.read_word(FLAGS2).unwrap());
+
+        // ...but nothing else, so the rest of the write is ignored.
+        m.write_word(FLAGS2, 0x01f9).unwrap();
+        assert_eq!(0x0001, m.read_word(FLAGS2).unwrap());
+        m.write_byte(0usize.into(), 5).unwrap();
+        assert_eq!(0, m.read_byte(0usize.into()).unwrap());
+
+        // Writing the same value back is harmless.
+        m.write_byte(0usize.into(), 0).unwrap();
+
+        // Past the header, dynamic memory is unrestricted.
+        m.write_byte(HEADER_SIZE.into(), 5).unwrap();
+        assert_eq!(5, m.read_byte(HEADER_SIZE.into()).unwrap());
     }
 }
@@ -0,0 +1,179 @@
+use crate::ensure;
+use crate::rszzy::addressing::ZOffset;
+use crate::rszzy::constants::header_extension::UNICODE_TABLE;
+use crate::rszzy::header::Header;
+use crate::rszzy::text::{DEFAULT_EXTRA_CHARS, ZSCII};
+use crate::rszzy::traits::Memory;
+use anyhow::{anyhow, Error, Result};
+use fehler::throws;
+use std::char::REPLACEMENT_CHARACTER;
+
+/// ZSpec 3.8.5.4 - a game's table may define at most 97 extra characters, ZSCII 155-251.
+const MAX_EXTRA_CHARS: usize = 97;
+
+/// ZSpec 3.8.5 - the mapping between the extra characters, ZSCII 155-251, and Unicode.
+/// V5+ games may supply their own table through the header extension. Otherwise,
+/// the default table is used.
+pub struct ZUnicodeTable {
+    // The characters for ZSCII 155 onwards.
+    extra: Vec<char>,
+}
+
+impl Default for ZUnicodeTable {
+    fn default() -> ZUnicodeTable {
+        ZUnicodeTable {
+            extra: DEFAULT_EXTRA_CHARS.to_vec(),
+        }
+    }
+}
+
+impl ZUnicodeTable {
+    #[throws]
+    pub fn new(memory: &impl Memory) -> ZUnicodeTable {
+        let table = match Header::new(memory).extension_word(UNICODE_TABLE)? {
+            None | Some(0) => return ZUnicodeTable::default(),
+            Some(addr) => ZOffset::from(addr),
+        };
+
+        // ZSpec 3.8.5.2.1 - a count byte, followed by that many words of Unicode.
+        let count = usize::from(memory.read_byte(table)?);
+        ensure!(
+            count <= MAX_EXTRA_CHARS,
+            anyhow!(
+                "Unicode translation table at {} has {} characters, but the maximum is {}",
+                table,
+                count,
+                MAX_EXTRA_CHARS
+            )
+        );
+        let mut extra = Vec::with_capacity(count);
+        for idx in 0..count {
+            let code = memory.read_word(table + 1 + 2 * idx)?;
+            extra.push(std::char::from_u32(u32::from(code)).unwrap_or(REPLACEMENT_CHARACTER));
+        }
+        ZUnicodeTable { extra }
+    }
+
+    /// ZSpec 3.8 - the character to print for `zscii`, if it has one.
+    pub fn to_char(&self, zscii: ZSCII) -> Option<char> {
+        match zscii.code() {
+            code @ 155..=251 => self.extra.get(usize::from(code - 155)).copied(),
+            _ => zscii.to_char(),
+        }
+    }
+
+    /// ZSpec 3.8 - the ZSCII code for a character typed by the player, if it has one.
+    pub fn to_zscii(&self, ch: char) -> Option<ZSCII> {
+        match ch {
+            '\n' | '\r' => Some(ZSCII::from(13)),
+            ' '..='~' => Some(ZSCII::from(ch as u16)),
+            _ => self
+                .extra
+                .iter()
+                .position(|&extra| extra == ch)
+                .map(|idx| ZSCII::from(155 + idx as u16)),
+        }
+    }
+
+    /// Convert decoded text to a String. ZSCII 0 prints nothing, and codes without
+    /// a character print as the Unicode replacement character.
+    #[throws]
+    pub fn decode(&self, zscii: impl Iterator<Item = Result<ZSCII>>) -> String {
+        let mut result = String::new();
+        for zscii in zscii {
+            let zscii = zscii?;
+            if zscii.code() != 0 {
+                result.push(self.to_char(zscii).unwrap_or(REPLACEMENT_CHARACTER));
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::rszzy::constants::header_offset::HEADER_EXTENSION_START;
+    use crate::rszzy::memory::test::fake_memory_with;
+
+    // Story with a header extension at 0x40, and a Unicode table at 0x48
+    // containing the given characters.
+    fn unicode_memory_in(version: u8, chars: &[u16]) -> impl Memory {
+        let chars = chars.to_vec();
+        fake_memory_with(version, 0x100, 0x80, move |bytes| {
+            bytes[HEADER_EXTENSION_START + 1] = 0x40;
+            bytes[0x41] = 3;
+            bytes[0x47] = 0x48;
+            bytes[0x48] = chars.len() as u8;
+            for (idx, ch) in chars.iter().enumerate() {
+                bytes[0x49 + 2 * idx] = (ch >> 8) as u8;
+                bytes[0x4a + 2 * idx] = (ch & 0xff) as u8;
+            }
+        })
+    }
+
+    fn unicode_memory(chars: &[u16]) -> impl Memory {
+        unicode_memory_in(5, chars)
+    }
+
+    #[test]
+    fn test_default_table() {
+        let table = ZUnicodeTable::default();
+        assert_eq!(Some('ä'), table.to_char(ZSCII::from(155)));
+        assert_eq!(Some('¿'), table.to_char(ZSCII::from(223)));
+        assert_eq!(None, table.to_char(ZSCII::from(224)));
+        assert_eq!(Some('a'), table.to_char(ZSCII::from(97)));
+
+        assert_eq!(Some(ZSCII::from(155)), table.to_zscii('ä'));
+        assert_eq!(Some(ZSCII::from(97)), table.to_zscii('a'));
+        assert_eq!(Some(ZSCII::from(13)), table.to_zscii('\n'));
+        assert_eq!(None, table.to_zscii('€'));
+        assert_eq!(None, table.to_zscii('\u{7}'));
+    }
+
+    #[test]
+    fn test_custom_table() {
+        let memory = unicode_memory(&[0x0416, 0x20ac]);
+        let table = ZUnicodeTable::new(&memory).unwrap();
+        assert_eq!(Some('Ж'), table.to_char(ZSCII::from(155)));
+        assert_eq!(Some('€'), table.to_char(ZSCII::from(156)));
+        assert_eq!(None, table.to_char(ZSCII::from(157)));
+        assert_eq!(Some('a'), table.to_char(ZSCII::from(97)));
+
+        assert_eq!(Some(ZSCII::from(156)), table.to_zscii('€'));
+        assert_eq!(None, table.to_zscii('ä'));
+
+        let decoded = table
+            .decode(
+                [155, 0, 97, 156, 300]
+                    .iter()
+                    .map(|code| Ok(ZSCII::from(*code))),
+            )
+            .unwrap();
+        assert_eq!("Жa€\u{fffd}", decoded);
+    }
+
+    #[test]
+    fn test_table_location() {
+        // Before V5, the header extension is ignored.
+        let memory = unicode_memory_in(3, &[0x0416]);
+        let table = ZUnicodeTable::new(&memory).unwrap();
+        assert_eq!(Some('ä'), table.to_char(ZSCII::from(155)));
+
+        // No extension table.
+        let memory = fake_memory_with(5, 0x100, 0x80, |_| {});
+        let table = ZUnicodeTable::new(&memory).unwrap();
+        assert_eq!(Some('ä'), table.to_char(ZSCII::from(155)));
+    }
+
+    #[test]
+    fn test_too_many_chars() {
+        let memory = fake_memory_with(5, 0x100, 0x80, |bytes| {
+            bytes[HEADER_EXTENSION_START + 1] = 0x40;
+            bytes[0x41] = 3;
+            bytes[0x47] = 0x48;
+            bytes[0x48] = 98;
+        });
+        assert!(ZUnicodeTable::new(&memory).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use crate::ensure;
+use crate::rszzy::constants::header_offset::{
+    CHECKSUM, FILE_LENGTH, HEADER_SIZE, RELEASE_NUMBER, SERIAL_NUMBER, VERSION_NUMBER,
+};
+use crate::rszzy::versions::number_to_version;
+use anyhow::{anyhow, Error};
+use fehler::throws;
+use std::fmt::{Display, Formatter};
+
+/// ZSpec 11.1.6 - the checksum is the sum of the story's bytes after the header,
+/// modulo 0x10000. `bytes` should be those bytes, from 0x40 up to the file length.
+pub fn checksum(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(u16::from(*byte)))
+}
+
+/// What `rszzy verify` finds out about a story file, without loading it.
+#[derive(Debug)]
+pub struct StoryReport {
+    pub version: u8,
+    pub release: u16,
+    pub serial: String,
+
+    /// The number of bytes in the file.
+    pub actual_length: usize,
+    /// The length from the header, scaled for the version. None if the header
+    /// doesn't give one.
+    pub header_length: Option<usize>,
+
+    pub header_checksum: u16,
+    /// The checksum of the bytes up to the header length, or as many of them as
+    /// the file contains.
+    pub computed_checksum: u16,
+}
+
+impl StoryReport {
+    #[throws]
+    pub fn from_bytes(bytes: &[u8]) -> StoryReport {
+        ensure!(
+            bytes.len() >= HEADER_SIZE,
+            anyhow!(
+                "Story is {} bytes, too short to contain a header of {} bytes",
+                bytes.len(),
+                HEADER_SIZE
+            )
+        );
+        let word = |offset: usize| (u16::from(bytes[offset]) << 8) + u16::from(bytes[offset + 1]);
+
+        let version_number = bytes[VERSION_NUMBER];
+        let version = number_to_version(version_number)?;
+        let header_length = match word(FILE_LENGTH) {
+            0 => None,
+            len => Some(usize::from(len) * version.file_length_multiplier),
+        };
+
+        let end = std::cmp::min(header_length.unwrap_or(bytes.len()), bytes.len());
+        let computed_checksum = checksum(&bytes[HEADER_SIZE..std::cmp::max(HEADER_SIZE, end)]);
+
+        StoryReport {
+            version: version_number,
+            release: word(RELEASE_NUMBER),
+            serial: bytes[SERIAL_NUMBER..SERIAL_NUMBER + 6]
+                .iter()
+                .map(|byte| match byte {
+                    0x20..=0x7e => char::from(*byte),
+                    _ => '?',
+                })
+                .collect(),
+            actual_length: bytes.len(),
+            header_length,
+            header_checksum: word(CHECKSUM),
+            computed_checksum,
+        }
+    }
+
+    /// Descriptions of everything that is wrong with the story. Empty if it verifies.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = vec![];
+        match self.header_length {
+            Some(len) if len > self.actual_length => problems.push(format!(
+                "File is truncated: the header gives a length of {} bytes, but the file has {}",
+                len, self.actual_length
+            )),
+            Some(len) if len < HEADER_SIZE => problems.push(format!(
+                "The header gives a length of {} bytes, which is shorter than the header",
+                len
+            )),
+            _ => {}
+        }
+        if self.header_checksum != self.computed_checksum {
+            problems.push(format!(
+                "Checksum mismatch: the header gives 0x{:04x}, but the story sums to 0x{:04x}",
+                self.header_checksum, self.computed_checksum
+            ));
+        }
+        problems
+    }
+}
+
+impl Display for StoryReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        writeln!(
+            f,
+            "Version {}, release {}, serial {}",
+            self.version, self.release, self.serial
+        )?;
+        match self.header_length {
+            Some(len) => writeln!(f, "Header length:     {} bytes", len)?,
+            None => writeln!(f, "Header length:     not given")?,
+        }
+        writeln!(f, "File length:       {} bytes", self.actual_length)?;
+        if let Some(len) = self.header_length {
+            if len < self.actual_length {
+                // ZSpec 11.1.6 - padding after the story is allowed, and ignored.
+                writeln!(
+                    f,
+                    "                   ({} bytes of padding)",
+                    self.actual_length - len
+                )?;
+            }
+        }
+        writeln!(f, "Header checksum:   0x{:04x}", self.header_checksum)?;
+        writeln!(f, "Computed checksum: 0x{:04x}", self.computed_checksum)?;
+
+        let problems = self.problems();
+        if problems.is_empty() {
+            write!(f, "OK")
+        } else {
+            for problem in &problems {
+                writeln!(f, "{}", problem)?;
+            }
+            write!(f, "FAILED")
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A V3 story of `len` bytes, where every byte after the header is 1.
+    fn story(len: usize, header_words: u16, header_checksum: u16) -> Vec<u8> {
+        let mut bytes = vec![1; len];
+        for byte in &mut bytes[..HEADER_SIZE] {
+            *byte = 0;
+        }
+        bytes[VERSION_NUMBER] = 3;
+        bytes[SERIAL_NUMBER..SERIAL_NUMBER + 6].copy_from_slice(b"870915");
+        bytes[FILE_LENGTH] = (header_words >> 8) as u8;
+        bytes[FILE_LENGTH + 1] = (header_words & 0xff) as u8;
+        bytes[CHECKSUM] = (header_checksum >> 8) as u8;
+        bytes[CHECKSUM + 1] = (header_checksum & 0xff) as u8;
+        bytes
+    }
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(0, checksum(&[]));
+        assert_eq!(6, checksum(&[1, 2, 3]));
+        assert_eq!(0x00fe, checksum(&vec![0xff; 0x102]));
+    }
+
+    #[test]
+    fn test_good_story() {
+        let report = StoryReport::from_bytes(&story(0x100, 0x80, 0xc0)).unwrap();
+        assert_eq!(3, report.version);
+        assert_eq!("870915", report.serial);
+        assert_eq!(Some(0x100), report.header_length);
+        assert_eq!(0xc0, report.computed_checksum);
+        assert!(report.problems().is_empty());
+        assert!(format!("{}", report).ends_with("OK"));
+    }
+
+    #[test]
+    fn test_padded_story() {
+        // The padding is not part of the checksum.
+        let report = StoryReport::from_bytes(&story(0x180, 0x80, 0xc0)).unwrap();
+        assert!(report.problems().is_empty());
+        assert!(format!("{}", report).contains("128 bytes of padding"));
+    }
+
+    #[test]
+    fn test_truncated_story() {
+        let report = StoryReport::from_bytes(&story(0xc0, 0x80, 0xc0)).unwrap();
+        assert_eq!(0x80, report.computed_checksum);
+        let problems = report.problems();
+        assert_eq!(2, problems.len());
+        assert!(problems[0].contains("truncated"));
+        assert!(problems[1].contains("Checksum mismatch"));
+        assert!(format!("{}", report).ends_with("FAILED"));
+    }
+
+    #[test]
+    fn test_no_header_length() {
+        let report = StoryReport::from_bytes(&story(0x100, 0, 0xc0)).unwrap();
+        assert_eq!(None, report.header_length);
+        assert!(report.problems().is_empty());
+    }
+
+    #[test]
+    fn test_bad_files() {
+        assert!(StoryReport::from_bytes(&[3; 0x20]).is_err());
+        let mut bytes = story(0x100, 0x80, 0xc0);
+        bytes[VERSION_NUMBER] = 9;
+        assert!(StoryReport::from_bytes(&bytes).is_err());
+    }
+}
@@ -1,12 +1,86 @@
+use crate::rszzy::constants::flags2;
+use crate::rszzy::constants::header_offset::{ROUTINES_OFFSET, STRINGS_OFFSET};
+use crate::rszzy::objects::{ObjectLayout, LARGE_LAYOUT, SMALL_LAYOUT};
+use crate::rszzy::text::{DEFAULT_ALPHABETS, V1_ALPHABETS};
 use anyhow::{anyhow, Error};
 use fehler::throws;
 use std::fmt::{Display, Formatter};
 
+/// ZSpec 8.2 - who draws the status line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusLine {
+    /// V1-3 - the interpreter draws it from the first three globals, showing either
+    /// score and turns or the time, as chosen by the header flags.
+    Interpreter,
+    /// V4+ - the game draws its own status line in the upper window.
+    Game,
+}
+
+/// The facts about the ZMachine which vary with the story's version number.
 pub struct Version {
     pub version_number: u8,
+
+    /// ZSpec 1.1.4 - the largest legal story file.
     pub max_story_len: usize,
 
+    /// ZSpec 1.2.3 - the multiplier for packed addresses.
     pub packed_multiplier: u8,
+    /// ZSpec 1.2.3 - V6-7 add 8 times the routine and string offsets at these header
+    /// locations to packed routine and string addresses.
+    pub packed_offsets: Option<(usize, usize)>,
+
+    /// ZSpec 11.1.6 - the multiplier for the file length in the header.
+    pub file_length_multiplier: usize,
+
+    /// ZSpec 5.2.1 - whether routine headers give initial values for the locals.
+    /// (In V5+, locals start at zero and the header holds only their count.)
+    pub initial_locals: bool,
+
+    /// ZSpec 5.5.1 - whether the game starts by calling a main routine, whose packed
+    /// address is in the header (V6), rather than at the address of an instruction.
+    pub main_routine: bool,
+
+    /// ZSpec 4.3.1 - whether 0xbe starts an extended instruction.
+    pub extended_opcodes: bool,
+
+    /// ZSpec 11.1.7 - whether the header may point to a header extension table.
+    pub header_extension: bool,
+    /// ZSpec 11.1 - whether the interpreter fills in the Flags 1 capabilities, its
+    /// number and version, and the screen size in characters (V4+).
+    pub interpreter_header: bool,
+    /// ZSpec 11.1 - whether the interpreter also fills in the screen and font sizes
+    /// in units, and the default colours (V5+).
+    pub screen_units: bool,
+    /// ZSpec 11.1 - V6 gives the font height before the font width.
+    pub font_height_first: bool,
+    /// ZSpec 11.1.3 - the Flags 2 bits in which the game asks for features that the
+    /// interpreter may refuse.
+    pub flags2_requests: u16,
+
+    /// ZSpec 12.3 - the shape of the object table.
+    pub object_layout: &'static ObjectLayout,
+
+    /// ZSpec 3.5 - the default alphabets A0-A2, 26 characters each.
+    pub alphabets: &'static [u8],
+    /// ZSpec 3.5.5 - whether the game may supply its own alphabet table.
+    pub custom_alphabet: bool,
+    /// ZSpec 3.3 - how many of z-chars 1-3 are abbreviations.
+    pub abbrev_zchars: u8,
+    /// ZSpec 3.2.2 - whether z-chars 4 and 5 are shift-locks (V1-2) rather than
+    /// single shifts, with z-chars 2 and 3 as the single shifts.
+    pub shift_lock: bool,
+
+    /// ZSpec 13.3 - the number of z-chars in an encoded dictionary word.
+    pub dictionary_zchars: usize,
+    /// ZSpec 15 (read) - whether text buffers hold the length of the text in byte 1
+    /// (V5+), rather than ending the text with a zero.
+    pub text_buffer_length: bool,
+    /// ZSpec 10.5.2.1 - whether the header may point to a table of extra characters
+    /// which end input (V5+).
+    pub terminating_chars: bool,
+
+    /// ZSpec 8.2 - who draws the status line.
+    pub status_line: StatusLine,
 }
 
 impl Display for Version {
@@ -15,24 +89,170 @@ impl Display for Version {
     }
 }
 
-const V3: Version = Version {
-    version_number: 3,
+const V1: Version = Version {
+    version_number: 1,
     max_story_len: 128 * 1024,
     packed_multiplier: 2,
+    packed_offsets: None,
+    file_length_multiplier: 2,
+    initial_locals: true,
+    main_routine: false,
+    extended_opcodes: false,
+    header_extension: false,
+    interpreter_header: false,
+    screen_units: false,
+    font_height_first: false,
+    flags2_requests: 0,
+    object_layout: &SMALL_LAYOUT,
+    alphabets: V1_ALPHABETS,
+    custom_alphabet: false,
+    abbrev_zchars: 0,
+    shift_lock: true,
+    dictionary_zchars: 6,
+    text_buffer_length: false,
+    terminating_chars: false,
+    status_line: StatusLine::Interpreter,
 };
 
-const V5: Version = Version {
-    version_number: 5,
+const V2: Version = Version {
+    version_number: 2,
+    alphabets: DEFAULT_ALPHABETS,
+    abbrev_zchars: 1,
+    ..V1
+};
+
+const V3: Version = Version {
+    version_number: 3,
+    abbrev_zchars: 3,
+    shift_lock: false,
+    ..V2
+};
+
+const V4: Version = Version {
+    version_number: 4,
     max_story_len: 256 * 1024,
     packed_multiplier: 4,
+    file_length_multiplier: 4,
+    interpreter_header: true,
+    object_layout: &LARGE_LAYOUT,
+    dictionary_zchars: 9,
+    status_line: StatusLine::Game,
+    ..V3
+};
+
+const V5: Version = Version {
+    version_number: 5,
+    initial_locals: false,
+    extended_opcodes: true,
+    header_extension: true,
+    screen_units: true,
+    flags2_requests: flags2::PICTURES
+        | flags2::UNDO
+        | flags2::MOUSE
+        | flags2::COLOURS
+        | flags2::SOUND_EFFECTS,
+    custom_alphabet: true,
+    text_buffer_length: true,
+    terminating_chars: true,
+    ..V4
+};
+
+const V6: Version = Version {
+    version_number: 6,
+    max_story_len: 576 * 1024,
+    packed_offsets: Some((ROUTINES_OFFSET, STRINGS_OFFSET)),
+    file_length_multiplier: 8,
+    main_routine: true,
+    font_height_first: true,
+    flags2_requests: V5.flags2_requests | flags2::MENUS,
+    ..V5
+};
+
+const V7: Version = Version {
+    version_number: 7,
+    main_routine: false,
+    font_height_first: false,
+    flags2_requests: V5.flags2_requests,
+    ..V6
+};
+
+const V8: Version = Version {
+    version_number: 8,
+    max_story_len: 512 * 1024,
+    packed_multiplier: 8,
+    packed_offsets: None,
+    file_length_multiplier: 8,
+    ..V5
 };
 
 #[throws]
 pub fn number_to_version(number: u8) -> &'static Version {
-    let versions: Vec<&'static Version> = vec![&V3, &V5];
+    let versions: Vec<&'static Version> = vec![&V1, &V2, &V3, &V4, &V5, &V6, &V7, &V8];
 
     versions
         .into_iter()
         .find(|v| v.version_number == number)
         .ok_or_else(|| anyhow!("Unknown (or unimplemented) version number: {}", number))?
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_versions() {
+        for number in 1..=8 {
+            assert_eq!(number, number_to_version(number).unwrap().version_number);
+        }
+        assert!(number_to_version(0).is_err());
+        assert!(number_to_version(9).is_err());
+    }
+
+    #[test]
+    fn test_facts() {
+        let v1 = number_to_version(1).unwrap();
+        let v3 = number_to_version(3).unwrap();
+        let v4 = number_to_version(4).unwrap();
+        let v6 = number_to_version(6).unwrap();
+        let v8 = number_to_version(8).unwrap();
+
+        assert_eq!(0, v1.abbrev_zchars);
+        assert!(v1.shift_lock);
+        assert!(!v3.shift_lock);
+        assert_eq!(StatusLine::Interpreter, v3.status_line);
+        assert_eq!(StatusLine::Game, v4.status_line);
+
+        assert_eq!(6, v3.dictionary_zchars);
+        assert_eq!(9, v4.dictionary_zchars);
+        assert!(!v4.text_buffer_length);
+        assert!(v8.text_buffer_length);
+        assert_eq!(32, v3.object_layout.max_attributes);
+        assert_eq!(48, v4.object_layout.max_attributes);
+
+        assert_eq!(2, v3.packed_multiplier);
+        assert_eq!(4, v6.packed_multiplier);
+        assert_eq!(8, v8.packed_multiplier);
+        assert_eq!(Some((ROUTINES_OFFSET, STRINGS_OFFSET)), v6.packed_offsets);
+        assert_eq!(None, v8.packed_offsets);
+        assert_eq!(576 * 1024, v6.max_story_len);
+
+        assert_eq!(2, v3.file_length_multiplier);
+        assert_eq!(4, v4.file_length_multiplier);
+        assert_eq!(8, v6.file_length_multiplier);
+        assert_eq!(8, v8.file_length_multiplier);
+
+        assert!(!v3.interpreter_header);
+        assert!(v4.interpreter_header);
+        assert!(!v4.screen_units);
+        assert!(v8.screen_units);
+        assert!(v6.font_height_first);
+        assert!(v6.main_routine);
+        assert!(!number_to_version(7).unwrap().main_routine);
+        assert!(!v8.main_routine);
+        assert!(!number_to_version(7).unwrap().font_height_first);
+
+        assert_eq!(0, v4.flags2_requests);
+        assert_eq!(0, v8.flags2_requests & flags2::MENUS);
+        assert_ne!(0, v6.flags2_requests & flags2::MENUS);
+    }
+}